@@ -0,0 +1,452 @@
+//! Security audit log.
+//!
+//! Security-relevant events are appended to a reserved [log file][crate::log_file] on the
+//! internal filesystem.  The counter in the header is the counter of the first entry in the file,
+//! followed by up to [`MAX_ENTRIES`][] entries of [`ENTRY_LEN`][] bytes in the order they were
+//! recorded.  To limit the flash wear, new entries are appended to the file.  Only if the file is
+//! full, it is rewritten without the oldest entries, keeping at least half of them.
+
+use core::time::Duration;
+
+use admin_app::Config as _;
+use heapless::Vec;
+use heapless_bytes::Bytes;
+use littlefs2_core::{path, Path};
+use serde::Serialize;
+use trussed::{
+    api::{request, Request},
+    service::ServiceResources,
+    store::Store,
+    types::Location,
+    Platform,
+};
+use trussed_manage::ManageRequest;
+
+#[cfg(feature = "backend-auth")]
+use trussed::{
+    api::reply, backend::Backend, error::Error as TrussedError, serde_extensions::ExtensionImpl,
+    types::CoreContext,
+};
+#[cfg(feature = "backend-auth")]
+use trussed_auth::{AuthExtension, AuthReply, AuthRequest};
+
+use crate::{
+    config_transfer,
+    log_file::{self, LogFile, HEADER_LEN},
+    Config, CONFIG_FIELDS,
+};
+
+pub(crate) const LOG_PATH: &Path = path!("/audit-log");
+/// Path of the admin-app config in the admin client directory
+const CONFIG_PATH: &Path = path!("config");
+/// Path of the admin-app config on the internal filesystem
+const CONFIG_FILE: &Path = path!("/admin/dat/config");
+/// Maximum size of the admin-app config that is compared to detect the changed keys
+const MAX_CONFIG_LEN: usize = 1024;
+
+pub const MAX_ENTRIES: usize = 64;
+const ENTRY_LEN: usize = 40;
+/// Long enough for all config keys
+const SUBJECT_LEN: usize = 30;
+const FILE_LEN: usize = HEADER_LEN + MAX_ENTRIES * ENTRY_LEN;
+/// The stored uptime of the events recorded during boot
+const BOOT_UPTIME: u32 = u32::MAX;
+
+const LOG: LogFile<FILE_LEN> = LogFile::new(LOG_PATH, "audit log");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Event {
+    PinFailure = 1,
+    PinLocked = 2,
+    FactoryResetDevice = 3,
+    FactoryResetClient = 4,
+    ConfigChange = 5,
+    MigrationError = 6,
+    Se050Error = 7,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Entry {
+    #[serde(rename = "n")]
+    pub counter: u32,
+    /// Uptime in milliseconds, `None` for events recorded during boot before the platform clock
+    /// is available to the apps
+    #[serde(rename = "t", skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u32>,
+    #[serde(rename = "e")]
+    pub event: u8,
+    /// The client that caused or was affected by the event, or the changed key for
+    /// [`Event::ConfigChange`][], truncated to 30 bytes
+    #[serde(rename = "s")]
+    pub subject: Bytes<SUBJECT_LEN>,
+}
+
+impl Entry {
+    fn serialize(&self) -> [u8; ENTRY_LEN] {
+        let mut data = [0; ENTRY_LEN];
+        data[..4].copy_from_slice(&self.counter.to_be_bytes());
+        data[4..8].copy_from_slice(&self.uptime.unwrap_or(BOOT_UPTIME).to_be_bytes());
+        data[8] = self.event;
+        data[9] = self.subject.len() as u8;
+        data[10..][..self.subject.len()].copy_from_slice(&self.subject);
+        data
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        let counter = u32::from_be_bytes(data[..4].try_into().ok()?);
+        let uptime = u32::from_be_bytes(data[4..8].try_into().ok()?);
+        let subject_len = usize::from(data[9]).min(SUBJECT_LEN);
+        Some(Self {
+            counter,
+            uptime: (uptime != BOOT_UPTIME).then_some(uptime),
+            event: data[8],
+            subject: Bytes::from_slice(&data[10..][..subject_len]).ok()?,
+        })
+    }
+}
+
+/// An event that is appended to the audit log by [`record_all`][]
+#[derive(Clone, Copy, Debug)]
+pub struct Record<'a> {
+    /// The uptime, or `None` for events recorded during boot
+    pub uptime: Option<Duration>,
+    pub event: Event,
+    /// The client or the changed config key, see [`Entry::subject`][]
+    pub subject: &'a str,
+}
+
+/// The content of the audit log, returned by the admin-app audit log command
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AuditLog {
+    /// The entries, sorted by their counter
+    #[serde(rename = "e")]
+    pub entries: Vec<Entry, MAX_ENTRIES>,
+}
+
+impl AuditLog {
+    pub fn read<S: Store>(store: &S) -> Self {
+        let mut log = Self::default();
+        let Some(data) = LOG.read(store.ifs()) else {
+            return log;
+        };
+        log.entries = entries(&data)
+            .chunks_exact(ENTRY_LEN)
+            .filter_map(Entry::deserialize)
+            .collect();
+        log
+    }
+
+    /// Removes all entries but keeps the counter
+    pub fn clear<S: Store>(store: &S) -> bool {
+        let fs = store.ifs();
+        let Some(data) = LOG.read(fs) else {
+            return true;
+        };
+        LOG.write(fs, &next_counter(&data).to_be_bytes())
+    }
+}
+
+/// Returns the complete entries stored in the file content
+fn entries(data: &[u8]) -> &[u8] {
+    let entries = data.get(HEADER_LEN..).unwrap_or_default();
+    &entries[..entries.len() - entries.len() % ENTRY_LEN]
+}
+
+/// Returns the counter of the next entry
+fn next_counter(data: &[u8]) -> u32 {
+    let count = entries(data).len() / ENTRY_LEN;
+    log_file::counter(data).wrapping_add(count as u32)
+}
+
+/// Appends an event to the audit log, see [`record_all`][]
+pub fn record<S: Store>(store: &S, uptime: Option<Duration>, event: Event, subject: &str) {
+    record_all(
+        store,
+        &[Record {
+            uptime,
+            event,
+            subject,
+        }],
+    );
+}
+
+/// Appends events to the audit log with a single write
+///
+/// Errors are only logged because a failure to write the audit log must not block the operation
+/// that caused the event.
+pub fn record_all<S: Store>(store: &S, records: &[Record<'_>]) {
+    let records = &records[..records.len().min(MAX_ENTRIES)];
+    if records.is_empty() {
+        return;
+    }
+    let fs = store.ifs();
+    let data = LOG.read(fs).unwrap_or_default();
+    let first = log_file::counter(&data);
+    let entries = entries(&data);
+    let count = entries.len() / ENTRY_LEN;
+
+    let mut new: Vec<u8, { MAX_ENTRIES * ENTRY_LEN }> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        info_now!("Audit event {:?} for {}", record.event, record.subject);
+        let subject = record.subject.as_bytes();
+        let entry = Entry {
+            counter: first.wrapping_add((count + i) as u32),
+            uptime: record.uptime.map(|uptime| {
+                u32::try_from(uptime.as_millis())
+                    .unwrap_or(u32::MAX)
+                    .min(BOOT_UPTIME - 1)
+            }),
+            event: record.event as u8,
+            subject: Bytes::from_slice(&subject[..subject.len().min(SUBJECT_LEN)]).unwrap(),
+        };
+        new.extend_from_slice(&entry.serialize()).ok();
+    }
+
+    if data.len() == HEADER_LEN + entries.len() && count + records.len() <= MAX_ENTRIES {
+        LOG.append(fs, &new);
+        return;
+    }
+
+    // The file is missing, damaged or full, so it is rewritten.  Only half of the entries are
+    // kept so that the next rewrite is only necessary after MAX_ENTRIES / 2 events.
+    let keep = count.min(MAX_ENTRIES / 2).min(MAX_ENTRIES - records.len());
+    let dropped = count - keep;
+    let mut rewritten: Vec<u8, FILE_LEN> = Vec::new();
+    rewritten
+        .extend_from_slice(&first.wrapping_add(dropped as u32).to_be_bytes())
+        .ok();
+    rewritten
+        .extend_from_slice(&entries[dropped * ENTRY_LEN..])
+        .ok();
+    rewritten.extend_from_slice(&new).ok();
+    LOG.write(fs, &rewritten);
+}
+
+/// Records the events caused by a core request, see [`Backend::Audit`][crate::Backend::Audit]
+pub fn check_request<P: Platform>(
+    resources: &mut ServiceResources<P>,
+    client: &Path,
+    request: &Request,
+) {
+    if let Request::WriteFile(request) = request {
+        if client == path!("admin")
+            && request.location == Location::Internal
+            && &*request.path == CONFIG_PATH
+        {
+            // The request is checked before it is executed, so the file contains the old config
+            let old = resources
+                .platform()
+                .store()
+                .ifs()
+                .read::<MAX_CONFIG_LEN>(CONFIG_FILE)
+                .unwrap_or_default();
+            let uptime = resources.platform_mut().user_interface().uptime();
+            let store = resources.platform().store();
+            match changed_config_fields(&old, &request.data) {
+                Some(keys) => {
+                    let records: Vec<_, { CONFIG_FIELDS.len() }> = keys
+                        .into_iter()
+                        .map(|subject| Record {
+                            uptime: Some(uptime),
+                            event: Event::ConfigChange,
+                            subject,
+                        })
+                        .collect();
+                    record_all(&store, &records);
+                }
+                None => record(&store, Some(uptime), Event::ConfigChange, client.as_str()),
+            }
+        }
+    }
+}
+
+/// Returns the keys of the listed config fields that differ between the serialized configs, or
+/// `None` if the new config cannot be parsed
+///
+/// Internal state like the filesystem version is not listed, so writing only internal state does
+/// not return any keys.
+fn changed_config_fields(
+    old: &[u8],
+    new: &[u8],
+) -> Option<Vec<&'static str, { CONFIG_FIELDS.len() }>> {
+    let mut old: Config = cbor_smol::cbor_deserialize(old).unwrap_or_default();
+    let mut new: Config = cbor_smol::cbor_deserialize(new).ok()?;
+    let mut keys = Vec::new();
+    for field in CONFIG_FIELDS {
+        if let (Some(old), Some(new)) = (old.field(field.name), new.field(field.name)) {
+            if !config_transfer::is_equal(&old, &new) {
+                keys.push(field.name).ok();
+            }
+        }
+    }
+    Some(keys)
+}
+
+/// Records the events caused by a manage extension request
+pub fn check_manage_request<P: Platform>(
+    resources: &mut ServiceResources<P>,
+    client: &Path,
+    request: &request::SerdeExtension,
+) {
+    match cbor_smol::cbor_deserialize::<ManageRequest>(&request.request) {
+        Ok(ManageRequest::FactoryResetDevice(_)) => {
+            record_with_resources(resources, Event::FactoryResetDevice, client)
+        }
+        Ok(ManageRequest::FactoryResetClient(request)) => {
+            record_with_resources(resources, Event::FactoryResetClient, &request.client)
+        }
+        _ => {}
+    }
+}
+
+/// Executes a trussed-auth request and records failed PIN checks
+#[cfg(feature = "backend-auth")]
+pub fn auth_request<B, P>(
+    backend: &mut B,
+    core_ctx: &mut CoreContext,
+    backend_ctx: &mut B::Context,
+    request: &request::SerdeExtension,
+    resources: &mut ServiceResources<P>,
+) -> Result<reply::SerdeExtension, TrussedError>
+where
+    B: Backend + ExtensionImpl<AuthExtension>,
+    P: Platform,
+{
+    let reply = backend.extension_request_serialized(core_ctx, backend_ctx, request, resources)?;
+    let Ok(AuthRequest::CheckPin(check_pin)) = cbor_smol::cbor_deserialize(&request.request) else {
+        return Ok(reply);
+    };
+    let Ok(AuthReply::CheckPin(check_pin_reply)) = cbor_smol::cbor_deserialize(&reply.reply) else {
+        return Ok(reply);
+    };
+    if !check_pin_reply.success {
+        let retries = backend.extension_request(
+            core_ctx,
+            backend_ctx,
+            &AuthRequest::PinRetries(trussed_auth::request::PinRetries { id: check_pin.id }),
+            resources,
+        );
+        let event = match retries {
+            Ok(AuthReply::PinRetries(reply)) if reply.retries == Some(0) => Event::PinLocked,
+            _ => Event::PinFailure,
+        };
+        record_with_resources(resources, event, &core_ctx.path);
+    }
+    Ok(reply)
+}
+
+fn record_with_resources<P: Platform>(
+    resources: &mut ServiceResources<P>,
+    event: Event,
+    client: &Path,
+) {
+    let uptime = resources.platform_mut().user_interface().uptime();
+    let store = resources.platform().store();
+    record(&store, Some(uptime), event, client.as_str());
+}
+
+#[cfg(all(test, not(feature = "se050")))]
+mod tests {
+    use trussed::{
+        backend::BackendId,
+        syscall,
+        virt::{self, Ram},
+        Platform as _,
+    };
+    use trussed_manage::ManageClient as _;
+
+    use super::*;
+    use crate::{Backend, Dispatch};
+
+    const BACKENDS: &[BackendId<Backend>] = &[
+        BackendId::Custom(Backend::Audit),
+        BackendId::Custom(Backend::StagingManage),
+        BackendId::Core,
+    ];
+
+    #[test]
+    fn factory_reset_device() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            fs.create_dir_all(path!("/fido/dat")).unwrap();
+            fs.write(path!("/fido/dat/rk"), b"credential").unwrap();
+            record(&store, None, Event::ConfigChange, "fido.disabled");
+
+            let dispatch = Dispatch::<(), ()>::new(
+                #[cfg(feature = "backend-auth")]
+                crate::AUTH_LOCATION,
+            );
+            platform.run_client_with_backends("admin", dispatch, BACKENDS, |mut client| {
+                syscall!(client.factory_reset_device());
+            });
+
+            assert!(!fs.exists(path!("/fido/dat/rk")));
+            let log = AuditLog::read(&store);
+            let events: Vec<_, MAX_ENTRIES> = log.entries.iter().map(|e| e.event).collect();
+            assert_eq!(
+                events,
+                [Event::ConfigChange as u8, Event::FactoryResetDevice as u8]
+            );
+            assert_eq!(log.entries[1].counter, 1);
+            assert_eq!(&*log.entries[0].subject, b"fido.disabled");
+            assert_eq!(log.entries[0].uptime, None);
+            assert_eq!(&*log.entries[1].subject, b"admin");
+            assert!(log.entries[1].uptime.is_some());
+        });
+    }
+
+    #[test]
+    fn append_and_rewrite() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let uptime = Some(Duration::from_millis(1234));
+            for _ in 0..MAX_ENTRIES {
+                record(&store, uptime, Event::PinFailure, "opcard");
+            }
+            let log = AuditLog::read(&store);
+            assert_eq!(log.entries.len(), MAX_ENTRIES);
+            assert_eq!(log.entries[0].counter, 0);
+            assert_eq!(log.entries[0].uptime, Some(1234));
+
+            // The full log is rewritten with the newest half of the entries
+            record(&store, uptime, Event::PinLocked, "opcard");
+            let log = AuditLog::read(&store);
+            assert_eq!(log.entries.len(), MAX_ENTRIES / 2 + 1);
+            assert_eq!(log.entries[0].counter, MAX_ENTRIES as u32 / 2);
+            let last = log.entries.last().unwrap();
+            assert_eq!(last.counter, MAX_ENTRIES as u32);
+            assert_eq!(last.event, Event::PinLocked as u8);
+
+            assert!(AuditLog::clear(&store));
+            assert!(AuditLog::read(&store).entries.is_empty());
+            record(&store, None, Event::FactoryResetDevice, "admin");
+            let log = AuditLog::read(&store);
+            assert_eq!(log.entries.len(), 1);
+            assert_eq!(log.entries[0].counter, MAX_ENTRIES as u32 + 1);
+        });
+    }
+
+    #[test]
+    fn config_change_keys() {
+        let mut old_buffer = [0; MAX_CONFIG_LEN];
+        let mut buffer = [0; MAX_CONFIG_LEN];
+        let mut config = Config::default();
+        let old = cbor_smol::cbor_serialize(&config, &mut old_buffer).unwrap();
+
+        config.fs_version = 3;
+        let new = cbor_smol::cbor_serialize(&config, &mut buffer).unwrap();
+        assert_eq!(changed_config_fields(&old, new), Some(Vec::new()));
+
+        config.fido.disabled = true;
+        config.opcard.nfc_disabled = true;
+        let new = cbor_smol::cbor_serialize(&config, &mut buffer).unwrap();
+        let keys = changed_config_fields(&old, new).unwrap();
+        assert_eq!(keys, ["fido.disabled", "opcard.nfc_disabled"]);
+        // A missing old config is treated like the default config
+        assert_eq!(changed_config_fields(&[], new).unwrap(), keys);
+
+        assert_eq!(changed_config_fields(&old, b"invalid"), None);
+    }
+}
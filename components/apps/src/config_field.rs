@@ -0,0 +1,122 @@
+//! Typed config values.
+//!
+//! admin-app reads and writes config fields through the [`ConfigValueMut`][] returned by
+//! [`admin_app::Config::field`][].  The [`FieldType`][] listed for the field determines the valid
+//! values: integers within the bounds, enum indices into the variant names and flags with one
+//! bit per name.  Enums and flags are stored as integers to keep the serialized config small, so
+//! a stored value can be out of range, e. g. if it was written by a newer firmware with more
+//! variants.
+
+use admin_app::{ConfigValueMut, FieldType};
+
+/// Returns true if the value has the type of the field and is valid for it
+pub fn is_valid(ty: FieldType, value: &ConfigValueMut<'_>) -> bool {
+    match (ty, value) {
+        (FieldType::Bool, ConfigValueMut::Bool(_)) => true,
+        (FieldType::U8 { min, max }, ConfigValueMut::U8(value)) => (min..=max).contains(&**value),
+        (FieldType::Enum(variants), ConfigValueMut::Enum(value)) => {
+            usize::from(**value) < variants.len()
+        }
+        (FieldType::Flags(names), ConfigValueMut::Flags(value)) => {
+            **value & !flags_mask(names) == 0
+        }
+        _ => false,
+    }
+}
+
+/// Replaces an invalid value with the closest valid value
+///
+/// Integers are clamped to the bounds, unknown enum variants are replaced with the first
+/// variant, and unknown flags are removed.  Returns true if the value was changed.
+pub fn sanitize(ty: FieldType, value: ConfigValueMut<'_>) -> bool {
+    let old = match &value {
+        ConfigValueMut::Bool(value) => u16::from(**value),
+        ConfigValueMut::U8(value) | ConfigValueMut::Enum(value) => u16::from(**value),
+        ConfigValueMut::Flags(value) => **value,
+    };
+    let new = match (ty, value) {
+        (FieldType::U8 { min, max }, ConfigValueMut::U8(value)) => {
+            *value = (*value).clamp(min, max.max(min));
+            u16::from(*value)
+        }
+        (FieldType::Enum(variants), ConfigValueMut::Enum(value)) => {
+            if usize::from(*value) >= variants.len() {
+                *value = 0;
+            }
+            u16::from(*value)
+        }
+        (FieldType::Flags(names), ConfigValueMut::Flags(value)) => {
+            *value &= flags_mask(names);
+            *value
+        }
+        _ => old,
+    };
+    old != new
+}
+
+fn flags_mask(names: &[&str]) -> u16 {
+    u32::try_from(names.len())
+        .ok()
+        .and_then(|n| 1u16.checked_shl(n))
+        .map(|bit| bit - 1)
+        .unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARIANTS: &[&str] = &["a", "b", "c"];
+
+    #[test]
+    fn valid() {
+        let u8_ty = FieldType::U8 { min: 1, max: 10 };
+        for (value, valid) in [(0, false), (1, true), (10, true), (11, false)] {
+            let mut value = value;
+            assert_eq!(is_valid(u8_ty, &ConfigValueMut::U8(&mut value)), valid);
+        }
+        for (value, valid) in [(0, true), (2, true), (3, false)] {
+            let mut value = value;
+            let ty = FieldType::Enum(VARIANTS);
+            assert_eq!(is_valid(ty, &ConfigValueMut::Enum(&mut value)), valid);
+        }
+        for (value, valid) in [(0, true), (0b111, true), (0b1000, false)] {
+            let mut value = value;
+            let ty = FieldType::Flags(VARIANTS);
+            assert_eq!(is_valid(ty, &ConfigValueMut::Flags(&mut value)), valid);
+        }
+        let mut value = 0;
+        assert!(!is_valid(FieldType::Bool, &ConfigValueMut::U8(&mut value)));
+        assert!(!is_valid(u8_ty, &ConfigValueMut::Enum(&mut value)));
+    }
+
+    #[test]
+    fn sanitize_values() {
+        let mut value = 20;
+        let ty = FieldType::U8 { min: 1, max: 10 };
+        assert!(sanitize(ty, ConfigValueMut::U8(&mut value)));
+        assert_eq!(value, 10);
+        assert!(!sanitize(ty, ConfigValueMut::U8(&mut value)));
+
+        let mut value = 3;
+        assert!(sanitize(
+            FieldType::Enum(VARIANTS),
+            ConfigValueMut::Enum(&mut value)
+        ));
+        assert_eq!(value, 0);
+
+        let mut value = 0b1010;
+        assert!(sanitize(
+            FieldType::Flags(VARIANTS),
+            ConfigValueMut::Flags(&mut value)
+        ));
+        assert_eq!(value, 0b10);
+
+        const NAMES: &[&str] = &["x"; 16];
+        let mut value = u16::MAX;
+        assert!(!sanitize(
+            FieldType::Flags(NAMES),
+            ConfigValueMut::Flags(&mut value)
+        ));
+    }
+}
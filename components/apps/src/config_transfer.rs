@@ -0,0 +1,341 @@
+//! Export and import of the complete device configuration as a single CBOR blob.
+//!
+//! Only the fields listed by [`admin_app::Config::list_available_fields`][] are imported.
+//! Internal state like the filesystem version and the SE050 configuration version is not
+//! exported, and the values of the current config are kept during an import.
+//!
+//! The export and import config commands of admin-app call [`admin_app::Config::export`][] and
+//! [`admin_app::Config::prepare_import`][].  Before applying an import, admin-app asks for a touch
+//! if [`ConfigImport::requires_touch_confirmation`][] is set and resets the clients returned by
+//! [`ConfigImport::reset_clients`][] using their reset signals, like for a single destructive
+//! config change.  The reboot fields are returned to the host.
+
+use admin_app::{Config as _, ConfigField, ConfigValueMut, FieldType, ResetSignalAllocation};
+use heapless::Vec;
+use serde::Serialize;
+use trussed::types::Path;
+
+#[cfg(feature = "piv-authenticator")]
+use crate::PivConfig;
+#[cfg(feature = "secrets-app")]
+use crate::SecretsConfig;
+use crate::{config_field, quota::QuotaConfig, Config, FidoConfig, OpcardConfig};
+
+const MAX_FIELDS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigImportError {
+    /// The blob could not be serialized or deserialized
+    Serialization,
+    /// The blob contains a value that is not valid for the field
+    InvalidValue(&'static str),
+    /// The blob changes more fields than can be imported at once
+    TooLarge,
+}
+
+/// The exported part of the [`Config`][]
+///
+/// The keys are the same as for [`Config`][], so the blob can be deserialized as a [`Config`][]
+/// without internal state.
+#[derive(Serialize)]
+struct ExportedConfig<'a> {
+    #[serde(rename = "f", skip_serializing_if = "is_default")]
+    fido: &'a FidoConfig,
+    #[serde(rename = "o", skip_serializing_if = "is_default")]
+    opcard: &'a OpcardConfig,
+    #[cfg(feature = "piv-authenticator")]
+    #[serde(rename = "p", skip_serializing_if = "is_default")]
+    piv: &'a PivConfig,
+    #[cfg(feature = "secrets-app")]
+    #[serde(rename = "s", skip_serializing_if = "is_default")]
+    secrets: &'a SecretsConfig,
+    #[serde(rename = "q", skip_serializing_if = "is_default")]
+    quota: &'a QuotaConfig,
+}
+
+impl<'a> From<&'a Config> for ExportedConfig<'a> {
+    fn from(config: &'a Config) -> Self {
+        Self {
+            fido: &config.fido,
+            opcard: &config.opcard,
+            #[cfg(feature = "piv-authenticator")]
+            piv: &config.piv,
+            #[cfg(feature = "secrets-app")]
+            secrets: &config.secrets,
+            quota: &config.quota,
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &&T) -> bool {
+    *value == &T::default()
+}
+
+/// A validated config import that has not been applied yet
+pub struct ConfigImport {
+    config: Config,
+    changed: Vec<&'static ConfigField, MAX_FIELDS>,
+    reset_clients: Vec<(&'static Path, &'static ResetSignalAllocation), MAX_FIELDS>,
+    reboot_fields: Vec<&'static str, MAX_FIELDS>,
+}
+
+impl ConfigImport {
+    fn new(config: Config, changed: Vec<&'static ConfigField, MAX_FIELDS>) -> Self {
+        let mut reset_clients: Vec<_, MAX_FIELDS> = Vec::new();
+        let mut reboot_fields = Vec::new();
+        for field in &changed {
+            if field.requires_reboot {
+                reboot_fields.push(field.name).ok();
+            }
+            if !field.destructive {
+                continue;
+            }
+            let Some((client, signal)) = config.reset_client_id(field.name) else {
+                continue;
+            };
+            if !reset_clients.iter().any(|(c, _)| *c == client) {
+                reset_clients.push((client, signal)).ok();
+            }
+        }
+        Self {
+            config,
+            changed,
+            reset_clients,
+            reboot_fields,
+        }
+    }
+
+    /// The fields whose value differs from the current config
+    pub fn changed_fields(&self) -> impl Iterator<Item = &'static ConfigField> + '_ {
+        self.changed.iter().copied()
+    }
+
+    pub fn is_destructive(&self) -> bool {
+        self.changed_fields().any(|field| field.destructive)
+    }
+}
+
+impl admin_app::ConfigImport for ConfigImport {
+    type Config = Config;
+
+    /// Destructive changes always require a touch, even if the field does not request it
+    fn requires_touch_confirmation(&self) -> bool {
+        self.changed_fields()
+            .any(|field| field.requires_touch_confirmation || field.destructive)
+    }
+
+    /// The clients that have to be reset before the import is applied
+    fn reset_clients(&self) -> &[(&'static Path, &'static ResetSignalAllocation)] {
+        &self.reset_clients
+    }
+
+    /// The changed fields that only take effect after a reboot
+    fn reboot_fields(&self) -> &[&'static str] {
+        &self.reboot_fields
+    }
+
+    /// Applies the changed fields to the given config
+    fn apply(mut self, config: &mut Config) {
+        for field in &self.changed {
+            if let (Some(from), Some(to)) =
+                (self.config.field(field.name), config.field(field.name))
+            {
+                copy_value(from, to);
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Serializes the complete config, see [`admin_app::Config::export`][]
+    pub(crate) fn export_config<'a>(
+        &self,
+        buffer: &'a mut [u8],
+    ) -> Result<&'a [u8], ConfigImportError> {
+        cbor_smol::cbor_serialize(&ExportedConfig::from(self), buffer)
+            .map(|data| &*data)
+            .map_err(|_| ConfigImportError::Serialization)
+    }
+
+    /// Parses and validates an exported config and compares it with the current config, see
+    /// [`admin_app::Config::prepare_import`][]
+    pub(crate) fn prepare_config_import(
+        &mut self,
+        data: &[u8],
+    ) -> Result<ConfigImport, ConfigImportError> {
+        let mut config: Config =
+            cbor_smol::cbor_deserialize(data).map_err(|_| ConfigImportError::Serialization)?;
+        let mut changed = Vec::new();
+        for field in self.list_available_fields() {
+            let (Some(new), Some(current)) = (config.field(field.name), self.field(field.name))
+            else {
+                continue;
+            };
+            if !config_field::is_valid(field.ty, &new) {
+                return Err(ConfigImportError::InvalidValue(field.name));
+            }
+            if !is_equal(&new, &current) {
+                changed
+                    .push(field)
+                    .map_err(|_| ConfigImportError::TooLarge)?;
+            }
+        }
+        Ok(ConfigImport::new(config, changed))
+    }
+}
+
+pub(crate) fn is_equal(a: &ConfigValueMut<'_>, b: &ConfigValueMut<'_>) -> bool {
+    match (a, b) {
+        (ConfigValueMut::Bool(a), ConfigValueMut::Bool(b)) => a == b,
+        (ConfigValueMut::U8(a), ConfigValueMut::U8(b)) => a == b,
+        (ConfigValueMut::Enum(a), ConfigValueMut::Enum(b)) => a == b,
+        (ConfigValueMut::Flags(a), ConfigValueMut::Flags(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn copy_value(from: ConfigValueMut<'_>, to: ConfigValueMut<'_>) {
+    match (from, to) {
+        (ConfigValueMut::Bool(from), ConfigValueMut::Bool(to)) => *to = *from,
+        (ConfigValueMut::U8(from), ConfigValueMut::U8(to)) => *to = *from,
+        (ConfigValueMut::Enum(from), ConfigValueMut::Enum(to)) => *to = *from,
+        (ConfigValueMut::Flags(from), ConfigValueMut::Flags(to)) => *to = *from,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use admin_app::{Config as _, ConfigImport as _};
+    #[cfg(all(feature = "se050", feature = "factory-reset"))]
+    use littlefs2_core::path;
+
+    use super::*;
+
+    #[test]
+    fn export_import() {
+        let mut exported = Config::default();
+        exported.fido.skip_up_timeout = 5;
+        exported.opcard.disabled = true;
+        exported.fs_version = 42;
+        let mut buffer = [0; 1024];
+        let data = exported.export(&mut buffer).unwrap();
+
+        let mut config = Config::default();
+        let import = config.prepare_import(data).unwrap();
+        let changed: Vec<_, MAX_FIELDS> = import.changed_fields().map(|f| f.name).collect();
+        assert_eq!(changed, ["fido.skip_up_timeout", "opcard.disabled"]);
+        assert!(!import.requires_touch_confirmation());
+        assert!(!import.is_destructive());
+        assert!(import.reset_clients().is_empty());
+        assert_eq!(import.reboot_fields(), ["opcard.disabled"]);
+
+        import.apply(&mut config);
+        assert_eq!(config.fido, exported.fido);
+        assert_eq!(config.opcard, exported.opcard);
+        // internal state is not exported
+        let imported: Config = cbor_smol::cbor_deserialize(data).unwrap();
+        assert_eq!(imported.fs_version, 0);
+        assert_eq!(config.fs_version, 0);
+
+        let import = config.prepare_import(data).unwrap();
+        assert_eq!(import.changed_fields().count(), 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut exported = Config::default();
+        for field in exported.list_available_fields() {
+            match exported.field(field.name).unwrap() {
+                ConfigValueMut::Bool(value) => *value = !*value,
+                ConfigValueMut::U8(value) => {
+                    if let FieldType::U8 { max, .. } = field.ty {
+                        *value = max;
+                    }
+                }
+                ConfigValueMut::Enum(value) => {
+                    if let FieldType::Enum(variants) = field.ty {
+                        *value = (*value + 1) % variants.len() as u8;
+                    }
+                }
+                ConfigValueMut::Flags(value) => *value = 1,
+            }
+        }
+        let mut buffer = [0; 1024];
+        let data = exported.export(&mut buffer).unwrap();
+
+        let mut config = Config::default();
+        let import = config.prepare_import(data).unwrap();
+        assert_eq!(
+            import.changed_fields().count(),
+            config.list_available_fields().len()
+        );
+        import.apply(&mut config);
+        assert_eq!(config, exported);
+
+        let mut buffer = [0; 1024];
+        assert_eq!(config.export(&mut buffer).unwrap(), data);
+    }
+
+    #[cfg(all(feature = "se050", feature = "factory-reset"))]
+    #[test]
+    fn import_destructive() {
+        let mut exported = Config::default();
+        exported.opcard.use_se050_backend = true;
+        exported.opcard.nfc_disabled = true;
+        let mut buffer = [0; 1024];
+        let data = exported.export(&mut buffer).unwrap();
+
+        let mut config = Config::default();
+        let import = config.prepare_import(data).unwrap();
+        assert!(import.is_destructive());
+        assert!(import.requires_touch_confirmation());
+        // only the destructive field resets the client
+        let clients: Vec<_, MAX_FIELDS> = import
+            .reset_clients()
+            .iter()
+            .map(|(client, _)| *client)
+            .collect();
+        assert_eq!(clients, [path!("opcard")]);
+        assert_eq!(import.reboot_fields(), ["opcard.use_se050_backend"]);
+    }
+
+    #[test]
+    fn export_internal_state() {
+        let mut config = Config::default();
+        config.fs_version = 42;
+        #[cfg(feature = "se050")]
+        {
+            config.se050_backend_configured_version = 1;
+        }
+        let mut buffer = [0; 1024];
+        assert_eq!(config.export(&mut buffer).unwrap(), [0xa0]);
+
+        // internal state in an imported blob is ignored
+        let mut buffer = [0; 1024];
+        let data = cbor_smol::cbor_serialize(&config, &mut buffer).unwrap();
+        let mut current = Config::default();
+        let import = current.prepare_import(data).unwrap();
+        assert_eq!(import.changed_fields().count(), 0);
+        import.apply(&mut current);
+        assert_eq!(current.fs_version, 0);
+    }
+
+    #[test]
+    fn import_invalid() {
+        let mut exported = Config::default();
+        exported.fido.skip_up_timeout = 200;
+        let mut buffer = [0; 1024];
+        let data = exported.export(&mut buffer).unwrap();
+
+        let mut config = Config::default();
+        assert_eq!(
+            config.prepare_import(data).unwrap_err(),
+            ConfigImportError::InvalidValue("fido.skip_up_timeout")
+        );
+        assert_eq!(
+            config.prepare_import(&[0xff]).unwrap_err(),
+            ConfigImportError::Serialization
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Crash log.
+//!
+//! The boards store information about a panic or hard fault in a RAM region that survives the
+//! reset.  On the next boot, it is moved to a reserved [log file][crate::log_file] on the
+//! internal filesystem.  The counter in the header is the number of crashes recorded so far,
+//! followed by the last crash.
+
+use heapless::Vec;
+use heapless_bytes::Bytes;
+use littlefs2_core::{path, Path};
+use serde::Serialize;
+use trussed::store::Store;
+
+use crate::log_file::{self, LogFile, HEADER_LEN};
+
+pub(crate) const LOG_PATH: &Path = path!("/crash-log");
+
+const MAX_MESSAGE_LEN: usize = Crash::MAX_MESSAGE_LEN;
+const REGISTER_COUNT: usize = Crash::REGISTER_COUNT;
+
+const FILE_LEN: usize = HEADER_LEN + 2 + REGISTER_COUNT * 4 + MAX_MESSAGE_LEN;
+
+const LOG: LogFile<FILE_LEN> = LogFile::new(LOG_PATH, "crash log");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CrashKind {
+    Panic = 1,
+    HardFault = 2,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Crash {
+    #[serde(rename = "k")]
+    pub kind: u8,
+    /// The panic message, truncated to [`MAX_MESSAGE_LEN`][] bytes
+    #[serde(rename = "m")]
+    pub message: Bytes<MAX_MESSAGE_LEN>,
+    /// r0, r1, r2, r3, r12, lr, pc and xpsr of the exception frame of a hard fault
+    #[serde(rename = "r")]
+    pub registers: [u32; REGISTER_COUNT],
+}
+
+impl Crash {
+    /// Maximum length of a panic message, longer messages are truncated
+    pub const MAX_MESSAGE_LEN: usize = 128;
+    /// Number of registers of the exception frame, see [`Crash::registers`][]
+    pub const REGISTER_COUNT: usize = 8;
+
+    pub fn new(kind: CrashKind, message: &[u8], registers: [u32; REGISTER_COUNT]) -> Self {
+        let message = &message[..message.len().min(MAX_MESSAGE_LEN)];
+        Self {
+            kind: kind as u8,
+            message: Bytes::from_slice(message).unwrap(),
+            registers,
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8, { FILE_LEN - HEADER_LEN }> {
+        let mut data = Vec::new();
+        data.push(self.kind).ok();
+        for register in self.registers {
+            data.extend_from_slice(&register.to_be_bytes()).ok();
+        }
+        data.push(self.message.len() as u8).ok();
+        data.extend_from_slice(&self.message).ok();
+        data
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        let (&kind, data) = data.split_first()?;
+        let mut registers = [0; REGISTER_COUNT];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = u32::from_be_bytes(data.get(i * 4..i * 4 + 4)?.try_into().ok()?);
+        }
+        let data = &data[REGISTER_COUNT * 4..];
+        let (&message_len, data) = data.split_first()?;
+        let message = data.get(..usize::from(message_len))?;
+        Some(Self {
+            kind,
+            message: Bytes::from_slice(message).ok()?,
+            registers,
+        })
+    }
+}
+
+/// The content of the crash log, returned by the admin-app crash log command
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CrashLog {
+    /// The number of crashes recorded since the device was initialized
+    #[serde(rename = "n")]
+    pub counter: u32,
+    #[serde(rename = "c")]
+    pub last: Option<Crash>,
+}
+
+impl CrashLog {
+    pub fn read<S: Store>(store: &S) -> Self {
+        let Some(data) = LOG.read(store.ifs()) else {
+            return Self::default();
+        };
+        Self {
+            counter: log_file::counter(&data),
+            last: data.get(HEADER_LEN..).and_then(Crash::deserialize),
+        }
+    }
+
+    /// Removes the last crash but keeps the counter
+    pub fn clear<S: Store>(store: &S) -> bool {
+        LOG.clear(store.ifs())
+    }
+
+    /// Stores a crash that was recovered from RAM and increments the counter
+    pub fn record<S: Store>(store: &S, crash: &Crash) {
+        info_now!("Recording crash of kind {}", crash.kind);
+        let fs = store.ifs();
+        let counter = LOG.counter(fs);
+        let mut data: Vec<u8, FILE_LEN> = Vec::new();
+        data.extend_from_slice(&counter.wrapping_add(1).to_be_bytes())
+            .ok();
+        data.extend_from_slice(&crash.serialize()).ok();
+        LOG.write(fs, &data);
+    }
+}
@@ -1,6 +1,7 @@
 #[cfg(not(feature = "se050"))]
 use core::marker::PhantomData;
 
+use heapless::Vec;
 use trussed::{
     api::{Reply, Request},
     error::Error as TrussedError,
@@ -12,9 +13,9 @@ use trussed::{
 #[cfg(feature = "backend-auth")]
 use trussed::types::Location;
 
-use littlefs2_core::{path, Path};
+#[cfg(feature = "se050")]
+use littlefs2_core::path;
 
-use if_chain::if_chain;
 use trussed::{
     api::{reply, request},
     backend::Backend as _,
@@ -38,6 +39,9 @@ use trussed_auth_backend::{AuthBackend, AuthContext, MAX_HW_KEY_LEN};
 #[cfg(feature = "backend-rsa")]
 use trussed_rsa_alloc::SoftwareRsa;
 
+#[cfg(feature = "backend-software-ecc")]
+use super::software_ecc::SoftwareEcc;
+
 use trussed_chunked::ChunkedExtension;
 use trussed_fs_info::FsInfoExtension;
 use trussed_hkdf::HkdfExtension;
@@ -45,11 +49,18 @@ use trussed_manage::ManageExtension;
 use trussed_staging::{StagingBackend, StagingContext};
 use trussed_wrap_key_to_file::WrapKeyToFileExtension;
 
+use super::audit;
+use super::preserve;
+use super::quota::{self, Quota, QuotaContext, MAX_QUOTAS};
+use super::Runner;
+
 #[cfg(feature = "backend-auth")]
 use super::migrations::TRUSSED_AUTH_FS_LAYOUT;
 
 #[cfg(feature = "se050")]
 use super::migrations::SE050_BACKEND_FS_LAYOUT;
+#[cfg(feature = "se050")]
+use super::status::Se050Version;
 
 #[cfg(feature = "webcrypt")]
 use webcrypt::hmacsha256p256::{
@@ -57,7 +68,7 @@ use webcrypt::hmacsha256p256::{
     HmacSha256P256Extension,
 };
 
-#[cfg(feature = "piv-authenticator")]
+#[cfg(any(feature = "piv-authenticator", feature = "backend-software-hpke"))]
 use trussed_hpke::HpkeExtension;
 
 pub struct Dispatch<T = (), D = ()> {
@@ -66,8 +77,11 @@ pub struct Dispatch<T = (), D = ()> {
     #[cfg(feature = "webcrypt")]
     hmacsha256p256: HmacSha256P256Backend,
     staging: StagingBackend,
+    quotas: Vec<Quota, MAX_QUOTAS>,
     #[cfg(feature = "se050")]
     pub(crate) se050: Option<Se050Backend<T, D>>,
+    #[cfg(feature = "se050")]
+    pub(crate) se050_version: Option<Se050Version>,
     #[cfg(not(feature = "se050"))]
     __: PhantomData<(T, D)>,
 }
@@ -79,35 +93,15 @@ pub struct DispatchContext {
     #[cfg(feature = "webcrypt")]
     hmacsha256p256: HmacSha256P256Context,
     staging: StagingContext,
+    quota: QuotaContext,
     #[cfg(feature = "se050")]
     se050: Se050Context,
 }
 
-fn should_preserve_file(file: &Path) -> bool {
-    // We save all "special" objects, with an ID that is representable by a `u8`
-
-    const DIRS: &[&Path] = &[path!("x5c"), path!("ctr"), path!("sec"), path!("pub")];
-
-    let mut components = file.iter();
-    if_chain! {
-        if components.next().as_deref() == Some(path!("/"));
-        if  components.next().is_some();
-        if let Some(intermediary) = components.next();
-        if DIRS.contains(&&*intermediary);
-        if let Some(file_name) = components.next();
-        if components.next().is_none();
-        if file_name.as_ref().len() <=2;
-        then {
-            true
-        } else {
-            false
-        }
-    }
-}
-
 fn build_staging_backend() -> StagingBackend {
     let mut backend = StagingBackend::new();
-    backend.manage.should_preserve_file = |file, _location| should_preserve_file(file);
+    backend.manage.should_preserve_file =
+        |file, location| preserve::should_preserve_file(preserve::DEFAULT_POLICY, file, location);
     backend
 }
 
@@ -128,6 +122,14 @@ const NAMESPACE: trussed_se050_backend::namespacing::Namespace = {
             client: path!("piv"),
             value: NamespaceValue::Client3,
         },
+        NamespaceItem {
+            client: path!("secrets"),
+            value: NamespaceValue::Client4,
+        },
+        NamespaceItem {
+            client: path!("webcrypt"),
+            value: NamespaceValue::Client5,
+        },
     ])
 };
 
@@ -146,6 +148,7 @@ impl<T: Twi, D: Delay> Dispatch<T, D> {
             #[cfg(feature = "webcrypt")]
             hmacsha256p256: Default::default(),
             staging: build_staging_backend(),
+            quotas: Quota::defaults(),
             #[cfg(feature = "se050")]
             se050: se050.map(|driver| {
                 Se050Backend::new(
@@ -156,6 +159,8 @@ impl<T: Twi, D: Delay> Dispatch<T, D> {
                     SE050_BACKEND_FS_LAYOUT,
                 )
             }),
+            #[cfg(feature = "se050")]
+            se050_version: None,
             #[cfg(not(feature = "se050"))]
             __: Default::default(),
         }
@@ -175,6 +180,7 @@ impl<T: Twi, D: Delay> Dispatch<T, D> {
             #[cfg(feature = "webcrypt")]
             hmacsha256p256: Default::default(),
             staging: build_staging_backend(),
+            quotas: Quota::defaults(),
             #[cfg(feature = "se050")]
             se050: se050.map(|driver| {
                 Se050Backend::new(
@@ -185,10 +191,32 @@ impl<T: Twi, D: Delay> Dispatch<T, D> {
                     SE050_BACKEND_FS_LAYOUT,
                 )
             }),
+            #[cfg(feature = "se050")]
+            se050_version: None,
             #[cfg(not(feature = "se050"))]
             __: Default::default(),
         }
     }
+
+    /// Replaces the default storage quotas, see [`Quota::defaults`][]
+    ///
+    /// At most [`MAX_QUOTAS`][] quotas are used.
+    pub fn set_quotas(&mut self, quotas: &[Quota]) {
+        self.quotas = quotas.iter().copied().take(MAX_QUOTAS).collect();
+    }
+
+    /// Sets the rules for the files that are preserved by a factory reset, see
+    /// [`Runner::PRESERVE_POLICY`][]
+    pub(crate) fn set_preserve_policy<R: Runner>(&mut self) {
+        self.staging.manage.should_preserve_file =
+            |file, location| preserve::should_preserve_file(R::PRESERVE_POLICY, file, location);
+    }
+
+    /// Sets the SE050 firmware version that is reported in the extended admin-app status
+    #[cfg(feature = "se050")]
+    pub fn set_se050_version(&mut self, version: Se050Version) {
+        self.se050_version = Some(version);
+    }
 }
 
 // HACK around #[cfg] for where clauses. See https://users.rust-lang.org/t/cfg-on-where-clause-items/90292
@@ -225,6 +253,21 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
         resources: &mut ServiceResources<P>,
     ) -> Result<Reply, TrussedError> {
         match backend {
+            Backend::Audit => {
+                audit::check_request(resources, &ctx.core.path, request);
+                Err(TrussedError::RequestNotAvailable)
+            }
+            Backend::Quota => {
+                let store = resources.platform().store();
+                quota::check_request(
+                    &self.quotas,
+                    &store,
+                    &ctx.core.path,
+                    &mut ctx.backends.quota,
+                    request,
+                )?;
+                Err(TrussedError::RequestNotAvailable)
+            }
             #[cfg(feature = "backend-auth")]
             Backend::Auth => {
                 self.auth
@@ -234,10 +277,14 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
             Backend::HmacSha256P256 => Err(TrussedError::RequestNotAvailable),
             #[cfg(feature = "backend-rsa")]
             Backend::SoftwareRsa => SoftwareRsa.request(&mut ctx.core, &mut (), request, resources),
+            #[cfg(feature = "backend-software-ecc")]
+            Backend::SoftwareEcc => SoftwareEcc.request(&mut ctx.core, &mut (), request, resources),
             Backend::Staging => {
                 self.staging
                     .request(&mut ctx.core, &mut ctx.backends.staging, request, resources)
             }
+            #[cfg(feature = "backend-software-hpke")]
+            Backend::SoftwareHpke => Err(TrussedError::RequestNotAvailable),
             Backend::StagingManage => Err(TrussedError::RequestNotAvailable),
             #[cfg(feature = "se050")]
             Backend::Se050 => self
@@ -260,9 +307,29 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
     ) -> Result<reply::SerdeExtension, TrussedError> {
         #[allow(unreachable_patterns)]
         match backend {
+            Backend::Audit => {
+                if let Extension::Manage = extension {
+                    audit::check_manage_request(resources, &ctx.core.path, request);
+                }
+                Err(TrussedError::RequestNotAvailable)
+            }
+            Backend::Quota => {
+                if let Extension::Chunked = extension {
+                    let store = resources.platform().store();
+                    quota::check_chunked_request(
+                        &self.quotas,
+                        &store,
+                        &ctx.core.path,
+                        &mut ctx.backends.quota,
+                        request,
+                    )?;
+                }
+                Err(TrussedError::RequestNotAvailable)
+            }
             #[cfg(feature = "backend-auth")]
             Backend::Auth => match extension {
-                Extension::Auth => self.auth.extension_request_serialized(
+                Extension::Auth => audit::auth_request(
+                    &mut self.auth,
                     &mut ctx.core,
                     &mut ctx.backends.auth,
                     request,
@@ -283,6 +350,8 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
             },
             #[cfg(feature = "backend-rsa")]
             Backend::SoftwareRsa => Err(TrussedError::RequestNotAvailable),
+            #[cfg(feature = "backend-software-ecc")]
+            Backend::SoftwareEcc => Err(TrussedError::RequestNotAvailable),
             Backend::Staging => match extension {
                 Extension::Chunked => {
                     ExtensionImpl::<ChunkedExtension>::extension_request_serialized(
@@ -318,7 +387,11 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
                         resources,
                     )
                 }
-                #[cfg(feature = "backend-software-hpke")]
+                #[allow(unreachable_patterns)]
+                _ => Err(TrussedError::RequestNotAvailable),
+            },
+            #[cfg(feature = "backend-software-hpke")]
+            Backend::SoftwareHpke => match extension {
                 Extension::Hpke => ExtensionImpl::<HpkeExtension>::extension_request_serialized(
                     &mut self.staging,
                     &mut ctx.core,
@@ -326,7 +399,6 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
                     request,
                     resources,
                 ),
-                #[allow(unreachable_patterns)]
                 _ => Err(TrussedError::RequestNotAvailable),
             },
             Backend::StagingManage => match extension {
@@ -344,7 +416,7 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
             #[cfg(feature = "se050")]
             Backend::Se050 => match extension {
                 #[cfg(feature = "trussed-auth")]
-                Extension::Auth => ExtensionImpl::<AuthExtension>::extension_request_serialized(
+                Extension::Auth => audit::auth_request(
                     self.se050.as_mut().ok_or(TrussedError::GeneralError)?,
                     &mut ctx.core,
                     &mut ctx.backends.se050,
@@ -399,13 +471,24 @@ impl<T: Twi, D: Delay> ExtensionDispatch for Dispatch<T, D> {
 
 #[derive(Debug, Clone, Copy)]
 pub enum Backend {
+    /// Records security-relevant requests in the audit log and passes all requests to the next
+    /// backend
+    Audit,
+    /// Enforces the storage quotas and passes all requests to the next backend
+    Quota,
     #[cfg(feature = "backend-auth")]
     Auth,
     #[cfg(feature = "webcrypt")]
     HmacSha256P256,
     #[cfg(feature = "backend-rsa")]
     SoftwareRsa,
+    /// Software implementation of the curves provided by the SE050, only intended for testing
+    #[cfg(feature = "backend-software-ecc")]
+    SoftwareEcc,
     Staging,
+    /// Software implementation of the HPKE extension
+    #[cfg(feature = "backend-software-hpke")]
+    SoftwareHpke,
     /// Separate BackendId to prevent non-priviledged apps from accessing the manage Extension
     StagingManage,
     #[cfg(feature = "se050")]
@@ -428,7 +511,7 @@ pub enum Extension {
     HmacSha256P256,
     #[cfg(feature = "se050")]
     Se050Manage,
-    #[cfg(feature = "piv-authenticator")]
+    #[cfg(any(feature = "piv-authenticator", feature = "backend-software-hpke"))]
     Hpke,
 }
 
@@ -446,7 +529,7 @@ impl From<Extension> for u8 {
             Extension::Se050Manage => 5,
             Extension::Hkdf => 6,
             Extension::FsInfo => 7,
-            #[cfg(feature = "piv-authenticator")]
+            #[cfg(any(feature = "piv-authenticator", feature = "backend-software-hpke"))]
             Extension::Hpke => 8,
         }
     }
@@ -468,7 +551,7 @@ impl TryFrom<u8> for Extension {
             5 => Ok(Extension::Se050Manage),
             6 => Ok(Extension::Hkdf),
             7 => Ok(Extension::FsInfo),
-            #[cfg(feature = "piv-authenticator")]
+            #[cfg(any(feature = "piv-authenticator", feature = "backend-software-hpke"))]
             8 => Ok(Extension::Hpke),
             _ => Err(TrussedError::InternalError),
         }
@@ -526,30 +609,9 @@ impl<T: Twi, D: Delay> ExtensionId<FsInfoExtension> for Dispatch<T, D> {
     const ID: Self::Id = Self::Id::FsInfo;
 }
 
-#[cfg(feature = "piv-authenticator")]
+#[cfg(any(feature = "piv-authenticator", feature = "backend-software-hpke"))]
 impl<T: Twi, D: Delay> ExtensionId<HpkeExtension> for Dispatch<T, D> {
     type Id = Extension;
 
     const ID: Self::Id = Self::Id::Hpke;
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn file_preserve() {
-        assert!(should_preserve_file(path!("/fido/sec/00")));
-        assert!(should_preserve_file(path!("/fido/x5c/00")));
-        assert!(should_preserve_file(path!("/fido/sec/01")));
-        assert!(should_preserve_file(path!("/fido/x5c/01")));
-        assert!(should_preserve_file(path!("/attn/pub/00")));
-        assert!(should_preserve_file(path!("/attn/sec/01")));
-        assert!(should_preserve_file(path!("/attn/sec/02")));
-        assert!(should_preserve_file(path!("/attn/sec/03")));
-        assert!(should_preserve_file(path!("/attn/x5c/01")));
-        assert!(should_preserve_file(path!("/attn/x5c/02")));
-        assert!(should_preserve_file(path!("/attn/x5c/03")));
-        assert!(!should_preserve_file(path!("/fido/dat/sec/00")));
-    }
-}
@@ -0,0 +1,52 @@
+use apdu_app::{App, CommandView, Data, Interface, Result};
+use iso7816::{Aid, Status};
+
+/// Wrapper for an APDU app that can be disabled on the contactless interface.
+///
+/// The APDU dispatch does not tell us in advance which interface it is going to serve, so instead
+/// of removing the app from the list, the wrapper rejects all requests that are received over NFC.
+pub struct InterfaceFilter<'a, A> {
+    app: &'a mut A,
+    contactless: bool,
+}
+
+impl<'a, A> InterfaceFilter<'a, A> {
+    pub fn new(app: &'a mut A, contactless: bool) -> Self {
+        Self { app, contactless }
+    }
+
+    fn check(&self, interface: Interface) -> Result {
+        match interface {
+            Interface::Contact => Ok(()),
+            Interface::Contactless if self.contactless => Ok(()),
+            Interface::Contactless => Err(Status::ConditionsOfUseNotSatisfied),
+        }
+    }
+}
+
+impl<A: iso7816::App> iso7816::App for InterfaceFilter<'_, A> {
+    fn aid(&self) -> Aid {
+        self.app.aid()
+    }
+}
+
+impl<A: App<R>, const R: usize> App<R> for InterfaceFilter<'_, A> {
+    fn select(
+        &mut self,
+        interface: Interface,
+        apdu: CommandView<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        self.check(interface)?;
+        self.app.select(interface, apdu, reply)
+    }
+
+    fn deselect(&mut self) {
+        self.app.deselect()
+    }
+
+    fn call(&mut self, interface: Interface, apdu: CommandView<'_>, reply: &mut Data<R>) -> Result {
+        self.check(interface)?;
+        self.app.call(interface, apdu, reply)
+    }
+}
@@ -1,6 +1,6 @@
 #![no_std]
 
-#[cfg(feature = "trussed-usbip")]
+#[cfg(any(feature = "backend-software-ecc", feature = "trussed-usbip"))]
 extern crate alloc;
 
 #[cfg(feature = "secrets-app")]
@@ -10,7 +10,7 @@ const WEBCRYPT_APP_CREDENTIALS_COUNT_LIMIT: u16 = 50;
 
 use apdu_dispatch::{response::SIZE as ApduResponseSize, App as ApduApp};
 use bitflags::bitflags;
-use core::marker::PhantomData;
+use core::{marker::PhantomData, time::Duration};
 use ctaphid_dispatch::{app::App as CtaphidApp, MESSAGE_SIZE as CTAPHID_MESSAGE_SIZE};
 #[cfg(feature = "se050")]
 use embedded_hal::blocking::delay::DelayUs;
@@ -27,7 +27,10 @@ extern crate delog;
 generate_macros!();
 
 use serde::{Deserialize, Serialize};
-#[cfg(all(feature = "opcard", feature = "se050"))]
+#[cfg(all(
+    any(feature = "opcard", feature = "secrets-app", feature = "webcrypt"),
+    feature = "se050"
+))]
 use trussed::{api::NotBefore, service::Filestore};
 use trussed::{
     backend::BackendId,
@@ -50,6 +53,40 @@ use webcrypt::{PeekingBypass, Webcrypt};
 mod dispatch;
 pub use dispatch::{Backend, Dispatch, DispatchContext};
 
+mod audit;
+pub use audit::{AuditLog, Entry as AuditLogEntry, Event as AuditEvent};
+
+mod crash;
+pub use crash::{Crash, CrashKind, CrashLog};
+
+mod log_file;
+
+mod preserve;
+pub use preserve::{
+    PreserveRule, PreservedFile, PreservedFiles, DEFAULT_POLICY as DEFAULT_PRESERVE_POLICY,
+};
+
+mod quota;
+pub use quota::Quota;
+use quota::QuotaConfig;
+
+mod status;
+pub use status::{ExtendedStatus, FilesystemBlocks, LargeBlobsStatus, ResetReason, Se050Version};
+
+mod usage;
+pub use usage::{ClientUsage, StorageUsage, Usage};
+
+mod config_field;
+
+mod config_transfer;
+pub use config_transfer::{ConfigImport, ConfigImportError};
+
+mod interface;
+use interface::InterfaceFilter;
+
+#[cfg(feature = "backend-software-ecc")]
+mod software_ecc;
+
 #[cfg(any(feature = "backend-auth", feature = "se050"))]
 pub use dispatch::AUTH_LOCATION;
 
@@ -57,7 +94,50 @@ fn is_default<T: Default + PartialEq>(value: &T) -> bool {
     value == &Default::default()
 }
 
+#[cfg(feature = "se050")]
+fn is_se050_backend_field(name: &str) -> bool {
+    name.ends_with(".use_se050_backend")
+}
+
+#[cfg(all(feature = "piv-authenticator", feature = "se050"))]
+/// Returns true if the client has trussed-auth state or any files on the internal or external
+/// filesystem, using the trussed-auth layout for the given filesystem version
+#[cfg(all(feature = "se050", any(feature = "secrets-app", feature = "webcrypt")))]
+fn is_client_used<S: trussed::store::Store>(client: &Path, fs_version: u32, store: S) -> bool {
+    let auth_layout = if fs_version == 0 {
+        trussed_auth_backend::FilesystemLayout::V0
+    } else {
+        trussed_auth_backend::FilesystemLayout::V1
+    };
+    let trussed_auth_used = trussed_auth_backend::AuthBackend::is_client_active(
+        auth_layout,
+        dispatch::AUTH_LOCATION,
+        client,
+        store,
+    )
+    .unwrap_or_default();
+    let mut fs = ClientFilestore::new(client.into(), store);
+    let files_used = [Location::Internal, Location::External]
+        .into_iter()
+        .any(|location| {
+            fs.read_dir_first(path!(""), location, &NotBefore::None)
+                .unwrap_or_default()
+                .is_some()
+        });
+    trussed_auth_used || files_used
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(all(feature = "piv-authenticator", feature = "se050"))]
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
 mod migrations;
+pub use migrations::{MigrationReport, MigrationResult};
 
 #[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Config {
@@ -68,60 +148,230 @@ pub struct Config {
     #[cfg(feature = "piv-authenticator")]
     #[serde(default, rename = "p", skip_serializing_if = "is_default")]
     piv: PivConfig,
+    #[cfg(feature = "secrets-app")]
+    #[serde(default, rename = "s", skip_serializing_if = "is_default")]
+    secrets: SecretsConfig,
+    #[cfg(feature = "webcrypt")]
+    #[serde(default, rename = "w", skip_serializing_if = "is_default")]
+    webcrypt: WebcryptConfig,
+    #[serde(default, rename = "q", skip_serializing_if = "is_default")]
+    quota: QuotaConfig,
     #[serde(default, rename = "v", skip_serializing_if = "is_default")]
     fs_version: u32,
     #[cfg(feature = "se050")]
     #[serde(default, rename = "se", skip_serializing_if = "is_default")]
     se050_backend_configured_version: u32,
+    /// Set by `Apps::admin_app` if the runner cannot provide the SE050 backend, see
+    /// [`Runner::is_se050_backend_available`][]
+    #[cfg(feature = "se050")]
+    #[serde(skip)]
+    se050_backend_unavailable: bool,
 }
 
+const CONFIG_FIELDS: &[ConfigField] = &[
+    ConfigField {
+        name: "fido.skip_up_timeout",
+        requires_touch_confirmation: false,
+        requires_reboot: false,
+        destructive: false,
+        // in seconds, zero disables the timeout
+        ty: FieldType::U8 {
+            min: 0,
+            max: FidoConfig::MAX_SKIP_UP_TIMEOUT,
+        },
+    },
+    ConfigField {
+        name: "fido.max_resident_credentials",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        // zero selects the default, the effective bound depends on the internal filesystem, see
+        // ResidentCredentialLimits
+        ty: FieldType::U8 {
+            min: 0,
+            max: ResidentCredentialLimits::UPPER_BOUND,
+        },
+    },
+    ConfigField {
+        name: "fido.large_blobs",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        // the maximum size of the large blob array in bytes
+        ty: FieldType::Enum(FidoConfig::LARGE_BLOBS_VARIANTS),
+    },
+    ConfigField {
+        name: "fido.disabled",
+        requires_touch_confirmation: false,
+        // APDU dispatch does not handle well having the currently select application removed
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    ConfigField {
+        name: "opcard.disabled",
+        requires_touch_confirmation: false,
+        // APDU dispatch does not handle well having the currently select application removed
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    ConfigField {
+        name: "opcard.allowed_generation",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        // an empty set selects the default algorithms of the backend
+        ty: FieldType::Flags(OpcardConfig::ALGORITHMS),
+    },
+    ConfigField {
+        name: "opcard.allowed_imports",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        // an empty set selects the default algorithms of the backend
+        ty: FieldType::Flags(OpcardConfig::ALGORITHMS),
+    },
+    ConfigField {
+        name: "opcard.nfc_disabled",
+        requires_touch_confirmation: false,
+        requires_reboot: false,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    #[cfg(feature = "piv-authenticator")]
+    ConfigField {
+        name: "piv.disabled",
+        requires_touch_confirmation: false,
+        // APDU dispatch does not handle well having the currently select application removed
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    #[cfg(feature = "piv-authenticator")]
+    ConfigField {
+        name: "piv.nfc_disabled",
+        requires_touch_confirmation: false,
+        requires_reboot: false,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    #[cfg(feature = "secrets-app")]
+    ConfigField {
+        name: "secrets.nfc_disabled",
+        requires_touch_confirmation: false,
+        requires_reboot: false,
+        destructive: false,
+        ty: FieldType::Bool,
+    },
+    // the maximum share of the internal and the external filesystem in percent
+    #[cfg(feature = "fido-authenticator")]
+    ConfigField {
+        name: "quota.fido",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Enum(QuotaConfig::VARIANTS),
+    },
+    #[cfg(feature = "opcard")]
+    ConfigField {
+        name: "quota.opcard",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Enum(QuotaConfig::VARIANTS),
+    },
+    #[cfg(feature = "piv-authenticator")]
+    ConfigField {
+        name: "quota.piv",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Enum(QuotaConfig::VARIANTS),
+    },
+    #[cfg(feature = "secrets-app")]
+    ConfigField {
+        name: "quota.secrets",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Enum(QuotaConfig::VARIANTS),
+    },
+    #[cfg(feature = "webcrypt")]
+    ConfigField {
+        name: "quota.webcrypt",
+        requires_touch_confirmation: false,
+        requires_reboot: true,
+        destructive: false,
+        ty: FieldType::Enum(QuotaConfig::VARIANTS),
+    },
+    // the SE050 backend fields must be last, see Config::list_available_fields
+    #[cfg(feature = "se050")]
+    ConfigField {
+        name: "opcard.use_se050_backend",
+        requires_touch_confirmation: true,
+        requires_reboot: true,
+        destructive: true,
+        ty: FieldType::Bool,
+    },
+    #[cfg(all(feature = "piv-authenticator", feature = "se050"))]
+    ConfigField {
+        name: "piv.use_se050_backend",
+        requires_touch_confirmation: true,
+        requires_reboot: true,
+        destructive: true,
+        ty: FieldType::Bool,
+    },
+    #[cfg(all(feature = "secrets-app", feature = "se050"))]
+    ConfigField {
+        name: "secrets.use_se050_backend",
+        requires_touch_confirmation: true,
+        requires_reboot: true,
+        destructive: true,
+        ty: FieldType::Bool,
+    },
+];
+
 impl admin_app::Config for Config {
+    type Import = ConfigImport;
+    type ImportError = ConfigImportError;
+
     fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
+        #[cfg(feature = "se050")]
+        if self.se050_backend_unavailable && is_se050_backend_field(key) {
+            return None;
+        }
         let (app, key) = key.split_once('.')?;
         match app {
             "fido" => self.fido.field(key),
             "opcard" => self.opcard.field(key),
             #[cfg(feature = "piv-authenticator")]
             "piv" => self.piv.field(key),
+            #[cfg(feature = "secrets-app")]
+            "secrets" => self.secrets.field(key),
+            "quota" => self.quota.field(key).map(ConfigValueMut::Enum),
             _ => None,
         }
     }
 
     fn list_available_fields(&self) -> &'static [ConfigField] {
-        &[
-            ConfigField {
-                name: "fido.disable_skip_up_timeout",
-                requires_touch_confirmation: false,
-                requires_reboot: false,
-                destructive: false,
-                ty: FieldType::Bool,
-            },
-            #[cfg(feature = "se050")]
-            ConfigField {
-                name: "opcard.use_se050_backend",
-                requires_touch_confirmation: true,
-                requires_reboot: true,
-                destructive: true,
-                ty: FieldType::Bool,
-            },
-            ConfigField {
-                name: "opcard.disabled",
-                requires_touch_confirmation: false,
-                // APDU dispatch does not handle well having the currently select application removed
-                requires_reboot: true,
-                destructive: false,
-                ty: FieldType::Bool,
-            },
-            #[cfg(feature = "piv-authenticator")]
-            ConfigField {
-                name: "piv.disabled",
-                requires_touch_confirmation: false,
-                // APDU dispatch does not handle well having the currently select application removed
-                requires_reboot: true,
-                destructive: false,
-                ty: FieldType::Bool,
-            },
-        ]
+        #[cfg(feature = "se050")]
+        if self.se050_backend_unavailable {
+            let n = CONFIG_FIELDS
+                .iter()
+                .position(|field| is_se050_backend_field(field.name))
+                .unwrap_or(CONFIG_FIELDS.len());
+            return &CONFIG_FIELDS[..n];
+        }
+        CONFIG_FIELDS
+    }
+
+    fn export<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], ConfigImportError> {
+        self.export_config(buffer)
+    }
+
+    fn prepare_import(&mut self, data: &[u8]) -> Result<ConfigImport, ConfigImportError> {
+        self.prepare_config_import(data)
     }
 
     fn reset_client_id(
@@ -141,6 +391,11 @@ impl admin_app::Config for Config {
             #[cfg(feature = "piv-authenticator")]
             (None, "piv") => self.piv.reset_client_id(""),
 
+            #[cfg(feature = "secrets-app")]
+            (Some(("secrets", key)), _) => self.secrets.reset_client_id(key),
+            #[cfg(feature = "secrets-app")]
+            (None, "secrets") => self.secrets.reset_client_id(""),
+
             _ => None,
         };
 
@@ -156,6 +411,10 @@ impl admin_app::Config for Config {
         match key {
             "fido" => self.fido.reset_config(),
             "opcard" => self.opcard.reset_config(),
+            #[cfg(feature = "piv-authenticator")]
+            "piv" => self.piv.reset_config(),
+            #[cfg(feature = "secrets-app")]
+            "secrets" => self.secrets.reset_config(),
             _ => ResetConfigResult::WrongKey,
         }
     }
@@ -169,17 +428,187 @@ impl admin_app::Config for Config {
     }
 }
 
-#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(from = "SerializedFidoConfig", into = "SerializedFidoConfig")]
 pub struct FidoConfig {
+    /// The time in seconds during which a user presence check is reused, or zero to disable it
+    skip_up_timeout: u8,
+    /// A value written to the old `disable_skip_up_timeout` field that has not been applied to
+    /// `skip_up_timeout` yet, see [`FidoConfig::apply_skip_up_timeout_alias`][]
+    disable_skip_up_timeout: Option<bool>,
+    disabled: bool,
+    /// The maximum number of resident credentials, or zero to use the default
+    ///
+    /// Once the limits are set, see [`FidoConfig::set_resident_credential_limits`][], this is
+    /// the effective value.
+    max_resident_credentials: u8,
+    /// Index into [`FidoConfig::LARGE_BLOBS_VARIANTS`][]
+    large_blobs: u8,
+    /// The limits for `max_resident_credentials`, set after loading the config
+    resident_credential_limits: Option<ResidentCredentialLimits>,
+}
+
+impl Default for FidoConfig {
+    fn default() -> Self {
+        Self {
+            skip_up_timeout: Self::DEFAULT_SKIP_UP_TIMEOUT,
+            disable_skip_up_timeout: None,
+            disabled: false,
+            max_resident_credentials: 0,
+            large_blobs: 0,
+            resident_credential_limits: None,
+        }
+    }
+}
+
+/// Compares the effective values, so a pending alias value equals the value it is applied as
+impl PartialEq for FidoConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.skip_up_timeout() == other.skip_up_timeout()
+            && self.disabled == other.disabled
+            && self.stored_max_resident_credentials() == other.stored_max_resident_credentials()
+            && self.large_blobs == other.large_blobs
+    }
+}
+
+/// The serialized representation of the [`FidoConfig`][], including fields from older versions
+#[derive(Deserialize, Serialize)]
+struct SerializedFidoConfig {
+    /// Replaced by `skip_up_timeout`.  `true` is equivalent to a zero timeout.
     #[serde(default, rename = "t", skip_serializing_if = "is_default")]
     disable_skip_up_timeout: bool,
+    #[serde(
+        default = "FidoConfig::default_skip_up_timeout",
+        rename = "u",
+        skip_serializing_if = "FidoConfig::is_default_skip_up_timeout"
+    )]
+    skip_up_timeout: u8,
+    #[serde(default, rename = "d", skip_serializing_if = "is_default")]
+    disabled: bool,
+    #[serde(default, rename = "r", skip_serializing_if = "is_default")]
+    max_resident_credentials: u8,
+    #[serde(default, rename = "l", skip_serializing_if = "is_default")]
+    large_blobs: u8,
+}
+
+impl From<SerializedFidoConfig> for FidoConfig {
+    fn from(config: SerializedFidoConfig) -> Self {
+        let skip_up_timeout = if config.disable_skip_up_timeout {
+            0
+        } else {
+            config.skip_up_timeout
+        };
+        Self {
+            skip_up_timeout,
+            disable_skip_up_timeout: None,
+            disabled: config.disabled,
+            max_resident_credentials: config.max_resident_credentials,
+            large_blobs: config.large_blobs,
+            resident_credential_limits: None,
+        }
+    }
+}
+
+impl From<FidoConfig> for SerializedFidoConfig {
+    fn from(config: FidoConfig) -> Self {
+        Self {
+            disable_skip_up_timeout: false,
+            skip_up_timeout: config.skip_up_timeout(),
+            disabled: config.disabled,
+            max_resident_credentials: config.stored_max_resident_credentials(),
+            large_blobs: config.large_blobs,
+        }
+    }
 }
 
 impl FidoConfig {
+    const DEFAULT_SKIP_UP_TIMEOUT: u8 = 2;
+    const MAX_SKIP_UP_TIMEOUT: u8 = 10;
+    const LARGE_BLOBS_VARIANTS: &'static [&'static str] =
+        &["disabled", "1024", "2048", "4096", "8192"];
+
+    fn default_skip_up_timeout() -> u8 {
+        Self::DEFAULT_SKIP_UP_TIMEOUT
+    }
+
+    fn is_default_skip_up_timeout(value: &u8) -> bool {
+        *value == Self::DEFAULT_SKIP_UP_TIMEOUT
+    }
+
+    /// Returns the skip-UP timeout, including a value written to the old
+    /// `disable_skip_up_timeout` field
+    ///
+    /// Setting the old field to `true` disables the timeout.  Setting it to `false` keeps a
+    /// non-zero timeout and restores the default timeout otherwise.
+    fn skip_up_timeout(&self) -> u8 {
+        match self.disable_skip_up_timeout {
+            Some(true) => 0,
+            Some(false) if self.skip_up_timeout == 0 => Self::DEFAULT_SKIP_UP_TIMEOUT,
+            _ => self.skip_up_timeout,
+        }
+    }
+
+    /// Applies a value written to the old `disable_skip_up_timeout` field to `skip_up_timeout`
+    fn apply_skip_up_timeout_alias(&mut self) {
+        self.skip_up_timeout = self.skip_up_timeout();
+        self.disable_skip_up_timeout = None;
+    }
+
+    /// Sets the limits for `max_resident_credentials` and replaces the stored value with the
+    /// effective value so that admin-app reports the value that is actually used
+    #[cfg(feature = "fido-authenticator")]
+    fn set_resident_credential_limits(&mut self, limits: ResidentCredentialLimits) {
+        self.max_resident_credentials = limits.resolve(self.max_resident_credentials);
+        self.resident_credential_limits = Some(limits);
+    }
+
+    /// Returns the effective maximum number of resident credentials
+    #[cfg(feature = "fido-authenticator")]
+    fn max_resident_credentials(&self) -> u8 {
+        self.resident_credential_limits
+            .unwrap_or_default()
+            .resolve(self.max_resident_credentials)
+    }
+
+    /// Returns the value of `max_resident_credentials` that is stored in the config
+    ///
+    /// The default value is stored as zero so that it follows changes of the default limit,
+    /// e. g. if the quota of the FIDO authenticator is changed.
+    fn stored_max_resident_credentials(&self) -> u8 {
+        match self.resident_credential_limits {
+            Some(limits) if limits.default == self.max_resident_credentials => 0,
+            _ => self.max_resident_credentials,
+        }
+    }
+
+    /// Returns the maximum size of the large blob array, or `None` if large blobs are disabled
+    fn large_blobs_max_size(&self) -> Option<usize> {
+        match self.large_blobs {
+            1 => Some(1024),
+            2 => Some(2048),
+            3 => Some(4096),
+            4 => Some(8192),
+            _ => None,
+        }
+    }
+
     fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
+        // Only one pending value of the alias is stored, so it is applied before the next access
+        self.apply_skip_up_timeout_alias();
         match key {
+            "skip_up_timeout" => Some(ConfigValueMut::U8(&mut self.skip_up_timeout)),
+            // Alias for hosts that still use the boolean field that was replaced by
+            // skip_up_timeout.  It is not listed in the available fields.
             "disable_skip_up_timeout" => {
-                Some(ConfigValueMut::Bool(&mut self.disable_skip_up_timeout))
+                let disabled = self.skip_up_timeout == 0;
+                Some(ConfigValueMut::Bool(
+                    self.disable_skip_up_timeout.insert(disabled),
+                ))
+            }
+            "large_blobs" => Some(ConfigValueMut::Enum(&mut self.large_blobs)),
+            "disabled" => Some(ConfigValueMut::Bool(&mut self.disabled)),
+            "max_resident_credentials" => {
+                Some(ConfigValueMut::U8(&mut self.max_resident_credentials))
             }
             _ => None,
         }
@@ -213,25 +642,144 @@ pub struct OpcardConfig {
     use_se050_backend: bool,
     #[serde(default, rename = "d", skip_serializing_if = "is_default")]
     disabled: bool,
+    /// Bit mask of [`OpcardConfig::ALGORITHMS`][]
+    #[serde(default, rename = "g", skip_serializing_if = "is_default")]
+    allowed_generation: u16,
+    /// Bit mask of [`OpcardConfig::ALGORITHMS`][]
+    #[serde(default, rename = "i", skip_serializing_if = "is_default")]
+    allowed_imports: u16,
+    #[serde(default, rename = "n", skip_serializing_if = "is_default")]
+    nfc_disabled: bool,
 }
 
 #[cfg(feature = "opcard")]
 impl OpcardConfig {
+    fn algorithms(mask: u16) -> opcard::AllowedAlgorithms {
+        use opcard::AllowedAlgorithms as Alg;
+        const ALGORITHMS: [Alg; 12] = [
+            Alg::P_256,
+            Alg::P_384,
+            Alg::P_521,
+            Alg::BRAINPOOL_P256R1,
+            Alg::BRAINPOOL_P384R1,
+            Alg::BRAINPOOL_P512R1,
+            Alg::SECP256K1,
+            Alg::RSA_2048,
+            Alg::RSA_3072,
+            Alg::RSA_4096,
+            Alg::X_25519,
+            Alg::ED_25519,
+        ];
+        ALGORITHMS
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .fold(Alg::empty(), |acc, (_, v)| acc | v)
+    }
+
+    /// Returns the config for the app, without the SE050 backend if the runner cannot provide it
+    #[cfg_attr(not(feature = "se050"), allow(unused_variables))]
+    fn for_runner<R: Runner>(&self, runner: &R) -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: self.use_se050_backend && runner.is_se050_backend_available(),
+            ..*self
+        }
+    }
+
+    fn uses_se050_backend(&self) -> bool {
+        #[cfg(feature = "se050")]
+        return self.use_se050_backend;
+        #[cfg(not(feature = "se050"))]
+        false
+    }
+
+    /// Returns the algorithms supported by the selected backend
+    fn supported_algorithms(&self) -> opcard::AllowedAlgorithms {
+        use opcard::AllowedAlgorithms as Alg;
+        if self.uses_se050_backend() {
+            [
+                Alg::P_256,
+                Alg::P_384,
+                Alg::P_521,
+                Alg::BRAINPOOL_P256R1,
+                Alg::BRAINPOOL_P384R1,
+                Alg::BRAINPOOL_P512R1,
+                #[cfg(feature = "nk3-test")]
+                Alg::SECP256K1,
+                Alg::RSA_2048,
+                Alg::RSA_3072,
+                Alg::RSA_4096,
+                Alg::X_25519,
+                Alg::ED_25519,
+            ]
+            .into_iter()
+            .fold(Alg::empty(), |acc, v| acc | v)
+        } else {
+            [
+                Alg::P_256,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::P_384,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::P_521,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::BRAINPOOL_P256R1,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::BRAINPOOL_P384R1,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::BRAINPOOL_P512R1,
+                #[cfg(feature = "backend-software-ecc")]
+                Alg::SECP256K1,
+                Alg::RSA_2048,
+                Alg::RSA_3072,
+                Alg::RSA_4096,
+                Alg::X_25519,
+                Alg::ED_25519,
+            ]
+            .into_iter()
+            .fold(Alg::empty(), |acc, v| acc | v)
+        }
+    }
+
+    fn allowed_generation(&self) -> opcard::AllowedAlgorithms {
+        use opcard::AllowedAlgorithms as Alg;
+        match self.allowed_generation {
+            // RSA-3072 and RSA-4096 key generation is very slow in software, so it is only
+            // enabled if explicitly requested
+            0 if !self.uses_se050_backend() => {
+                Alg::P_256 | Alg::RSA_2048 | Alg::X_25519 | Alg::ED_25519
+            }
+            0 => self.supported_algorithms(),
+            mask => Self::algorithms(mask) & self.supported_algorithms(),
+        }
+    }
+
+    fn allowed_imports(&self) -> opcard::AllowedAlgorithms {
+        match self.allowed_imports {
+            0 => self.supported_algorithms(),
+            mask => Self::algorithms(mask) & self.supported_algorithms(),
+        }
+    }
+
     fn backends(&self) -> &'static [BackendId<Backend>] {
         const BACKENDS_OPCARD_DEFAULT: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
             BackendId::Custom(Backend::SoftwareRsa),
+            #[cfg(feature = "backend-software-ecc")]
+            BackendId::Custom(Backend::SoftwareEcc),
             BackendId::Custom(Backend::Auth),
             BackendId::Custom(Backend::Staging),
             BackendId::Core,
         ];
         #[cfg(feature = "se050")]
         const BACKENDS_OPCARD_SE050: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
             BackendId::Custom(Backend::Se050),
             BackendId::Custom(Backend::Staging),
             BackendId::Core,
         ];
         #[cfg(feature = "se050")]
-        return match self.use_se050_backend {
+        return match self.uses_se050_backend() {
             true => BACKENDS_OPCARD_SE050,
             false => BACKENDS_OPCARD_DEFAULT,
         };
@@ -241,6 +789,23 @@ impl OpcardConfig {
 }
 
 impl OpcardConfig {
+    /// Names of the algorithms for the `opcard.allowed_generation` and `opcard.allowed_imports`
+    /// fields.  The order must match [`OpcardConfig::algorithms`][].
+    const ALGORITHMS: &'static [&'static str] = &[
+        "p256",
+        "p384",
+        "p521",
+        "brainpoolp256r1",
+        "brainpoolp384r1",
+        "brainpoolp512r1",
+        "secp256k1",
+        "rsa2048",
+        "rsa3072",
+        "rsa4096",
+        "x25519",
+        "ed25519",
+    ];
+
     /// The config value used for initialization and after a factory-reset
     ///
     /// This is distinct from the `Default` value because the old default config was not
@@ -251,6 +816,9 @@ impl OpcardConfig {
             #[cfg(feature = "se050")]
             use_se050_backend: true,
             disabled: false,
+            allowed_generation: 0,
+            allowed_imports: 0,
+            nfc_disabled: false,
         }
     }
 
@@ -259,6 +827,9 @@ impl OpcardConfig {
             #[cfg(feature = "se050")]
             "use_se050_backend" => Some(ConfigValueMut::Bool(&mut self.use_se050_backend)),
             "disabled" => Some(ConfigValueMut::Bool(&mut self.disabled)),
+            "allowed_generation" => Some(ConfigValueMut::Flags(&mut self.allowed_generation)),
+            "allowed_imports" => Some(ConfigValueMut::Flags(&mut self.allowed_imports)),
+            "nfc_disabled" => Some(ConfigValueMut::Bool(&mut self.nfc_disabled)),
             _ => None,
         }
     }
@@ -292,9 +863,57 @@ impl OpcardConfig {
 
 #[cfg(feature = "piv-authenticator")]
 impl PivConfig {
+    /// Returns the config for the app, without the SE050 backend if the runner cannot provide it
+    #[cfg_attr(not(feature = "se050"), allow(unused_variables))]
+    fn for_runner<R: Runner>(&self, runner: &R) -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: self.use_se050_backend && runner.is_se050_backend_available(),
+            ..*self
+        }
+    }
+
+    #[cfg(feature = "se050")]
+    fn uses_se050_backend(&self) -> bool {
+        self.use_se050_backend
+    }
+
+    fn backends(&self) -> &'static [BackendId<Backend>] {
+        const BACKENDS_PIV_DEFAULT: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::SoftwareRsa),
+            #[cfg(feature = "backend-software-ecc")]
+            BackendId::Custom(Backend::SoftwareEcc),
+            BackendId::Custom(Backend::Auth),
+            #[cfg(feature = "backend-software-hpke")]
+            BackendId::Custom(Backend::SoftwareHpke),
+            BackendId::Custom(Backend::Staging),
+            BackendId::Core,
+        ];
+        #[cfg(feature = "se050")]
+        const BACKENDS_PIV_SE050: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::Se050),
+            #[cfg(feature = "backend-software-hpke")]
+            BackendId::Custom(Backend::SoftwareHpke),
+            BackendId::Custom(Backend::Staging),
+            BackendId::Core,
+        ];
+        #[cfg(feature = "se050")]
+        return match self.uses_se050_backend() {
+            true => BACKENDS_PIV_SE050,
+            false => BACKENDS_PIV_DEFAULT,
+        };
+        #[cfg(not(feature = "se050"))]
+        BACKENDS_PIV_DEFAULT
+    }
+
     fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
         match key {
+            #[cfg(feature = "se050")]
+            "use_se050_backend" => Some(ConfigValueMut::Bool(&mut self.use_se050_backend)),
             "disabled" => Some(ConfigValueMut::Bool(&mut self.disabled)),
+            "nfc_disabled" => Some(ConfigValueMut::Bool(&mut self.nfc_disabled)),
             _ => None,
         }
     }
@@ -302,17 +921,203 @@ impl PivConfig {
     #[cfg(feature = "factory-reset")]
     fn reset_client_id(
         &self,
-        _key: &str,
+        key: &str,
     ) -> Option<(&'static Path, &'static ResetSignalAllocation)> {
-        None
+        match key {
+            "" => Some((path!("piv"), &PIV_RESET_SIGNAL)),
+            #[cfg(feature = "se050")]
+            "use_se050_backend" => Some((path!("piv"), &PIV_RESET_SIGNAL)),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "factory-reset")]
+    fn reset_config(&mut self) -> ResetConfigResult {
+        use core::mem;
+        let old = mem::take(self);
+
+        if &old == self {
+            ResetConfigResult::Unchanged
+        } else {
+            ResetConfigResult::Changed
+        }
+    }
+}
+
+#[cfg(feature = "piv-authenticator")]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PivConfig {
+    /// PIV has always used the SE050 if available, so the default value is `true`, unlike for
+    /// `OpcardConfig::use_se050_backend`.
+    #[cfg(feature = "se050")]
+    #[serde(
+        default = "default_true",
+        rename = "s",
+        skip_serializing_if = "is_true"
+    )]
+    use_se050_backend: bool,
+    #[serde(default, rename = "d", skip_serializing_if = "is_default")]
+    disabled: bool,
+    #[serde(default, rename = "n", skip_serializing_if = "is_default")]
+    nfc_disabled: bool,
+}
+
+#[cfg(feature = "piv-authenticator")]
+impl Default for PivConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: true,
+            disabled: false,
+            nfc_disabled: false,
+        }
     }
 }
 
-#[cfg(feature = "piv-authenticator")]
-#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct PivConfig {
-    #[serde(default, rename = "d", skip_serializing_if = "is_default")]
-    disabled: bool,
+#[cfg(feature = "secrets-app")]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct SecretsConfig {
+    #[cfg(feature = "se050")]
+    #[serde(default, rename = "s", skip_serializing_if = "is_default")]
+    use_se050_backend: bool,
+    #[serde(default, rename = "n", skip_serializing_if = "is_default")]
+    nfc_disabled: bool,
+}
+
+#[cfg(feature = "secrets-app")]
+impl SecretsConfig {
+    /// The config value used after a factory-reset
+    ///
+    /// Like for opcard, the SE050 is only used by default for new installations, see
+    /// `Apps::admin_app`.
+    #[cfg(feature = "factory-reset")]
+    fn init() -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: true,
+            nfc_disabled: false,
+        }
+    }
+
+    /// Returns the config for the app, without the SE050 backend if the runner cannot provide it
+    #[cfg_attr(not(feature = "se050"), allow(unused_variables))]
+    fn for_runner<R: Runner>(&self, runner: &R) -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: self.use_se050_backend && runner.is_se050_backend_available(),
+            ..*self
+        }
+    }
+
+    #[cfg(feature = "se050")]
+    fn uses_se050_backend(&self) -> bool {
+        self.use_se050_backend
+    }
+
+    fn backends(&self) -> &'static [BackendId<Backend>] {
+        const BACKENDS_SECRETS_DEFAULT: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::Auth),
+            BackendId::Core,
+        ];
+        #[cfg(feature = "se050")]
+        const BACKENDS_SECRETS_SE050: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::Se050),
+            BackendId::Core,
+        ];
+        #[cfg(feature = "se050")]
+        return match self.uses_se050_backend() {
+            true => BACKENDS_SECRETS_SE050,
+            false => BACKENDS_SECRETS_DEFAULT,
+        };
+        #[cfg(not(feature = "se050"))]
+        BACKENDS_SECRETS_DEFAULT
+    }
+
+    fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
+        match key {
+            #[cfg(feature = "se050")]
+            "use_se050_backend" => Some(ConfigValueMut::Bool(&mut self.use_se050_backend)),
+            "nfc_disabled" => Some(ConfigValueMut::Bool(&mut self.nfc_disabled)),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "factory-reset")]
+    fn reset_client_id(
+        &self,
+        key: &str,
+    ) -> Option<(&'static Path, &'static ResetSignalAllocation)> {
+        match key {
+            "" => Some((path!("secrets"), &SECRETS_RESET_SIGNAL)),
+            #[cfg(feature = "se050")]
+            "use_se050_backend" => Some((path!("secrets"), &SECRETS_RESET_SIGNAL)),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "factory-reset")]
+    fn reset_config(&mut self) -> ResetConfigResult {
+        use core::mem;
+        let old = mem::replace(self, Self::init());
+
+        if &old == self {
+            ResetConfigResult::Unchanged
+        } else {
+            ResetConfigResult::Changed
+        }
+    }
+}
+
+/// webcrypt cannot be reset on its own, so there is no config option to select the backend.
+/// Instead, the SE050 backend is only used for new installations, see `Apps::admin_app`.
+#[cfg(feature = "webcrypt")]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct WebcryptConfig {
+    #[cfg(feature = "se050")]
+    #[serde(default, rename = "s", skip_serializing_if = "is_default")]
+    use_se050_backend: bool,
+}
+
+#[cfg(feature = "webcrypt")]
+impl WebcryptConfig {
+    /// Returns the config for the app, without the SE050 backend if the runner cannot provide it
+    #[cfg_attr(not(feature = "se050"), allow(unused_variables))]
+    fn for_runner<R: Runner>(&self, runner: &R) -> Self {
+        Self {
+            #[cfg(feature = "se050")]
+            use_se050_backend: self.use_se050_backend && runner.is_se050_backend_available(),
+        }
+    }
+
+    fn backends(&self) -> &'static [BackendId<Backend>] {
+        const BACKENDS_WEBCRYPT_DEFAULT: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::SoftwareRsa),
+            BackendId::Custom(Backend::Staging),
+            BackendId::Custom(Backend::Auth),
+            BackendId::Core,
+        ];
+        // The SE050 backend only handles the mechanisms it supports, so RSA and HMAC-SHA256-P256
+        // are still provided by the other backends
+        #[cfg(feature = "se050")]
+        const BACKENDS_WEBCRYPT_SE050: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::Se050),
+            BackendId::Custom(Backend::SoftwareRsa),
+            BackendId::Custom(Backend::Staging),
+            BackendId::Custom(Backend::Auth),
+            BackendId::Core,
+        ];
+        #[cfg(feature = "se050")]
+        return match self.use_se050_backend {
+            true => BACKENDS_WEBCRYPT_SE050,
+            false => BACKENDS_WEBCRYPT_DEFAULT,
+        };
+        #[cfg(not(feature = "se050"))]
+        BACKENDS_WEBCRYPT_DEFAULT
+    }
 }
 
 pub trait Runner {
@@ -332,7 +1137,18 @@ pub trait Runner {
     type Se050Timer: 'static;
 
     fn uuid(&self) -> [u8; 16];
+    fn has_external_flash(&self) -> bool;
     fn is_efs_available(&self) -> bool;
+    /// The rules for the files that are preserved by a factory reset, usually
+    /// [`DEFAULT_PRESERVE_POLICY`][] with additional rules for the files written by the runner
+    const PRESERVE_POLICY: &'static [PreserveRule];
+
+    /// Whether the SE050 backend supports the mechanisms used by the apps
+    ///
+    /// If not, the apps use the software backends and the `*.use_se050_backend` config fields
+    /// are not available.
+    #[cfg(feature = "se050")]
+    fn is_se050_backend_available(&self) -> bool;
 }
 
 pub struct Data<R: Runner> {
@@ -350,7 +1166,8 @@ type Client<R> = ClientImplementation<
     Dispatch<<R as Runner>::Twi, <R as Runner>::Se050Timer>,
 >;
 
-type AdminApp<R> = admin_app::App<Client<R>, <R as Runner>::Reboot, AdminStatus, Config>;
+type AdminApp<R> =
+    admin_app::App<Client<R>, <R as Runner>::Reboot, AdminStatus<<R as Runner>::Store>, Config>;
 #[cfg(feature = "fido-authenticator")]
 type FidoApp<R> = fido_authenticator::Authenticator<fido_authenticator::Conforming, Client<R>>;
 #[cfg(feature = "ndef-app")]
@@ -500,30 +1317,10 @@ const fn validate_mechanisms() {
         if contains(trussed_se050_backend::MECHANISMS, mechanism) {
             continue;
         }
-        // The usbip runner does not have the mechanisms normally provided by the se050 backend.
-        // Until there is a backend implementing them in software, we ignore them and return an
-        // error at runtime.
-        #[cfg(feature = "trussed-usbip")]
-        if contains(
-            &[
-                Mechanism::BrainpoolP256R1,
-                Mechanism::BrainpoolP256R1Prehashed,
-                Mechanism::BrainpoolP384R1,
-                Mechanism::BrainpoolP384R1Prehashed,
-                Mechanism::BrainpoolP512R1,
-                Mechanism::BrainpoolP512R1Prehashed,
-                Mechanism::P384,
-                Mechanism::P384Prehashed,
-                Mechanism::P521,
-                Mechanism::P521Prehashed,
-                Mechanism::Secp256k1,
-                Mechanism::Secp256k1Prehashed,
-            ],
-            mechanism,
-        ) {
+        #[cfg(feature = "backend-software-ecc")]
+        if contains(software_ecc::MECHANISMS, mechanism) {
             continue;
         }
-
         // This mechanism is not implemented by Trussed or any of the backends.
         mechanism.panic();
     }
@@ -549,35 +1346,56 @@ impl<R: Runner> Apps<R> {
             ..
         } = data;
 
-        let (admin, init_status) = Self::admin_app(runner, trussed_service, client_builder, admin);
+        let (admin, init_status, migrations) =
+            Self::admin_app(runner, trussed_service, client_builder, admin);
 
-        let migrated_successfully = !init_status.contains(InitStatus::MIGRATION_ERROR);
-        #[cfg(feature = "opcard")]
+        // Only the apps with data modified by a failed migrator are disabled
+        let migrated_successfully = |client: &Path| {
+            !init_status.contains(InitStatus::FS_DOWNGRADE)
+                && !migrations.is_client_affected(client)
+        };
+        #[cfg(any(
+            feature = "opcard",
+            feature = "piv-authenticator",
+            feature = "secrets-app"
+        ))]
         let config_has_error = init_status.contains(InitStatus::CONFIG_ERROR);
+        #[cfg(feature = "opcard")]
+        let opcard_config = admin.config().opcard.for_runner(runner);
+        #[cfg(feature = "piv-authenticator")]
+        let piv_config = admin.config().piv.for_runner(runner);
+        #[cfg(feature = "secrets-app")]
+        let secrets_config = admin.config().secrets.for_runner(runner);
+        #[cfg(feature = "webcrypt")]
+        let webcrypt_config = admin.config().webcrypt.for_runner(runner);
 
-        // Config errors can have security and stability implications for opcard as they select
-        // the backend to use (se050 or software).  Therefore we disable the app if a config
-        // error occured.
+        // Config errors can have security and stability implications for opcard, PIV and secrets
+        // as they select the backend to use (se050 or software).  Therefore we disable the apps if
+        // a config error occured.
         #[cfg(feature = "opcard")]
-        let opcard = (!config_has_error && migrated_successfully)
-            .then(|| App::new(runner, client_builder, (), &admin.config().opcard));
+        let opcard = (!config_has_error && migrated_successfully(OpcardApp::<R>::CLIENT_ID))
+            .then(|| App::new(runner, client_builder, (), &opcard_config));
         #[cfg(all(feature = "fido-authenticator", not(feature = "webcrypt")))]
-        let fido = migrated_successfully
+        let fido = migrated_successfully(FidoApp::<R>::CLIENT_ID)
             .then(|| App::new(runner, client_builder, fido, &admin.config().fido));
 
         #[cfg(feature = "webcrypt")]
-        let webcrypt_fido_bypass = migrated_successfully.then(|| {
+        let webcrypt_fido_bypass = (migrated_successfully(FidoApp::<R>::CLIENT_ID)
+            && migrated_successfully(WebcryptApp::<R>::CLIENT_ID))
+        .then(|| {
             PeekingBypass::new(
                 App::new(runner, client_builder, fido, &admin.config().fido),
-                App::new(runner, client_builder, (), &()),
+                App::new(runner, client_builder, (), &webcrypt_config),
             )
         });
 
         #[cfg(feature = "secrets-app")]
-        let oath = migrated_successfully.then(|| App::new(runner, client_builder, (), &()));
+        let oath = (!config_has_error && migrated_successfully(SecretsApp::<R>::CLIENT_ID))
+            .then(|| App::new(runner, client_builder, (), &secrets_config));
 
         #[cfg(feature = "piv-authenticator")]
-        let piv = migrated_successfully.then(|| App::new(runner, client_builder, (), &()));
+        let piv = (!config_has_error && migrated_successfully(PivApp::<R>::CLIENT_ID))
+            .then(|| App::new(runner, client_builder, (), &piv_config));
 
         #[cfg(feature = "provisioner-app")]
         let provisioner = App::new(runner, client_builder, provisioner, &());
@@ -606,9 +1424,14 @@ impl<R: Runner> Apps<R> {
         trussed_service: &mut Service<P, Dispatch<R::Twi, R::Se050Timer>>,
         client_builder: &mut ClientBuilder<R>,
         mut data: AdminData<R>,
-    ) -> (AdminApp<R>, InitStatus) {
-        #[cfg(not(feature = "se050"))]
-        let _ = trussed_service;
+    ) -> (AdminApp<R>, InitStatus, MigrationReport) {
+        #[cfg(feature = "se050")]
+        {
+            let dispatch = trussed_service.dispatch_mut();
+            data.se050_present = dispatch.se050.is_some();
+            data.se050_version = dispatch.se050_version;
+        }
+        data.locked = <R::Reboot as Reboot>::locked();
 
         let trussed = client_builder.client::<AdminApp<R>>(runner, &());
         // TODO: use CLIENT_ID directly
@@ -619,7 +1442,7 @@ impl<R: Runner> Apps<R> {
         // No migrations if the config failed to load. In that case applications are disabled anyways
         let config_error_migrators = &[];
 
-        let mut used_migrators = valid_migrators;
+        let mut used_migrations = migrations::MIGRATIONS;
 
         let mut app = AdminApp::<R>::load_config(
             trussed,
@@ -632,7 +1455,7 @@ impl<R: Runner> Apps<R> {
         )
         .unwrap_or_else(|(trussed, _err)| {
             data.init_status.insert(InitStatus::CONFIG_ERROR);
-            used_migrators = config_error_migrators;
+            used_migrations = &[];
             AdminApp::<R>::with_default_config(
                 trussed,
                 runner.uuid(),
@@ -643,6 +1466,31 @@ impl<R: Runner> Apps<R> {
             )
         });
 
+        if app.config_mut().sanitize() {
+            warn_now!("Ignoring invalid config values");
+        }
+        #[cfg(feature = "se050")]
+        {
+            app.config_mut().se050_backend_unavailable = !runner.is_se050_backend_available();
+        }
+        let quotas = app.config().quota.quotas();
+        #[cfg(feature = "fido-authenticator")]
+        {
+            // fido-authenticator stores the resident credentials on the internal filesystem
+            let ifs = usage::filesystem(&data.store, Location::Internal);
+            let blocks = quotas
+                .iter()
+                .find(|quota| {
+                    quota.client == FidoApp::<R>::CLIENT_ID && quota.location == Location::Internal
+                })
+                .map(|quota| quota.max_blocks(ifs))
+                .unwrap_or_else(|| ifs.total_blocks());
+            let limits = ResidentCredentialLimits::for_blocks(blocks, usage::block_size(ifs));
+            app.config_mut().fido.set_resident_credential_limits(limits);
+        }
+        trussed_service.dispatch_mut().set_quotas(&quotas);
+        trussed_service.dispatch_mut().set_preserve_policy::<R>();
+
         #[cfg(all(feature = "opcard", feature = "se050"))]
         if !data.init_status.contains(InitStatus::CONFIG_ERROR)
             && app.config().fs_version == 0
@@ -676,6 +1524,47 @@ impl<R: Runner> Apps<R> {
             }
         }
 
+        #[cfg(all(feature = "secrets-app", feature = "se050"))]
+        if !data.init_status.contains(InitStatus::CONFIG_ERROR)
+            && app.config().fs_version < migrations::MIGRATION_VERSION_SECRETS_SE050
+            && !app.config().secrets.use_se050_backend
+        {
+            // Existing credentials are not migrated to the SE050.  They are encrypted by
+            // secrets-app with a key that may be protected by the PIN, so their HMAC keys cannot
+            // be re-imported into the SE050 without the PIN.  Instead, the SE050 is only enabled
+            // if secrets-app does not have any data yet.  Existing installations keep the
+            // software backend until `secrets.use_se050_backend` is set, which resets
+            // secrets-app.
+            if !is_client_used(path!("secrets"), app.config().fs_version, data.store) {
+                app.config_mut().secrets.use_se050_backend = true;
+                app.save_config_filestore(&mut filestore)
+                    .map_err(|_err| {
+                        app.config_mut().secrets.use_se050_backend = false;
+                        error_now!("Failed to save config after migration: {_err:?}");
+                    })
+                    .ok();
+            }
+        }
+
+        #[cfg(all(feature = "webcrypt", feature = "se050"))]
+        if !data.init_status.contains(InitStatus::CONFIG_ERROR)
+            && app.config().fs_version < migrations::MIGRATION_VERSION_WEBCRYPT_SE050
+            && !app.config().webcrypt.use_se050_backend
+        {
+            // Keys that webcrypt stored in software cannot be moved into the SE050, and webcrypt
+            // cannot be reset on its own.  So existing installations keep the software backend
+            // and the SE050 is only enabled if webcrypt does not have any data yet.
+            if !is_client_used(path!("webcrypt"), app.config().fs_version, data.store) {
+                app.config_mut().webcrypt.use_se050_backend = true;
+                app.save_config_filestore(&mut filestore)
+                    .map_err(|_err| {
+                        app.config_mut().webcrypt.use_se050_backend = false;
+                        error_now!("Failed to save config after migration: {_err:?}");
+                    })
+                    .ok();
+            }
+        }
+
         #[cfg(feature = "se050")]
         'se050_configuration: {
             if app.config().se050_backend_configured_version
@@ -687,6 +1576,12 @@ impl<R: Runner> Apps<R> {
 
                 let Ok(_) = se050.configure().map_err(|_err| {
                     error_now!("Failed to configure SE050: {_err:?}");
+                    audit::record(
+                        &data.store,
+                        None,
+                        AuditEvent::Se050Error,
+                        ADMIN_APP_CLIENT_ID.as_str(),
+                    );
                     data.init_status.insert(InitStatus::SE050_ERROR);
                     *app.status_mut() = data.status();
                 }) else {
@@ -704,26 +1599,97 @@ impl<R: Runner> Apps<R> {
                     .ok();
             }
         }
-        let migration_version = used_migrators
+        let migration_version = used_migrations
             .iter()
-            .map(|m| m.version)
+            .map(|m| m.migrator.version)
             .max()
             .unwrap_or_default();
 
+        // Data written by a newer firmware can use a layout that is not known to this firmware, so
+        // we must not touch it
+        if app.config().fs_version > migration_version {
+            error_now!(
+                "Filesystem version {} is newer than the supported version {}",
+                app.config().fs_version,
+                migration_version
+            );
+            data.init_status.insert(InitStatus::FS_DOWNGRADE);
+            data.migration_version = app.config().fs_version;
+            *app.status_mut() = data.status();
+            return (app, data.init_status, data.migrations);
+        }
+
+        let fs_version = app.config().fs_version;
+        let mut report = MigrationReport::new(fs_version, migration_version);
+        // admin-app only stores the new version if all migrators succeeded, so failed migrators
+        // are run again on the next boot
         let migration_success = app
-            .migrate(migration_version, data.store, &mut filestore)
+            .migrate(
+                migration_version,
+                data.store,
+                &mut filestore,
+                |index, result| report.record(used_migrations, index, result),
+            )
             .is_ok();
+        if !report.succeeded() {
+            audit::record(
+                &data.store,
+                None,
+                AuditEvent::MigrationError,
+                ADMIN_APP_CLIENT_ID.as_str(),
+            );
+        }
         if !migration_success {
+            // Also set if only saving the new version failed.  In this case, all apps can still
+            // be used because the migrators are run again on the next boot.
             data.init_status.insert(InitStatus::MIGRATION_ERROR);
-            *app.status_mut() = data.status();
         }
-        (app, data.init_status)
+        data.migrations = report;
+        data.migration_version = app.config().fs_version;
+        // The FIDO authenticator disables large blobs if the external flash is not available, so
+        // we report the reason in the status
+        #[cfg(feature = "fido-authenticator")]
+        {
+            data.large_blobs = LargeBlobsStatus::new(
+                app.config().fido.large_blobs_max_size().is_some(),
+                runner.has_external_flash(),
+                data.nfc_powered,
+            );
+        }
+        *app.status_mut() = data.status();
+        (app, data.init_status, data.migrations)
     }
 
     pub fn apdu_dispatch<F, T>(&mut self, f: F) -> T
     where
         F: FnOnce(&mut [&mut dyn ApduApp<ApduResponseSize>]) -> T,
     {
+        let config = self.admin.config();
+
+        // The wrappers must outlive the app list, so we create them first
+        #[cfg(feature = "secrets-app")]
+        let mut oath = self
+            .oath
+            .as_mut()
+            .map(|oath| InterfaceFilter::new(oath, !config.secrets.nfc_disabled));
+
+        #[cfg(feature = "opcard")]
+        let mut opcard = self
+            .opcard
+            .as_mut()
+            .filter(|_| !config.opcard.disabled)
+            .map(|opcard| InterfaceFilter::new(opcard, !config.opcard.nfc_disabled));
+
+        #[cfg(feature = "piv-authenticator")]
+        let mut piv = self
+            .piv
+            .as_mut()
+            .filter(|_| !config.piv.disabled)
+            .map(|piv| InterfaceFilter::new(piv, !config.piv.nfc_disabled));
+
+        #[cfg(all(feature = "fido-authenticator", not(feature = "webcrypt")))]
+        let fido = self.fido.as_mut().filter(|_| !config.fido.disabled);
+
         let mut apps: Vec<&mut dyn ApduApp<ApduResponseSize>, 7> = Default::default();
 
         // App 1: ndef
@@ -731,26 +1697,22 @@ impl<R: Runner> Apps<R> {
         apps.push(&mut self.ndef).ok().unwrap();
 
         #[cfg(feature = "secrets-app")]
-        if let Some(oath) = self.oath.as_mut() {
+        if let Some(oath) = oath.as_mut() {
             apps.push(oath).ok().unwrap();
         }
 
         #[cfg(feature = "opcard")]
-        if let Some(opcard) = self.opcard.as_mut() {
-            if !self.admin.config().opcard.disabled {
-                apps.push(opcard).ok().unwrap();
-            }
+        if let Some(opcard) = opcard.as_mut() {
+            apps.push(opcard).ok().unwrap();
         }
 
         #[cfg(feature = "piv-authenticator")]
-        if let Some(piv) = self.piv.as_mut() {
-            if !self.admin.config().piv.disabled {
-                apps.push(piv).ok().unwrap();
-            }
+        if let Some(piv) = piv.as_mut() {
+            apps.push(piv).ok().unwrap();
         }
 
         #[cfg(all(feature = "fido-authenticator", not(feature = "webcrypt")))]
-        if let Some(fido) = self.fido.as_mut() {
+        if let Some(fido) = fido {
             apps.push(fido).ok().unwrap();
         }
 
@@ -774,12 +1736,16 @@ impl<R: Runner> Apps<R> {
         // App 1: webcrypt or fido
         #[cfg(feature = "webcrypt")]
         if let Some(webcrypt) = self.webcrypt.as_mut() {
-            apps.push(webcrypt).ok().unwrap();
+            if !self.admin.config().fido.disabled {
+                apps.push(webcrypt).ok().unwrap();
+            }
         }
 
         #[cfg(all(feature = "fido-authenticator", not(feature = "webcrypt")))]
         if let Some(fido) = self.fido.as_mut() {
-            apps.push(fido).ok().unwrap();
+            if !self.admin.config().fido.disabled {
+                apps.push(fido).ok().unwrap();
+            }
         }
 
         // App 2: admin
@@ -896,6 +1862,7 @@ bitflags! {
         const SE050_ERROR          = 0b00010000;
         const CONFIG_ERROR         = 0b00100000;
         const RNG_ERROR            = 0b01000000;
+        const FS_DOWNGRADE         = 0b10000000;
     }
 }
 
@@ -907,6 +1874,14 @@ pub struct AdminData<R: Runner> {
     pub variant: Variant,
     pub version: Version,
     pub version_string: &'static str,
+    pub nfc_powered: bool,
+    pub reset_reason: ResetReason,
+    migrations: MigrationReport,
+    migration_version: u32,
+    se050_present: bool,
+    se050_version: Option<Se050Version>,
+    locked: bool,
+    large_blobs: LargeBlobsStatus,
 }
 
 impl<R: Runner> AdminData<R> {
@@ -924,19 +1899,43 @@ impl<R: Runner> AdminData<R> {
             variant,
             version,
             version_string,
+            nfc_powered: false,
+            reset_reason: ResetReason::Unknown,
+            migrations: MigrationReport::default(),
+            migration_version: 0,
+            se050_present: false,
+            se050_version: None,
+            locked: false,
+            large_blobs: LargeBlobsStatus::Disabled,
         }
     }
 }
 
-pub struct AdminStatus {
+pub struct AdminStatus<S> {
+    store: S,
     init_status: InitStatus,
     ifs_blocks: u8,
     efs_blocks: u16,
     variant: Variant,
+    migrations: MigrationReport,
+    migration_version: u32,
+    se050_present: bool,
+    se050_version: Option<Se050Version>,
+    nfc_powered: bool,
+    reset_reason: ResetReason,
+    locked: bool,
+    large_blobs: LargeBlobsStatus,
+    preserve_policy: &'static [PreserveRule],
 }
 
-impl admin_app::StatusBytes for AdminStatus {
+impl<S: trussed::store::Store> admin_app::StatusBytes for AdminStatus<S> {
     type Serialized = [u8; 5];
+    type StorageUsage = StorageUsage;
+    type AuditLog = AuditLog;
+    type PreservedFiles = PreservedFiles;
+    type Migrations = MigrationReport;
+    type ExtendedStatus = ExtendedStatus;
+    type CrashLog = CrashLog;
     fn set_random_error(&mut self, value: bool) {
         self.init_status.set(InitStatus::RNG_ERROR, value);
     }
@@ -954,15 +1953,77 @@ impl admin_app::StatusBytes for AdminStatus {
             self.variant.into(),
         ]
     }
+
+    fn extended_status(&self, uptime: Duration) -> ExtendedStatus {
+        // Like during boot, the filesystems are not traversed if powered by NFC
+        let blocks = |location| {
+            (!self.nfc_powered)
+                .then(|| FilesystemBlocks::of(&self.store, location))
+                .flatten()
+        };
+        ExtendedStatus {
+            format_version: status::FORMAT_VERSION,
+            init_status: self.init_status.bits(),
+            variant: self.variant.into(),
+            migration_version: self.migration_version,
+            se050_present: self.se050_present,
+            se050_version: self.se050_version,
+            reset_reason: self.reset_reason as u8,
+            uptime: uptime.as_millis().try_into().unwrap_or(u64::MAX),
+            nfc_powered: self.nfc_powered,
+            locked: self.locked,
+            ifs_blocks: blocks(Location::Internal),
+            efs_blocks: blocks(Location::External),
+            large_blobs: self.large_blobs as u8,
+        }
+    }
+
+    fn storage_usage(&self) -> StorageUsage {
+        StorageUsage::collect(&self.store, self.nfc_powered)
+    }
+
+    fn audit_log(&self) -> AuditLog {
+        AuditLog::read(&self.store)
+    }
+
+    fn clear_audit_log(&mut self) -> bool {
+        AuditLog::clear(&self.store)
+    }
+
+    fn crash_log(&self) -> CrashLog {
+        CrashLog::read(&self.store)
+    }
+
+    fn clear_crash_log(&mut self) -> bool {
+        CrashLog::clear(&self.store)
+    }
+
+    fn preserved_files(&self, client: Option<&Path>) -> PreservedFiles {
+        PreservedFiles::collect(&self.store, self.preserve_policy, client)
+    }
+
+    fn migrations(&self) -> MigrationReport {
+        self.migrations.clone()
+    }
 }
 
 impl<R: Runner> AdminData<R> {
-    fn status(&self) -> AdminStatus {
+    fn status(&self) -> AdminStatus<R::Store> {
         AdminStatus {
+            store: self.store,
             init_status: self.init_status,
             ifs_blocks: self.ifs_blocks,
             efs_blocks: self.efs_blocks,
             variant: self.variant,
+            migrations: self.migrations.clone(),
+            migration_version: self.migration_version,
+            se050_present: self.se050_present,
+            se050_version: self.se050_version,
+            nfc_powered: self.nfc_powered,
+            reset_reason: self.reset_reason,
+            locked: self.locked,
+            large_blobs: self.large_blobs,
+            preserve_policy: R::PRESERVE_POLICY,
         }
     }
 }
@@ -993,6 +2054,7 @@ impl<R: Runner> App<R> for AdminApp<R> {
 
     fn backends(runner: &R, _config: &()) -> &'static [BackendId<Backend>] {
         const BACKENDS_ADMIN: &[BackendId<Backend>] = &[
+            BackendId::Custom(Backend::Audit),
             #[cfg(feature = "se050")]
             BackendId::Custom(Backend::Se050Manage),
             BackendId::Custom(Backend::StagingManage),
@@ -1008,6 +2070,65 @@ pub struct FidoData {
     pub has_nfc: bool,
 }
 
+/// Limits for the `fido.max_resident_credentials` config field
+///
+/// The limits depend on the space that is available for the resident credentials on the internal
+/// filesystem, see [`ResidentCredentialLimits::for_blocks`][].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResidentCredentialLimits {
+    /// The limit used if the config field is not set
+    pub default: u8,
+    /// The upper bound for the config field
+    pub max: u8,
+}
+
+impl ResidentCredentialLimits {
+    /// The upper bound for the config field independent of the filesystem
+    pub const UPPER_BOUND: u8 = 100;
+    /// The limit that was used before it was configurable
+    const MIN_DEFAULT: u8 = 10;
+    /// Upper bound for the size of a serialized resident credential in bytes
+    const CREDENTIAL_SIZE: usize = 512;
+    /// Blocks reserved for the other files of the FIDO authenticator, e. g. the PIN state
+    const RESERVED_BLOCKS: usize = 8;
+
+    /// Returns the limits for the given number of filesystem blocks that the FIDO authenticator
+    /// may use
+    ///
+    /// The upper bound is the number of credentials that fit into these blocks.  The default is
+    /// half of the upper bound, but not less than the limit used before it was configurable.
+    pub fn for_blocks(blocks: usize, block_size: usize) -> Self {
+        let blocks_per_credential = usage::blocks(Self::CREDENTIAL_SIZE, block_size).max(1);
+        let credentials = blocks.saturating_sub(Self::RESERVED_BLOCKS) / blocks_per_credential;
+        let max = u8::try_from(credentials)
+            .unwrap_or(u8::MAX)
+            .clamp(1, Self::UPPER_BOUND);
+        Self {
+            default: (max / 2).clamp(Self::MIN_DEFAULT.min(max), max),
+            max,
+        }
+    }
+
+    #[cfg(feature = "fido-authenticator")]
+    fn resolve(&self, configured: u8) -> u8 {
+        if configured == 0 {
+            self.default
+        } else {
+            configured.min(self.max)
+        }
+    }
+}
+
+/// Used if the filesystem is not known, i. e. the limit before it was configurable
+impl Default for ResidentCredentialLimits {
+    fn default() -> Self {
+        Self {
+            default: Self::MIN_DEFAULT,
+            max: Self::MIN_DEFAULT,
+        }
+    }
+}
+
 #[cfg(feature = "fido-authenticator")]
 impl<R: Runner> App<R> for FidoApp<R> {
     const CLIENT_ID: &'static Path = path!("fido");
@@ -1016,26 +2137,35 @@ impl<R: Runner> App<R> for FidoApp<R> {
     type Config = FidoConfig;
 
     fn with_client(runner: &R, trussed: Client<R>, data: FidoData, config: &Self::Config) -> Self {
-        let skip_up_timeout = if config.disable_skip_up_timeout {
-            None
-        } else {
-            Some(core::time::Duration::from_secs(2))
-        };
-        let large_blobs = if cfg!(feature = "nk3-test") && runner.is_efs_available() {
-            Some(fido_authenticator::LargeBlobsConfig {
-                location: Location::External,
-                max_size: 4096,
-            })
-        } else {
-            None
+        let skip_up_timeout = match config
+            .skip_up_timeout()
+            .min(FidoConfig::MAX_SKIP_UP_TIMEOUT)
+        {
+            0 => None,
+            secs => Some(core::time::Duration::from_secs(secs.into())),
         };
+        let max_resident_credentials = config.max_resident_credentials();
+        // Large blobs are stored on the external flash.  If it is not available, e. g. if the
+        // device is NFC-powered or does not have an external flash, the authenticator does not
+        // advertise the largeBlobs extension.
+        let large_blobs = config.large_blobs_max_size().and_then(|max_size| {
+            if runner.is_efs_available() {
+                Some(fido_authenticator::LargeBlobsConfig {
+                    location: Location::External,
+                    max_size,
+                })
+            } else {
+                warn_now!("External flash not available, disabling large blobs");
+                None
+            }
+        });
         fido_authenticator::Authenticator::new(
             trussed,
             fido_authenticator::Conforming {},
             fido_authenticator::Config {
                 max_msg_size: usbd_ctaphid::constants::MESSAGE_SIZE,
                 skip_up_timeout,
-                max_resident_credential_count: Some(10),
+                max_resident_credential_count: Some(max_resident_credentials.into()),
                 large_blobs,
                 nfc_transport: data.has_nfc,
             },
@@ -1053,7 +2183,11 @@ impl<R: Runner> App<R> for FidoApp<R> {
     }
 
     fn backends(_runner: &R, _config: &Self::Config) -> &'static [BackendId<Backend>] {
-        &[BackendId::Custom(Backend::Staging), BackendId::Core]
+        &[
+            BackendId::Custom(Backend::Quota),
+            BackendId::Custom(Backend::Staging),
+            BackendId::Core,
+        ]
     }
 }
 
@@ -1062,9 +2196,9 @@ impl<R: Runner> App<R> for WebcryptApp<R> {
     const CLIENT_ID: &'static Path = path!("webcrypt");
 
     type Data = ();
-    type Config = ();
+    type Config = WebcryptConfig;
 
-    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &()) -> Self {
+    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &WebcryptConfig) -> Self {
         let uuid = runner.uuid();
         Webcrypt::new_with_options(
             trussed,
@@ -1081,34 +2215,35 @@ impl<R: Runner> App<R> for WebcryptApp<R> {
         &CHANNEL
     }
 
-    fn backends(runner: &R, _: &()) -> &'static [BackendId<Backend>] {
-        const BACKENDS_WEBCRYPT: &[BackendId<Backend>] = &[
-            BackendId::Custom(Backend::SoftwareRsa),
-            BackendId::Custom(Backend::Staging),
-            BackendId::Custom(Backend::Auth),
-            BackendId::Core,
-        ];
-        let _ = runner;
-        BACKENDS_WEBCRYPT
+    fn backends(_runner: &R, config: &WebcryptConfig) -> &'static [BackendId<Backend>] {
+        config.backends()
     }
 }
 
+#[cfg(all(feature = "secrets-app", feature = "factory-reset"))]
+static SECRETS_RESET_SIGNAL: ResetSignalAllocation = ResetSignalAllocation::new();
+
 #[cfg(feature = "secrets-app")]
 impl<R: Runner> App<R> for SecretsApp<R> {
     const CLIENT_ID: &'static Path = path!("secrets");
 
     type Data = ();
-    type Config = ();
+    type Config = SecretsConfig;
 
-    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &()) -> Self {
+    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &SecretsConfig) -> Self {
         let uuid = runner.uuid();
-        let options = secrets_app::Options::new(
+        #[allow(unused_mut)]
+        let mut options = secrets_app::Options::new(
             Location::External,
             CustomStatus::ReverseHotpSuccess.into(),
             CustomStatus::ReverseHotpError.into(),
             [uuid[0], uuid[1], uuid[2], uuid[3]],
             SECRETS_APP_CREDENTIALS_COUNT_LIMIT,
         );
+        #[cfg(feature = "factory-reset")]
+        {
+            options.reset_signal = Some(&SECRETS_RESET_SIGNAL);
+        }
         Self::new(trussed, options)
     }
 
@@ -1117,11 +2252,8 @@ impl<R: Runner> App<R> for SecretsApp<R> {
         &CHANNEL
     }
 
-    fn backends(runner: &R, _: &()) -> &'static [BackendId<Backend>] {
-        const BACKENDS_OATH: &[BackendId<Backend>] =
-            &[BackendId::Custom(Backend::Auth), BackendId::Core];
-        let _ = runner;
-        BACKENDS_OATH
+    fn backends(_runner: &R, config: &SecretsConfig) -> &'static [BackendId<Backend>] {
+        config.backends()
     }
 
     fn interrupt() -> Option<&'static InterruptFlag> {
@@ -1141,7 +2273,6 @@ impl<R: Runner> App<R> for OpcardApp<R> {
     type Config = OpcardConfig;
 
     fn with_client(runner: &R, trussed: Client<R>, _: (), config: &OpcardConfig) -> Self {
-        let _ = config;
         let uuid = runner.uuid();
         let mut options = opcard::Options::default();
         options.button_available = true;
@@ -1149,41 +2280,8 @@ impl<R: Runner> App<R> for OpcardApp<R> {
         options.manufacturer = 0x000Fu16.to_be_bytes();
         options.serial = [uuid[0], uuid[1], uuid[2], uuid[3]];
         options.storage = Location::External;
-        {
-            use opcard::AllowedAlgorithms as Alg;
-            options.allowed_imports = Alg::P_256
-                | Alg::RSA_2048
-                | Alg::RSA_3072
-                | Alg::RSA_4096
-                | Alg::X_25519
-                | Alg::ED_25519;
-            options.allowed_generation = Alg::P_256 | Alg::RSA_2048 | Alg::X_25519 | Alg::ED_25519;
-        }
-        #[cfg(feature = "se050")]
-        {
-            if config.use_se050_backend {
-                use opcard::AllowedAlgorithms as Alg;
-                let algs = [
-                    Alg::P_256,
-                    Alg::P_384,
-                    Alg::P_521,
-                    Alg::BRAINPOOL_P256R1,
-                    Alg::BRAINPOOL_P384R1,
-                    Alg::BRAINPOOL_P512R1,
-                    #[cfg(feature = "nk3-test")]
-                    Alg::SECP256K1,
-                    Alg::RSA_2048,
-                    Alg::RSA_3072,
-                    Alg::RSA_4096,
-                    Alg::X_25519,
-                    Alg::ED_25519,
-                ]
-                .into_iter()
-                .fold(Alg::empty(), |acc, v| acc | v);
-                options.allowed_imports = algs;
-                options.allowed_generation = algs;
-            }
-        }
+        options.allowed_imports = config.allowed_imports();
+        options.allowed_generation = config.allowed_generation();
 
         #[cfg(any(feature = "factory-reset", feature = "se050"))]
         {
@@ -1207,18 +2305,24 @@ impl<R: Runner> App<R> for OpcardApp<R> {
     }
 }
 
+#[cfg(all(
+    feature = "piv-authenticator",
+    any(feature = "factory-reset", feature = "se050")
+))]
+static PIV_RESET_SIGNAL: ResetSignalAllocation = ResetSignalAllocation::new();
+
 #[cfg(feature = "piv-authenticator")]
 impl<R: Runner> App<R> for PivApp<R> {
     const CLIENT_ID: &'static Path = path!("piv");
 
     type Data = ();
-    type Config = ();
+    type Config = PivConfig;
 
-    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &()) -> Self {
-        Self::new(
-            trussed,
-            piv_authenticator::Options::default().uuid(Some(runner.uuid())),
-        )
+    fn with_client(runner: &R, trussed: Client<R>, _: (), _: &PivConfig) -> Self {
+        let options = piv_authenticator::Options::default().uuid(Some(runner.uuid()));
+        #[cfg(any(feature = "factory-reset", feature = "se050"))]
+        let options = options.reset_signal(Some(&PIV_RESET_SIGNAL));
+        Self::new(trussed, options)
     }
 
     fn channel() -> &'static TrussedChannel {
@@ -1226,15 +2330,8 @@ impl<R: Runner> App<R> for PivApp<R> {
         &CHANNEL
     }
 
-    fn backends(runner: &R, _: &()) -> &'static [BackendId<Backend>] {
-        const BACKENDS_PIV: &[BackendId<Backend>] = &[
-            #[cfg(feature = "se050")]
-            BackendId::Custom(Backend::Se050),
-            BackendId::Custom(Backend::Staging),
-            BackendId::Core,
-        ];
-        let _ = runner;
-        BACKENDS_PIV
+    fn backends(_runner: &R, config: &PivConfig) -> &'static [BackendId<Backend>] {
+        config.backends()
     }
 
     fn interrupt() -> Option<&'static InterruptFlag> {
@@ -1285,23 +2382,59 @@ impl<R: Runner> App<R> for ProvisionerApp<R> {
 mod tests {
     #[cfg(feature = "piv-authenticator")]
     use super::PivConfig;
-    use super::{Config, FidoConfig, OpcardConfig};
-    use cbor_smol::cbor_serialize;
+    #[cfg(feature = "secrets-app")]
+    use super::SecretsConfig;
+    use super::{
+        Config, FidoConfig, LargeBlobsStatus, OpcardConfig, QuotaConfig, ResidentCredentialLimits,
+    };
+    use admin_app::{Config as _, ConfigValueMut, FieldType};
+    use cbor_smol::{cbor_deserialize, cbor_serialize};
 
     #[test]
     fn test_config_size() {
+        // a common case with changes in all sections
         let config = Config {
             fido: FidoConfig {
-                disable_skip_up_timeout: true,
+                skip_up_timeout: 10,
+                disable_skip_up_timeout: None,
+                disabled: false,
+                max_resident_credentials: 100,
+                large_blobs: 4,
+                resident_credential_limits: None,
             },
             opcard: OpcardConfig {
                 #[cfg(feature = "se050")]
                 use_se050_backend: true,
-                disabled: true,
+                disabled: false,
+                allowed_generation: 0x0fff,
+                allowed_imports: 0,
+                nfc_disabled: true,
             },
             #[cfg(feature = "piv-authenticator")]
-            piv: PivConfig { disabled: true },
+            piv: PivConfig {
+                #[cfg(feature = "se050")]
+                use_se050_backend: false,
+                disabled: true,
+                nfc_disabled: false,
+            },
+            #[cfg(feature = "secrets-app")]
+            secrets: SecretsConfig {
+                #[cfg(feature = "se050")]
+                use_se050_backend: true,
+                nfc_disabled: true,
+            },
+            #[cfg(feature = "webcrypt")]
+            webcrypt: Default::default(),
+            quota: QuotaConfig {
+                fido: 8,
+                secrets: 3,
+                ..Default::default()
+            },
             fs_version: 1,
+            #[cfg(feature = "se050")]
+            se050_backend_configured_version: 1,
+            #[cfg(feature = "se050")]
+            se050_backend_unavailable: false,
         };
         let mut buffer = [0; 1024];
         let data = cbor_serialize(&config, &mut buffer).unwrap();
@@ -1310,4 +2443,257 @@ mod tests {
         // LPC55, this could be increased to 128 if necessary.
         assert!(data.len() < 64, "{}: {}", data.len(), hex::encode(data));
     }
+
+    #[test]
+    fn test_fido_config_migration() {
+        // {"f": {"t": true}}
+        let config: Config = cbor_deserialize(&hex::decode("a16166a16174f5").unwrap()).unwrap();
+        assert_eq!(config.fido.skip_up_timeout, 0);
+
+        // {"f": {"t": false}}
+        let config: Config = cbor_deserialize(&hex::decode("a16166a16174f4").unwrap()).unwrap();
+        assert_eq!(config.fido, FidoConfig::default());
+        assert_eq!(config.fido.skip_up_timeout, 2);
+
+        // {}
+        let config: Config = cbor_deserialize(&hex::decode("a0").unwrap()).unwrap();
+        assert_eq!(config, Config::default());
+
+        let mut buffer = [0; 1024];
+        let data = cbor_serialize(&Config::default(), &mut buffer).unwrap();
+        assert_eq!(data, hex::decode("a0").unwrap());
+    }
+
+    #[test]
+    fn test_fido_skip_up_timeout_alias() {
+        fn set_alias(config: &mut Config, value: bool) {
+            let Some(ConfigValueMut::Bool(alias)) = config.field("fido.disable_skip_up_timeout")
+            else {
+                panic!("missing alias");
+            };
+            *alias = value;
+        }
+        fn get_alias(config: &mut Config) -> bool {
+            let Some(ConfigValueMut::Bool(alias)) = config.field("fido.disable_skip_up_timeout")
+            else {
+                panic!("missing alias");
+            };
+            *alias
+        }
+
+        let mut config = Config::default();
+        assert!(!get_alias(&mut config));
+        set_alias(&mut config, true);
+        assert_eq!(config.fido.skip_up_timeout(), 0);
+        let mut buffer = [0; 1024];
+        let data = cbor_serialize(&config, &mut buffer).unwrap();
+        // {"f": {"u": 0}}
+        assert_eq!(data, hex::decode("a16166a1617500").unwrap());
+        assert!(get_alias(&mut config));
+        assert_eq!(config.fido.skip_up_timeout, 0);
+
+        set_alias(&mut config, false);
+        assert_eq!(config.fido.skip_up_timeout(), 2);
+        let data = cbor_serialize(&config, &mut buffer).unwrap();
+        assert_eq!(data, hex::decode("a0").unwrap());
+
+        let Some(ConfigValueMut::U8(timeout)) = config.field("fido.skip_up_timeout") else {
+            panic!("missing field");
+        };
+        *timeout = 5;
+        set_alias(&mut config, false);
+        assert_eq!(config.fido.skip_up_timeout(), 5);
+        assert!(config
+            .list_available_fields()
+            .iter()
+            .all(|field| field.name != "fido.disable_skip_up_timeout"));
+    }
+
+    #[test]
+    fn test_config_fields() {
+        let mut config = Config::default();
+        for field in config.list_available_fields() {
+            let value = config
+                .field(field.name)
+                .unwrap_or_else(|| panic!("missing field {}", field.name));
+            match (field.ty, value) {
+                (FieldType::Bool, ConfigValueMut::Bool(_)) => {}
+                (FieldType::U8 { min, max }, ConfigValueMut::U8(value)) => {
+                    assert!(min <= max, "{}", field.name);
+                    assert!((min..=max).contains(value), "{}", field.name);
+                }
+                (FieldType::Enum(variants), ConfigValueMut::Enum(value)) => {
+                    assert!(usize::from(*value) < variants.len(), "{}", field.name);
+                }
+                (FieldType::Flags(names), ConfigValueMut::Flags(value)) => {
+                    assert!(names.len() <= 16, "{}", field.name);
+                    assert_eq!(*value, 0, "{}", field.name);
+                }
+                _ => panic!("type mismatch for field {}", field.name),
+            }
+        }
+        assert!(config.field("fido.unknown").is_none());
+        assert!(config.field("opcard.unknown").is_none());
+        assert!(config.field("piv.unknown").is_none());
+        assert!(config.field("secrets.unknown").is_none());
+        assert!(config.field("unknown").is_none());
+        assert!(!config.sanitize());
+
+        config.fido.large_blobs = u8::MAX;
+        config.opcard.allowed_generation = u16::MAX;
+        assert!(config.sanitize());
+        assert_eq!(config.fido.large_blobs, 0);
+        assert_eq!(config.opcard.allowed_generation, 0x0fff);
+        assert!(!config.sanitize());
+    }
+
+    #[cfg(feature = "se050")]
+    #[test]
+    fn test_se050_backend_unavailable() {
+        let mut config = Config::default();
+        let fields = config.list_available_fields();
+        let n = fields
+            .iter()
+            .position(|field| is_se050_backend_field(field.name))
+            .unwrap();
+        assert!(fields[n..]
+            .iter()
+            .all(|field| is_se050_backend_field(field.name)));
+        assert!(config.field("opcard.use_se050_backend").is_some());
+
+        config.se050_backend_unavailable = true;
+        assert_eq!(config.list_available_fields().len(), n);
+        assert!(config.field("opcard.use_se050_backend").is_none());
+        assert!(config.field("opcard.disabled").is_some());
+
+        // the flag is not stored
+        config.opcard.use_se050_backend = true;
+        let mut buffer = [0; 1024];
+        let data = cbor_serialize(&config, &mut buffer).unwrap();
+        // {"o": {"s": true}}
+        assert_eq!(data, hex::decode("a1616fa16173f5").unwrap());
+    }
+
+    #[cfg(feature = "fido-authenticator")]
+    #[test]
+    fn test_resident_credential_field() {
+        let limits = ResidentCredentialLimits {
+            default: 20,
+            max: 50,
+        };
+        let mut buffer = [0; 16];
+        let mut default_buffer = [0; 16];
+        let default_data = cbor_serialize(&FidoConfig::default(), &mut default_buffer).unwrap();
+
+        // the default is reported as the effective value but stored as zero
+        let mut config = FidoConfig::default();
+        config.set_resident_credential_limits(limits);
+        assert_eq!(config.max_resident_credentials, 20);
+        assert_eq!(config, FidoConfig::default());
+        assert_eq!(cbor_serialize(&config, &mut buffer).unwrap(), default_data);
+
+        // configured values are clamped to the upper bound
+        let mut config = FidoConfig {
+            max_resident_credentials: 80,
+            ..Default::default()
+        };
+        config.set_resident_credential_limits(limits);
+        assert_eq!(config.max_resident_credentials, 50);
+        assert_eq!(config.max_resident_credentials(), 50);
+
+        // without limits, the limit used before it was configurable applies
+        let config = FidoConfig::default();
+        assert_eq!(config.max_resident_credentials(), 10);
+    }
+
+    #[test]
+    fn test_large_blobs_max_size() {
+        let mut config = FidoConfig::default();
+        assert_eq!(config.large_blobs_max_size(), None);
+        for (i, variant) in FidoConfig::LARGE_BLOBS_VARIANTS.iter().enumerate() {
+            config.large_blobs = i.try_into().unwrap();
+            let expected = variant.parse().ok();
+            assert_eq!(config.large_blobs_max_size(), expected, "{variant}");
+        }
+        config.large_blobs = u8::MAX;
+        assert_eq!(config.large_blobs_max_size(), None);
+    }
+
+    #[test]
+    fn test_large_blobs_status() {
+        use LargeBlobsStatus as S;
+        for (enabled, has_external_flash, nfc_powered, expected) in [
+            (false, true, false, S::Disabled),
+            (false, false, true, S::Disabled),
+            (true, true, false, S::Enabled),
+            (true, false, false, S::NoExternalFlash),
+            (true, false, true, S::NoExternalFlash),
+            (true, true, true, S::NfcPowered),
+        ] {
+            assert_eq!(
+                S::new(enabled, has_external_flash, nfc_powered),
+                expected,
+                "{enabled} {has_external_flash} {nfc_powered}"
+            );
+        }
+    }
+
+    #[cfg(feature = "opcard")]
+    #[test]
+    fn test_opcard_algorithms() {
+        use opcard::AllowedAlgorithms as Alg;
+
+        let mut config = OpcardConfig::default();
+        assert_eq!(
+            config.allowed_generation(),
+            Alg::P_256 | Alg::RSA_2048 | Alg::X_25519 | Alg::ED_25519
+        );
+        assert!(config.allowed_imports().contains(Alg::RSA_4096));
+
+        // ed25519 and x25519
+        config.allowed_generation = 0b1100_0000_0000;
+        config.allowed_imports = 0b1100_0000_0000;
+        assert_eq!(config.allowed_generation(), Alg::X_25519 | Alg::ED_25519);
+        assert_eq!(config.allowed_imports(), Alg::X_25519 | Alg::ED_25519);
+
+        // rsa4096 and p384, p384 is only supported by the software backend with the software
+        // ECC backend
+        config.allowed_generation = 0b10_0000_0010;
+        if cfg!(feature = "backend-software-ecc") {
+            assert_eq!(config.allowed_generation(), Alg::RSA_4096 | Alg::P_384);
+        } else {
+            assert_eq!(config.allowed_generation(), Alg::RSA_4096);
+        }
+    }
+
+    #[cfg(feature = "fido-authenticator")]
+    #[test]
+    fn test_resident_credential_limits() {
+        let limits = ResidentCredentialLimits {
+            default: 20,
+            max: 50,
+        };
+        assert_eq!(limits.resolve(0), 20);
+        assert_eq!(limits.resolve(1), 1);
+        assert_eq!(limits.resolve(50), 50);
+        assert_eq!(limits.resolve(200), 50);
+    }
+
+    #[test]
+    fn test_resident_credential_limits_for_blocks() {
+        for (blocks, block_size, default, max) in [
+            (0, 256, 1, 1),
+            (20, 256, 6, 6),
+            (40, 256, 10, 16),
+            (208, 256, 50, 100),
+            (100, 4096, 46, 92),
+            (1000, 4096, 50, 100),
+        ] {
+            assert_eq!(
+                ResidentCredentialLimits::for_blocks(blocks, block_size),
+                ResidentCredentialLimits { default, max },
+                "{blocks} {block_size}"
+            );
+        }
+    }
 }
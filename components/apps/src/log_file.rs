@@ -0,0 +1,80 @@
+//! Log files on the internal filesystem.
+//!
+//! The audit log and the crash log are stored in reserved files in the root directory, outside
+//! of the client directories, so that apps cannot access them.  Both files start with a
+//! big-endian counter of the recorded entries, see [`audit`][] and [`crash`][] for the layout of
+//! the entries.  The counter is kept when the log is cleared, so that missing entries can be
+//! detected, and the files are kept during a factory reset of the device, see [`is_reserved`][].
+
+use heapless::Vec;
+use littlefs2_core::{DynFilesystem, OpenSeekFrom, Path};
+use trussed::types::Location;
+
+use crate::{audit, crash};
+
+/// The length of the counter at the start of the file
+pub const HEADER_LEN: usize = 4;
+
+/// The reserved files on the internal filesystem
+const RESERVED_FILES: &[&Path] = &[audit::LOG_PATH, crash::LOG_PATH];
+
+/// Returns true if the given file is a reserved log file
+pub fn is_reserved(file: &Path, location: Location) -> bool {
+    location == Location::Internal && RESERVED_FILES.contains(&file)
+}
+
+/// A log file with at most `N` bytes including the header
+pub struct LogFile<const N: usize> {
+    path: &'static Path,
+    /// The name used in error messages
+    name: &'static str,
+}
+
+impl<const N: usize> LogFile<N> {
+    pub const fn new(path: &'static Path, name: &'static str) -> Self {
+        Self { path, name }
+    }
+
+    pub fn read(&self, fs: &dyn DynFilesystem) -> Option<Vec<u8, N>> {
+        if !fs.exists(self.path) {
+            return None;
+        }
+        fs.read(self.path)
+            .map_err(|_err| error_now!("Failed to read {}: {_err:?}", self.name))
+            .ok()
+    }
+
+    pub fn write(&self, fs: &dyn DynFilesystem, data: &[u8]) -> bool {
+        fs.write(self.path, data)
+            .map_err(|_err| error_now!("Failed to write {}: {_err:?}", self.name))
+            .is_ok()
+    }
+
+    /// Appends data to the end of the existing file
+    pub fn append(&self, fs: &dyn DynFilesystem, data: &[u8]) -> bool {
+        fs.write_chunk(self.path, data, OpenSeekFrom::End(0))
+            .map_err(|_err| error_now!("Failed to append to {}: {_err:?}", self.name))
+            .is_ok()
+    }
+
+    /// Returns the counter stored in the header, or zero if the file does not exist
+    pub fn counter(&self, fs: &dyn DynFilesystem) -> u32 {
+        self.read(fs).map(|data| counter(&data)).unwrap_or_default()
+    }
+
+    /// Removes all entries but keeps the counter
+    pub fn clear(&self, fs: &dyn DynFilesystem) -> bool {
+        let Some(data) = self.read(fs) else {
+            return true;
+        };
+        self.write(fs, &data[..HEADER_LEN.min(data.len())])
+    }
+}
+
+/// Returns the counter stored in the header of the file content
+pub fn counter(data: &[u8]) -> u32 {
+    data.get(..HEADER_LEN)
+        .and_then(|header| header.try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or_default()
+}
@@ -1,9 +1,15 @@
 #![allow(unused)]
 
 use admin_app::migrations::Migrator;
-use littlefs2_core::path;
+use heapless::Vec;
+use littlefs2_core::{path, Path};
+use serde::Serialize;
 
 pub(crate) const MIGRATION_VERSION_SPACE_EFFICIENCY: u32 = 1;
+/// Enables the SE050 backend for secrets-app if it is not used yet, see `Apps::admin_app`
+pub(crate) const MIGRATION_VERSION_SECRETS_SE050: u32 = 2;
+/// Enables the SE050 backend for webcrypt if it is not used yet, see `Apps::admin_app`
+pub(crate) const MIGRATION_VERSION_WEBCRYPT_SE050: u32 = 3;
 
 #[cfg(feature = "backend-auth")]
 pub(crate) const TRUSSED_AUTH_FS_LAYOUT: trussed_auth_backend::FilesystemLayout =
@@ -12,29 +18,176 @@ pub(crate) const TRUSSED_AUTH_FS_LAYOUT: trussed_auth_backend::FilesystemLayout
 pub(crate) const SE050_BACKEND_FS_LAYOUT: trussed_se050_backend::FilesystemLayout =
     trussed_se050_backend::FilesystemLayout::V1;
 
-pub(crate) const MIGRATORS: &[Migrator] = &[
+/// A migrator together with the clients whose data it modifies
+///
+/// If a migrator fails, only the apps using these clients are disabled.
+pub(crate) struct Migration {
+    pub migrator: Migrator,
+    pub clients: &'static [&'static Path],
+}
+
+pub(crate) const MIGRATIONS: &[Migration] = &[
     // We first migrate the SE050 since this migration deletes data to make sure that the other
     // migrations succeed even on low block availability
     #[cfg(feature = "se050")]
-    Migrator {
-        migrate: |ifs, _efs| {
-            trussed_se050_backend::migrate::migrate_remove_all_dat(ifs, &[path!("/opcard")])
+    Migration {
+        migrator: Migrator {
+            migrate: |ifs, _efs| {
+                trussed_se050_backend::migrate::migrate_remove_all_dat(ifs, &[path!("/opcard")])
+            },
+            version: MIGRATION_VERSION_SPACE_EFFICIENCY,
         },
-        version: MIGRATION_VERSION_SPACE_EFFICIENCY,
+        clients: &[path!("opcard")],
     },
     #[cfg(feature = "backend-auth")]
-    Migrator {
-        migrate: |ifs, _efs| {
-            trussed_auth_backend::migrate::migrate_remove_dat(
-                ifs,
-                &[
-                    path!("opcard"),
-                    path!("webcrypt"),
-                    path!("secrets"),
-                    path!("piv"),
-                ],
-            )
+    Migration {
+        migrator: Migrator {
+            migrate: |ifs, _efs| {
+                trussed_auth_backend::migrate::migrate_remove_dat(
+                    ifs,
+                    &[
+                        path!("opcard"),
+                        path!("webcrypt"),
+                        path!("secrets"),
+                        path!("piv"),
+                    ],
+                )
+            },
+            version: MIGRATION_VERSION_SPACE_EFFICIENCY,
+        },
+        clients: &[
+            path!("opcard"),
+            path!("webcrypt"),
+            path!("secrets"),
+            path!("piv"),
+        ],
+    },
+    // The migration is performed by Apps::admin_app before the migrators are run because it
+    // needs to change the config.  This entry only makes sure that it is performed once.
+    #[cfg(all(feature = "secrets-app", feature = "se050"))]
+    Migration {
+        migrator: Migrator {
+            migrate: |_ifs, _efs| Ok(()),
+            version: MIGRATION_VERSION_SECRETS_SE050,
+        },
+        clients: &[path!("secrets")],
+    },
+    // Same as above, for webcrypt
+    #[cfg(all(feature = "webcrypt", feature = "se050"))]
+    Migration {
+        migrator: Migrator {
+            migrate: |_ifs, _efs| Ok(()),
+            version: MIGRATION_VERSION_WEBCRYPT_SE050,
         },
-        version: MIGRATION_VERSION_SPACE_EFFICIENCY,
+        clients: &[path!("webcrypt")],
     },
 ];
+
+/// Maximum number of migrators that are included in a [`MigrationReport`][]
+const MAX_MIGRATIONS: usize = 8;
+
+const _: () = assert!(MIGRATIONS.len() <= MAX_MIGRATIONS);
+
+/// The migrators of [`MIGRATIONS`][], as expected by the admin app
+pub(crate) const MIGRATORS: &[Migrator] = &migrators::<{ MIGRATIONS.len() }>(MIGRATIONS);
+
+const fn migrators<const N: usize>(migrations: &[Migration]) -> [Migrator; N] {
+    const NOOP: Migrator = Migrator {
+        migrate: |_ifs, _efs| Ok(()),
+        version: 0,
+    };
+    let mut migrators = [NOOP; N];
+    let mut i = 0;
+    while i < N {
+        migrators[i] = Migrator {
+            migrate: migrations[i].migrator.migrate,
+            version: migrations[i].migrator.version,
+        };
+        i += 1;
+    }
+    migrators
+}
+
+/// The result of a migrator that was run during boot
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct MigrationResult {
+    /// The index of the migrator in the list of migrators
+    #[serde(rename = "i")]
+    pub index: u8,
+    #[serde(rename = "v")]
+    pub version: u32,
+    /// The littlefs error code if the migrator failed
+    #[serde(rename = "e")]
+    pub error: Option<i32>,
+    #[serde(skip)]
+    clients: &'static [&'static Path],
+}
+
+/// The results of the migrators that were run during boot, returned by an admin-app command
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MigrationReport {
+    /// The filesystem version before the migration
+    #[serde(rename = "f")]
+    pub from_version: u32,
+    /// The filesystem version after a successful migration
+    #[serde(rename = "t")]
+    pub to_version: u32,
+    #[serde(rename = "r")]
+    pub results: Vec<MigrationResult, MAX_MIGRATIONS>,
+}
+
+impl MigrationReport {
+    pub(crate) fn new(from_version: u32, to_version: u32) -> Self {
+        Self {
+            from_version,
+            to_version,
+            results: Vec::new(),
+        }
+    }
+
+    /// Records the result of the migrator with the given index in `migrations`
+    ///
+    /// This is called by `admin_app::App::migrate` for every migrator that it runs.  Other than
+    /// in earlier admin-app versions, the remaining migrators are still run if one of them fails
+    /// so that only the clients modified by the failed migrator are affected, and the new
+    /// filesystem version is only stored if all migrators succeeded.
+    pub(crate) fn record(
+        &mut self,
+        migrations: &[Migration],
+        index: usize,
+        result: Result<(), littlefs2_core::Error>,
+    ) {
+        let Some(migration) = migrations.get(index) else {
+            return;
+        };
+        let version = migration.migrator.version;
+        let error = result
+            .map_err(|err| {
+                error_now!("Migrator {index} to version {version} failed: {err:?}");
+                err.code()
+            })
+            .err();
+        let result = MigrationResult {
+            index: index as u8,
+            version,
+            error,
+            clients: migration.clients,
+        };
+        self.results.push(result).ok();
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(|result| result.error.is_none())
+    }
+
+    /// Returns true if the data of the given client was modified by a failed migrator
+    pub fn is_client_affected(&self, client: &Path) -> bool {
+        self.results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .any(|result| result.clients.iter().any(|c| *c == client))
+    }
+}
+
+#[cfg(test)]
+mod tests;
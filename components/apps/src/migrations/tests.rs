@@ -0,0 +1,542 @@
+//! Host tests for the migrators
+//!
+//! The tests build the internal and external filesystem from fixture trees on the RAM store of
+//! the trussed virt platform, run the migrations with `admin_app::App::migrate` like
+//! `Apps::admin_app` and compare the resulting layout with the expected tree and the filesystem
+//! version stored in the admin-app config.  The fixtures for trussed-auth follow the V0 and V1
+//! filesystem layouts of trussed-auth-backend.
+
+use core::fmt::Write as _;
+
+use admin_app::{migrations::Migrator, Config as _, Reboot};
+use littlefs2_core::{path, Error, Path, PathBuf};
+use trussed::{
+    backend::BackendId,
+    store::filestore::ClientFilestore,
+    types::Location,
+    virt::{self, Ram, StoreProvider},
+    Platform as _,
+};
+
+use super::{migrators, Migration, MigrationReport, MIGRATIONS, MIGRATORS};
+use crate::{
+    usage::{block_size, filesystem},
+    AdminStatus, Backend, Config, Dispatch, InitStatus, LargeBlobsStatus, ResetReason, Variant,
+    ADMIN_APP_CLIENT_ID,
+};
+
+type VirtStore = <Ram as StoreProvider>::Store;
+
+/// A file in a fixture tree
+type FixtureFile = (Location, &'static Path, &'static [u8]);
+
+#[cfg(feature = "backend-auth")]
+const AUTH_V0: &[FixtureFile] = &[
+    (
+        Location::Internal,
+        path!("/backend-auth/dat/salt"),
+        b"global salt",
+    ),
+    (
+        Location::Internal,
+        path!("/opcard/dat/backend-auth/pin.00"),
+        b"opcard user pin",
+    ),
+    (
+        Location::Internal,
+        path!("/opcard/dat/backend-auth/pin.01"),
+        b"opcard admin pin",
+    ),
+    (
+        Location::Internal,
+        path!("/secrets/dat/backend-auth/pin.00"),
+        b"secrets pin",
+    ),
+    (
+        Location::Internal,
+        path!("/piv/dat/backend-auth/pin.00"),
+        b"piv pin",
+    ),
+];
+
+#[cfg(feature = "backend-auth")]
+const AUTH_V1: &[FixtureFile] = &[
+    (
+        Location::Internal,
+        path!("/backend-auth/salt"),
+        b"global salt",
+    ),
+    (
+        Location::Internal,
+        path!("/opcard/backend-auth/pin.00"),
+        b"opcard user pin",
+    ),
+    (
+        Location::Internal,
+        path!("/opcard/backend-auth/pin.01"),
+        b"opcard admin pin",
+    ),
+    (
+        Location::Internal,
+        path!("/secrets/backend-auth/pin.00"),
+        b"secrets pin",
+    ),
+    (
+        Location::Internal,
+        path!("/piv/backend-auth/pin.00"),
+        b"piv pin",
+    ),
+];
+
+/// Files that must not be touched by the migrations
+const UNRELATED: &[FixtureFile] = &[
+    (
+        Location::Internal,
+        path!("/fido/sec/00"),
+        b"attestation key",
+    ),
+    (
+        Location::Internal,
+        path!("/fido/x5c/00"),
+        b"attestation cert",
+    ),
+    (Location::External, path!("/fido/dat/rk/00"), b"credential"),
+    (
+        Location::External,
+        path!("/opcard/dat/state"),
+        b"opcard state",
+    ),
+];
+
+fn latest_version() -> u32 {
+    MIGRATIONS
+        .iter()
+        .map(|m| m.migrator.version)
+        .max()
+        .unwrap_or_default()
+}
+
+/// Removes the files of the clients modified by the SE050 migrator from a fixture
+///
+/// The SE050 migrator removes the opcard data, so the other migrators do not see it.  It is
+/// covered by `migrate_se050`.
+fn expected(files: &[FixtureFile]) -> heapless::Vec<FixtureFile, 16> {
+    files
+        .iter()
+        .filter(|(_, path, _)| !cfg!(feature = "se050") || !path.as_str().starts_with("/opcard/"))
+        .copied()
+        .collect()
+}
+
+fn write_fixture(store: &VirtStore, files: &[FixtureFile]) {
+    for (location, path, data) in files {
+        let fs = filesystem(store, *location);
+        if let Some(parent) = path.parent() {
+            fs.create_dir_all(&parent).unwrap();
+        }
+        fs.write(path, data).unwrap();
+    }
+}
+
+fn assert_layout(store: &VirtStore, present: &[FixtureFile], absent: &[FixtureFile]) {
+    for (location, path, data) in present {
+        let fs = filesystem(store, *location);
+        let content: heapless::Vec<u8, 1024> = fs
+            .read(path)
+            .unwrap_or_else(|err| panic!("{path} missing on {location:?}: {err:?}"));
+        assert_eq!(&content, data, "{path} on {location:?}");
+    }
+    for (location, path, _) in absent {
+        let fs = filesystem(store, *location);
+        assert!(!fs.exists(path), "{path} still present on {location:?}");
+    }
+}
+
+/// Fills the filesystem with dummy files until at most `blocks` blocks are available
+fn fill(store: &VirtStore, location: Location, blocks: usize) {
+    let fs = filesystem(store, location);
+    let chunk = [0xff; 1024];
+    let chunk = &chunk[..block_size(fs).min(chunk.len())];
+    fs.create_dir_all(path!("/filler")).unwrap();
+    let mut i = 0;
+    while fs.available_blocks().unwrap() > blocks {
+        let mut name = heapless::String::<16>::new();
+        write!(name, "/filler/{i}").unwrap();
+        let name = PathBuf::try_from(name.as_str()).unwrap();
+        if fs.write(&name, chunk).is_err() {
+            break;
+        }
+        i += 1;
+    }
+    assert!(fs.available_blocks().unwrap() <= blocks);
+}
+
+const BACKENDS: &[BackendId<Backend>] = &[BackendId::Core];
+
+/// Placeholders for the SE050 driver, the migrators do not access the SE050
+#[cfg(feature = "se050")]
+mod se050 {
+    use embedded_hal::blocking::{
+        delay::DelayUs,
+        i2c::{Read, Write, WriteRead},
+    };
+    use se05x::t1::I2CErrorNack;
+
+    #[derive(Debug)]
+    pub struct Nack;
+
+    impl I2CErrorNack for Nack {
+        fn is_address_nack(&self) -> bool {
+            true
+        }
+
+        fn is_data_nack(&self) -> bool {
+            false
+        }
+    }
+
+    pub struct NoTwi;
+
+    impl Read<u8> for NoTwi {
+        type Error = Nack;
+
+        fn read(&mut self, _address: u8, _buffer: &mut [u8]) -> Result<(), Nack> {
+            Err(Nack)
+        }
+    }
+
+    impl Write<u8> for NoTwi {
+        type Error = Nack;
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), Nack> {
+            Err(Nack)
+        }
+    }
+
+    impl WriteRead<u8> for NoTwi {
+        type Error = Nack;
+
+        fn write_read(
+            &mut self,
+            _address: u8,
+            _bytes: &[u8],
+            _buffer: &mut [u8],
+        ) -> Result<(), Nack> {
+            Err(Nack)
+        }
+    }
+
+    pub struct NoDelay;
+
+    impl DelayUs<u32> for NoDelay {
+        fn delay_us(&mut self, _us: u32) {}
+    }
+}
+
+struct TestReboot;
+
+impl Reboot for TestReboot {
+    fn reboot() -> ! {
+        unimplemented!();
+    }
+
+    fn reboot_to_firmware_update() -> ! {
+        unimplemented!();
+    }
+
+    fn reboot_to_firmware_update_destructive() -> ! {
+        unimplemented!();
+    }
+
+    fn locked() -> bool {
+        false
+    }
+}
+
+fn status(store: VirtStore) -> AdminStatus<VirtStore> {
+    AdminStatus {
+        store,
+        init_status: InitStatus::empty(),
+        ifs_blocks: u8::MAX,
+        efs_blocks: u16::MAX,
+        variant: Variant::Usbip,
+        migrations: MigrationReport::default(),
+        migration_version: 0,
+        se050_present: false,
+        se050_version: None,
+        nfc_powered: false,
+        reset_reason: ResetReason::Unknown,
+        locked: false,
+        large_blobs: LargeBlobsStatus::Disabled,
+        preserve_policy: crate::DEFAULT_PRESERVE_POLICY,
+    }
+}
+
+/// Reads the filesystem version from the config stored by admin-app
+fn stored_version(store: &VirtStore) -> u32 {
+    let config: heapless::Vec<u8, 1024> = filesystem(store, Location::Internal)
+        .read(path!("/admin/dat/config"))
+        .unwrap();
+    let config: Config = cbor_smol::cbor_deserialize(&config).unwrap();
+    config.fs_version
+}
+
+/// Builds the filesystem with `setup`, then runs `migrations` once for every entry of
+/// `fs_versions`, starting at that version.  `check` is called with the filesystem version
+/// stored by admin-app and the report of the last run.
+fn run_migrations(
+    migrations: &'static [Migration],
+    migrators: &'static [Migrator],
+    setup: impl FnOnce(&VirtStore),
+    fs_versions: &[u32],
+    check: impl FnOnce(&VirtStore, u32, MigrationReport),
+) {
+    let to_version = migrations
+        .iter()
+        .map(|m| m.migrator.version)
+        .max()
+        .unwrap_or_default();
+    virt::with_platform(Ram::default(), |platform| {
+        let store = platform.store();
+        setup(&store);
+        #[cfg(feature = "se050")]
+        let dispatch = Dispatch::<se050::NoTwi, se050::NoDelay>::new(crate::AUTH_LOCATION, None);
+        #[cfg(not(feature = "se050"))]
+        let dispatch = Dispatch::<(), ()>::new(
+            #[cfg(feature = "backend-auth")]
+            crate::AUTH_LOCATION,
+        );
+        platform.run_client_with_backends("admin", dispatch, BACKENDS, |client| {
+            let mut filestore = ClientFilestore::new(ADMIN_APP_CLIENT_ID.into(), store);
+            let mut app = admin_app::App::<_, TestReboot, _, Config>::with_default_config(
+                client,
+                [0; 16],
+                0,
+                "",
+                status(store),
+                migrators,
+            );
+            let mut report = MigrationReport::default();
+            for fs_version in fs_versions {
+                // The version stored by a previous firmware or boot
+                app.config_mut().set_migration_version(*fs_version);
+                app.save_config_filestore(&mut filestore).unwrap();
+
+                report = MigrationReport::new(*fs_version, to_version);
+                app.migrate(to_version, store, &mut filestore, |index, result| {
+                    report.record(migrations, index, result)
+                })
+                .ok();
+            }
+            check(&store, stored_version(&store), report);
+        });
+    })
+}
+
+/// Runs [`MIGRATIONS`][] like [`run_migrations`][] and calls `check` with the stored version
+/// and the success of the last run
+fn run(
+    setup: impl FnOnce(&VirtStore),
+    fs_versions: &[u32],
+    check: impl FnOnce(&VirtStore, u32, bool),
+) {
+    run_migrations(
+        MIGRATIONS,
+        MIGRATORS,
+        setup,
+        fs_versions,
+        |store, version, report| check(store, version, report.succeeded()),
+    );
+}
+
+#[test]
+fn migrate_empty() {
+    run(
+        |_| {},
+        &[0],
+        |_, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+        },
+    );
+}
+
+#[test]
+fn migrate_unrelated() {
+    run(
+        |store| write_fixture(store, UNRELATED),
+        &[0],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            assert_layout(store, UNRELATED, &[]);
+        },
+    );
+}
+
+#[cfg(feature = "backend-auth")]
+#[test]
+fn migrate_auth_v0() {
+    run(
+        |store| {
+            write_fixture(store, AUTH_V0);
+            write_fixture(store, UNRELATED);
+        },
+        &[0],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            assert_layout(store, &expected(AUTH_V1), AUTH_V0);
+            assert_layout(store, UNRELATED, &[]);
+        },
+    );
+}
+
+#[cfg(feature = "backend-auth")]
+#[test]
+fn migrate_auth_up_to_date() {
+    run(
+        |store| write_fixture(store, AUTH_V0),
+        &[latest_version()],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            // The migrators are not run again
+            assert_layout(store, AUTH_V0, AUTH_V1);
+        },
+    );
+}
+
+/// The device was reset after the migrators ran but before the new version was saved
+#[cfg(feature = "backend-auth")]
+#[test]
+fn migrate_auth_repeated() {
+    run(
+        |store| write_fixture(store, AUTH_V0),
+        &[0, 0],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            assert_layout(store, &expected(AUTH_V1), AUTH_V0);
+        },
+    );
+}
+
+/// The device was reset while the auth migrator was running, so only some clients are migrated
+#[cfg(feature = "backend-auth")]
+#[test]
+fn migrate_auth_partial() {
+    run(
+        |store| {
+            write_fixture(store, &AUTH_V1[..3]);
+            write_fixture(store, &AUTH_V0[3..]);
+        },
+        &[0],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            assert_layout(store, &expected(AUTH_V1), AUTH_V0);
+        },
+    );
+}
+
+#[cfg(feature = "backend-auth")]
+#[test]
+fn migrate_auth_low_blocks() {
+    run(
+        |store| {
+            write_fixture(store, AUTH_V0);
+            fill(store, Location::Internal, 4);
+        },
+        &[0],
+        |store, version, success| {
+            assert!(success);
+            assert_eq!(version, latest_version());
+            assert_layout(store, &expected(AUTH_V1), AUTH_V0);
+        },
+    );
+}
+
+#[cfg(feature = "se050")]
+#[test]
+fn migrate_se050() {
+    run_migrations(
+        MIGRATIONS,
+        MIGRATORS,
+        |store| write_fixture(store, UNRELATED),
+        &[0],
+        |store, version, report| {
+            assert!(report.succeeded());
+            assert_eq!(version, latest_version());
+            // The SE050 migrator is run first and only affects opcard
+            let result = &report.results[0];
+            assert_eq!(result.index, 0);
+            assert_eq!(result.version, super::MIGRATION_VERSION_SPACE_EFFICIENCY);
+            assert_eq!(result.error, None);
+            assert_eq!(result.clients, [path!("opcard")]);
+            assert_layout(store, UNRELATED, &[]);
+        },
+    );
+}
+
+#[test]
+fn migrate_failure_affects_clients() {
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            migrator: Migrator {
+                migrate: |_ifs, _efs| Ok(()),
+                version: 1,
+            },
+            clients: &[path!("fido")],
+        },
+        Migration {
+            migrator: Migrator {
+                migrate: |_ifs, _efs| Err(Error::IO),
+                version: 2,
+            },
+            clients: &[path!("opcard"), path!("piv")],
+        },
+        Migration {
+            migrator: Migrator {
+                migrate: |_ifs, _efs| Ok(()),
+                version: 3,
+            },
+            clients: &[path!("secrets")],
+        },
+    ];
+    const MIGRATORS: &[Migrator] = &migrators::<{ MIGRATIONS.len() }>(MIGRATIONS);
+
+    run_migrations(
+        MIGRATIONS,
+        MIGRATORS,
+        |_| {},
+        &[0],
+        |_, version, report| {
+            assert!(!report.succeeded());
+            // The version is not updated so that the migrators are run again on the next boot
+            assert_eq!(version, 0);
+            // The migrators after the failed one are still run
+            assert_eq!(report.results.len(), 3);
+            assert_eq!(report.results[1].version, 2);
+            assert_eq!(report.results[1].error, Some(Error::IO.code()));
+            assert!(report.is_client_affected(path!("opcard")));
+            assert!(report.is_client_affected(path!("piv")));
+            assert!(!report.is_client_affected(path!("fido")));
+            assert!(!report.is_client_affected(path!("secrets")));
+        },
+    );
+
+    // Migrators that are not newer than the filesystem version are skipped
+    run_migrations(
+        MIGRATIONS,
+        MIGRATORS,
+        |_| {},
+        &[2],
+        |_, version, report| {
+            assert!(report.succeeded());
+            assert_eq!(version, 3);
+            assert_eq!(report.results.len(), 1);
+            assert_eq!(report.results[0].index, 2);
+        },
+    );
+}
@@ -0,0 +1,225 @@
+//! Files that are preserved when a client or the device is factory reset.
+//!
+//! The staging backend asks for every file whether it should be kept during a factory reset.
+//! The answer is taken from the table of rules provided by the runner with
+//! [`Runner::PRESERVE_POLICY`][crate::Runner::PRESERVE_POLICY], matching files with the pattern
+//! `/<client>/<dir>/<name>`.  Runners that write additional attestation artefacts or provisioned
+//! files extend [`DEFAULT_POLICY`][] with their own rules.  The reserved log files in the root directory of the
+//! internal filesystem are always preserved, see [`log_file::is_reserved`][].
+
+use heapless::Vec;
+use littlefs2_core::{path, DynFilesystem, Path, PathBuf};
+use serde::Serialize;
+use trussed::{store::Store, types::Location};
+
+use crate::{
+    log_file,
+    usage::{filesystem, location_id},
+};
+
+/// Maximum number of files returned by [`PreservedFiles::collect`][]
+const MAX_REPORTED_FILES: usize = 32;
+
+/// A rule that matches the files that are preserved by a factory reset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PreserveRule {
+    /// The client directory, or `None` to match all clients
+    pub client: Option<&'static Path>,
+    /// The directory within the client directory
+    pub dir: &'static Path,
+    /// The maximum length of the file name
+    pub max_name_len: usize,
+    /// The filesystem, or `None` to match all filesystems
+    pub location: Option<Location>,
+}
+
+impl PreserveRule {
+    /// Preserves the files with a name of at most two bytes, i. e. with an ID that is
+    /// representable by a `u8`, in the given directory of all clients
+    const fn special_objects(dir: &'static Path) -> Self {
+        Self {
+            client: None,
+            dir,
+            max_name_len: 2,
+            location: None,
+        }
+    }
+
+    pub fn matches(&self, client: &Path, dir: &Path, name: &Path, location: Location) -> bool {
+        self.client.map(|c| c == client).unwrap_or(true)
+            && self.dir == dir
+            && name.as_ref().len() <= self.max_name_len
+            && self.location.map(|l| l == location).unwrap_or(true)
+    }
+}
+
+/// The default preservation policy
+pub const DEFAULT_POLICY: &[PreserveRule] = &[
+    // Attestation keys and certificates and the counters written by the provisioner
+    PreserveRule::special_objects(path!("x5c")),
+    PreserveRule::special_objects(path!("ctr")),
+    PreserveRule::special_objects(path!("sec")),
+    PreserveRule::special_objects(path!("pub")),
+];
+
+/// Returns true if the given file is a reserved log file or preserved by one of the rules of the
+/// policy
+pub fn should_preserve_file(policy: &[PreserveRule], file: &Path, location: Location) -> bool {
+    if log_file::is_reserved(file, location) {
+        return true;
+    }
+    let mut components = file.iter();
+    if components.next().as_deref() != Some(path!("/")) {
+        return false;
+    }
+    let (Some(client), Some(dir), Some(name), None) = (
+        components.next(),
+        components.next(),
+        components.next(),
+        components.next(),
+    ) else {
+        return false;
+    };
+    policy
+        .iter()
+        .any(|rule| rule.matches(&client, &dir, &name, location))
+}
+
+/// A file that would be preserved by a factory reset
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PreservedFile {
+    /// The filesystem, see [`location_id`][]
+    #[serde(rename = "l")]
+    pub location: u8,
+    #[serde(rename = "p")]
+    pub path: PathBuf,
+}
+
+/// The files that would be preserved by a factory reset, returned by an admin-app command
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PreservedFiles {
+    #[serde(rename = "f")]
+    pub files: Vec<PreservedFile, MAX_REPORTED_FILES>,
+    /// Set if there are more files than fit into the report
+    #[serde(rename = "t")]
+    pub truncated: bool,
+}
+
+impl PreservedFiles {
+    /// Collects the preserved files of the given client, or of all clients if `client` is `None`
+    pub fn collect<S: Store>(store: &S, policy: &[PreserveRule], client: Option<&Path>) -> Self {
+        let mut report = Self::default();
+        for location in [Location::Internal, Location::External] {
+            let fs = filesystem(store, location);
+            let root = path!("/");
+            let result = fs.read_dir_and_then(root, |clients| {
+                for entry in clients.skip(2) {
+                    let entry = entry?;
+                    if !entry.metadata().is_dir() {
+                        continue;
+                    }
+                    if client.is_some_and(|client| client != entry.file_name()) {
+                        continue;
+                    }
+                    report.collect_client(fs, policy, entry.path(), location)?;
+                }
+                Ok(())
+            });
+            if let Err(_err) = result {
+                warn_now!("Failed to list preserved files on {location:?}: {_err:?}");
+            }
+        }
+        report
+    }
+
+    fn collect_client(
+        &mut self,
+        fs: &dyn DynFilesystem,
+        policy: &[PreserveRule],
+        client_dir: &Path,
+        location: Location,
+    ) -> littlefs2_core::Result<()> {
+        fs.read_dir_and_then(client_dir, |dirs| {
+            for dir in dirs.skip(2) {
+                let dir = dir?;
+                if !dir.metadata().is_dir() {
+                    continue;
+                }
+                fs.read_dir_and_then(dir.path(), |files| {
+                    for file in files.skip(2) {
+                        let file = file?;
+                        if file.metadata().is_dir()
+                            || !should_preserve_file(policy, file.path(), location)
+                        {
+                            continue;
+                        }
+                        let file = PreservedFile {
+                            location: location_id(location),
+                            path: file.path().into(),
+                        };
+                        if self.files.push(file).is_err() {
+                            self.truncated = true;
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn should_preserve_file(file: &Path) -> bool {
+        super::should_preserve_file(DEFAULT_POLICY, file, Location::Internal)
+    }
+
+    #[test]
+    fn file_preserve() {
+        assert!(should_preserve_file(path!("/fido/sec/00")));
+        assert!(should_preserve_file(path!("/fido/x5c/00")));
+        assert!(should_preserve_file(path!("/fido/sec/01")));
+        assert!(should_preserve_file(path!("/fido/x5c/01")));
+        assert!(should_preserve_file(path!("/attn/pub/00")));
+        assert!(should_preserve_file(path!("/attn/sec/01")));
+        assert!(should_preserve_file(path!("/attn/sec/02")));
+        assert!(should_preserve_file(path!("/attn/sec/03")));
+        assert!(should_preserve_file(path!("/attn/x5c/01")));
+        assert!(should_preserve_file(path!("/attn/x5c/02")));
+        assert!(should_preserve_file(path!("/attn/x5c/03")));
+        assert!(!should_preserve_file(path!("/fido/dat/sec/00")));
+        assert!(!should_preserve_file(path!("/fido/sec/000")));
+        assert!(!should_preserve_file(path!("/fido/sec")));
+        assert!(!should_preserve_file(path!("fido/sec/00")));
+        assert!(should_preserve_file(path!("/audit-log")));
+        assert!(should_preserve_file(path!("/crash-log")));
+        assert!(!super::should_preserve_file(
+            DEFAULT_POLICY,
+            path!("/audit-log"),
+            Location::External
+        ));
+        assert!(!should_preserve_file(path!("/fido/audit-log")));
+    }
+
+    #[test]
+    fn file_preserve_custom_policy() {
+        const POLICY: &[PreserveRule] = &[PreserveRule {
+            client: Some(path!("attn")),
+            dir: path!("dat"),
+            max_name_len: 8,
+            location: Some(Location::External),
+        }];
+
+        let external = |file| super::should_preserve_file(POLICY, file, Location::External);
+        let internal = |file| super::should_preserve_file(POLICY, file, Location::Internal);
+
+        assert!(external(path!("/attn/dat/12345678")));
+        assert!(!external(path!("/attn/dat/123456789")));
+        assert!(!external(path!("/fido/dat/00")));
+        assert!(!external(path!("/attn/sec/00")));
+        assert!(!internal(path!("/attn/dat/00")));
+    }
+}
@@ -0,0 +1,566 @@
+//! Per-client storage quotas.
+//!
+//! All apps share the internal and external filesystem, so one app could use up the space needed
+//! by the others.  The quota backend is the first backend of the apps with a quota.  It rejects
+//! requests that would write to a filesystem where the client already uses more than its share
+//! and passes all other requests to the next backend.
+//!
+//! Walking the client directory for every write would be too slow, so the usage is cached in the
+//! [`QuotaContext`][] of the client and only updated with the size of the writes.  The cached
+//! usage is an upper bound because removed files are not tracked.  If a write would exceed the
+//! quota according to the cached usage, the directory is walked again before rejecting it.
+
+use heapless::Vec;
+use littlefs2_core::{path, DynFilesystem, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use trussed::{
+    api::{request, Request},
+    error::Error as TrussedError,
+    store::Store,
+    types::Location,
+};
+use trussed_chunked::ChunkedRequest;
+
+use crate::usage::{self, block_size, blocks, filesystem, Usage};
+
+/// The maximum number of quotas, see [`Dispatch::set_quotas`][crate::Dispatch::set_quotas]
+pub const MAX_QUOTAS: usize = 10;
+
+const _: () = assert!(Quota::DEFAULT_SHARES.len() * LOCATIONS.len() <= MAX_QUOTAS);
+const _: () = assert!(Quota::total_default_share() <= 100);
+
+/// The error returned for requests that would exceed the quota
+///
+/// trussed reports a full filesystem as a failed write, so an exceeded quota is handled by the
+/// apps like a full filesystem.
+pub const QUOTA_EXCEEDED: TrussedError = TrussedError::FilesystemWriteFailure;
+
+/// The filesystems with a quota
+const LOCATIONS: [Location; 2] = [Location::Internal, Location::External];
+
+/// The maximum share of a filesystem that a client may use
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub client: &'static Path,
+    pub location: Location,
+    /// Maximum share of the filesystem blocks in percent
+    pub max_percent: u8,
+}
+
+impl Quota {
+    /// The default share of the internal and of the external filesystem in percent
+    ///
+    /// The shares add up to less than 100 percent so that the clients without a quota, e. g.
+    /// admin-app, can still write their files.
+    const DEFAULT_SHARES: &'static [(&'static Path, u8)] = &[
+        (path!("fido"), 35),
+        (path!("opcard"), 15),
+        (path!("piv"), 10),
+        (path!("secrets"), 20),
+        (path!("webcrypt"), 10),
+    ];
+
+    const fn total_default_share() -> usize {
+        let mut total = 0;
+        let mut i = 0;
+        while i < Self::DEFAULT_SHARES.len() {
+            total += Self::DEFAULT_SHARES[i].1 as usize;
+            i += 1;
+        }
+        total
+    }
+
+    /// Returns the default quotas for all clients and filesystems
+    pub fn defaults() -> Vec<Self, MAX_QUOTAS> {
+        QuotaConfig::default().quotas()
+    }
+
+    pub(crate) fn max_blocks(&self, fs: &dyn DynFilesystem) -> usize {
+        fs.total_blocks() * usize::from(self.max_percent) / 100
+    }
+}
+
+/// The `quota` section of the config that overrides the default quotas
+///
+/// The values are indices into [`QuotaConfig::VARIANTS`][], i. e. the maximum share of the
+/// filesystems in steps of ten percent, or zero to use the default quota of the client.  To keep
+/// the config small, they are serialized as four bits per client in a single integer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(from = "u32", into = "u32")]
+pub struct QuotaConfig {
+    pub(crate) fido: u8,
+    pub(crate) opcard: u8,
+    pub(crate) piv: u8,
+    pub(crate) secrets: u8,
+    pub(crate) webcrypt: u8,
+}
+
+impl From<u32> for QuotaConfig {
+    fn from(value: u32) -> Self {
+        let nibble = |i: u32| ((value >> (4 * i)) & 0xf) as u8;
+        Self {
+            fido: nibble(0),
+            opcard: nibble(1),
+            piv: nibble(2),
+            secrets: nibble(3),
+            webcrypt: nibble(4),
+        }
+    }
+}
+
+impl From<QuotaConfig> for u32 {
+    fn from(config: QuotaConfig) -> Self {
+        [
+            config.fido,
+            config.opcard,
+            config.piv,
+            config.secrets,
+            config.webcrypt,
+        ]
+        .into_iter()
+        .enumerate()
+        .fold(0, |acc, (i, value)| {
+            acc | (u32::from(value & 0xf) << (4 * i))
+        })
+    }
+}
+
+impl QuotaConfig {
+    pub(crate) const VARIANTS: &'static [&'static str] = &[
+        "default", "10", "20", "30", "40", "50", "60", "70", "80", "90", "100",
+    ];
+
+    pub(crate) fn field(&mut self, key: &str) -> Option<&mut u8> {
+        match key {
+            #[cfg(feature = "fido-authenticator")]
+            "fido" => Some(&mut self.fido),
+            #[cfg(feature = "opcard")]
+            "opcard" => Some(&mut self.opcard),
+            #[cfg(feature = "piv-authenticator")]
+            "piv" => Some(&mut self.piv),
+            #[cfg(feature = "secrets-app")]
+            "secrets" => Some(&mut self.secrets),
+            #[cfg(feature = "webcrypt")]
+            "webcrypt" => Some(&mut self.webcrypt),
+            _ => None,
+        }
+    }
+
+    /// Returns the configured share in percent, or `None` to use the default
+    fn max_percent(&self, client: &Path) -> Option<u8> {
+        let value = match client.as_str() {
+            "fido" => self.fido,
+            "opcard" => self.opcard,
+            "piv" => self.piv,
+            "secrets" => self.secrets,
+            "webcrypt" => self.webcrypt,
+            _ => 0,
+        };
+        // invalid values are ignored like the default value
+        Some(value)
+            .filter(|value| (1..Self::VARIANTS.len()).contains(&usize::from(*value)))
+            .map(|value| value * 10)
+    }
+
+    /// Returns the default quotas with the configured overrides
+    pub fn quotas(&self) -> Vec<Quota, MAX_QUOTAS> {
+        LOCATIONS
+            .into_iter()
+            .flat_map(|location| {
+                Quota::DEFAULT_SHARES
+                    .iter()
+                    .map(move |(client, share)| Quota {
+                        client,
+                        location,
+                        max_percent: self.max_percent(client).unwrap_or(*share),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Per-client state of the quota backend
+#[derive(Default)]
+pub struct QuotaContext {
+    /// The location of the current chunked write
+    chunked_write: Option<Location>,
+    /// Upper bound for the blocks used by the client, indexed by [`location_index`][]
+    usage: [Option<usize>; 3],
+}
+
+/// A request that writes to the filesystem
+struct Write<'a> {
+    location: Location,
+    /// The number of bytes written, or `None` if it is not known, e. g. for generated keys
+    len: Option<usize>,
+    /// The file in the `dat` directory of the client that is replaced by the write
+    replaces: Option<&'a Path>,
+}
+
+impl Write<'_> {
+    fn new(location: Location, len: Option<usize>) -> Self {
+        Self {
+            location,
+            len,
+            replaces: None,
+        }
+    }
+}
+
+/// Checks a core request and returns an error if it would exceed the quota of the client
+pub fn check_request<S: Store>(
+    quotas: &[Quota],
+    store: &S,
+    client: &Path,
+    context: &mut QuotaContext,
+    request: &Request,
+) -> Result<(), TrussedError> {
+    let write = match request {
+        Request::WriteFile(request) => Write {
+            location: request.location,
+            len: Some(request.data.len()),
+            replaces: Some(&request.path),
+        },
+        Request::WriteCertificate(request) => Write::new(request.location, Some(request.der.len())),
+        Request::CreateCounter(request) => Write::new(request.location, Some(0)),
+        Request::Agree(request) => Write::new(request.attributes.persistence, None),
+        Request::DeriveKey(request) => Write::new(request.attributes.persistence, None),
+        Request::DeserializeKey(request) => Write::new(request.attributes.persistence, None),
+        Request::GenerateKey(request) => Write::new(request.attributes.persistence, None),
+        Request::GenerateSecretKey(request) => Write::new(request.attributes.persistence, None),
+        Request::UnsafeInjectKey(request) => Write::new(request.attributes.persistence, None),
+        Request::UnwrapKey(request) => Write::new(request.attributes.persistence, None),
+        _ => return Ok(()),
+    };
+    check(quotas, store, client, context, write)
+}
+
+/// Checks a chunked extension request and returns an error if it would exceed the quota
+pub fn check_chunked_request<S: Store>(
+    quotas: &[Quota],
+    store: &S,
+    client: &Path,
+    context: &mut QuotaContext,
+    request: &request::SerdeExtension,
+) -> Result<(), TrussedError> {
+    let Ok(request) = cbor_smol::cbor_deserialize::<ChunkedRequest>(&request.request) else {
+        // Let the chunked backend handle invalid requests
+        return Ok(());
+    };
+    // The replaced file is only subtracted once when the write is started
+    let write = match &request {
+        ChunkedRequest::StartChunkedWrite(request) => {
+            context.chunked_write = Some(request.location);
+            Write {
+                location: request.location,
+                len: Some(0),
+                replaces: Some(&request.path),
+            }
+        }
+        ChunkedRequest::StartEncryptedChunkedWrite(request) => {
+            context.chunked_write = Some(request.location);
+            Write {
+                location: request.location,
+                len: Some(0),
+                replaces: Some(&request.path),
+            }
+        }
+        ChunkedRequest::WriteChunk(request) => {
+            let Some(location) = context.chunked_write else {
+                return Ok(());
+            };
+            Write::new(location, Some(request.data.len()))
+        }
+        ChunkedRequest::AbortChunkedWrite(_) => {
+            context.chunked_write = None;
+            return Ok(());
+        }
+        ChunkedRequest::AppendFile(request) => {
+            Write::new(request.location, Some(request.data.len()))
+        }
+        _ => return Ok(()),
+    };
+    check(quotas, store, client, context, write)
+}
+
+fn check<S: Store>(
+    quotas: &[Quota],
+    store: &S,
+    client: &Path,
+    context: &mut QuotaContext,
+    write: Write<'_>,
+) -> Result<(), TrussedError> {
+    let location = write.location;
+    let Some(quota) = quotas
+        .iter()
+        .find(|quota| quota.client == client && quota.location == location)
+    else {
+        return Ok(());
+    };
+    let fs = filesystem(store, location);
+    let block_size = block_size(fs);
+    let max_blocks = quota.max_blocks(fs);
+    // Every write needs at least one new block
+    let new_blocks = blocks(write.len.unwrap_or_default(), block_size).max(1);
+    let replaced_blocks = write
+        .replaces
+        .map(|path| replaced_blocks(fs, client, path, block_size))
+        .unwrap_or_default();
+    let required = |usage: usize| (usage + new_blocks).saturating_sub(replaced_blocks);
+
+    let cached = &mut context.usage[location_index(location)];
+    let mut usage = match *cached {
+        Some(usage) => usage,
+        None => client_blocks(fs, client)?,
+    };
+    if required(usage) > max_blocks && cached.is_some() {
+        // The cached usage may be too high, so check the actual usage before rejecting the write
+        usage = client_blocks(fs, client)?;
+    }
+    if required(usage) > max_blocks {
+        warn_now!(
+            "Quota exceeded for client {:?} on {:?}: {} of {} blocks",
+            client,
+            location,
+            required(usage),
+            max_blocks
+        );
+        *cached = Some(usage);
+        return Err(QUOTA_EXCEEDED);
+    }
+    // The size of keys is not known, so the usage is read again for the next write
+    *cached = write.len.map(|_| required(usage));
+    Ok(())
+}
+
+fn client_blocks(fs: &dyn DynFilesystem, client: &Path) -> Result<usize, TrussedError> {
+    Usage::of_client(fs, client)
+        .map(|usage| usage.blocks)
+        .map_err(|_| TrussedError::FilesystemReadFailure)
+}
+
+/// Returns the blocks used by the file that is replaced by a write to the given client path
+fn replaced_blocks(fs: &dyn DynFilesystem, client: &Path, path: &Path, block_size: usize) -> usize {
+    let mut actual_path = PathBuf::from(client);
+    actual_path.push(path!("dat"));
+    actual_path.push(path);
+    fs.metadata(&actual_path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| blocks(metadata.len(), block_size).max(1))
+        .unwrap_or_default()
+}
+
+fn location_index(location: Location) -> usize {
+    usage::location_id(location).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write as _;
+
+    use trussed::{
+        virt::{self, Ram, StoreProvider},
+        Platform as _,
+    };
+
+    use super::*;
+
+    type VirtStore = <Ram as StoreProvider>::Store;
+
+    const CLIENT: &Path = path!("fido");
+    const QUOTAS: &[Quota] = &[Quota {
+        client: CLIENT,
+        location: Location::External,
+        max_percent: 50,
+    }];
+
+    fn write(len: usize) -> Write<'static> {
+        Write::new(Location::External, Some(len))
+    }
+
+    fn check(store: &VirtStore, context: &mut QuotaContext, write: Write<'_>) -> bool {
+        match super::check(QUOTAS, store, CLIENT, context, write) {
+            Ok(()) => true,
+            Err(err) => {
+                assert_eq!(err, QUOTA_EXCEEDED);
+                false
+            }
+        }
+    }
+
+    /// Writes files to the client directory until it uses at least `blocks` blocks
+    fn fill(fs: &dyn DynFilesystem, blocks: usize) {
+        let chunk = [0xff; 1024];
+        let mut i = 0;
+        while client_blocks(fs, CLIENT).unwrap() < blocks {
+            let mut name = heapless::String::<24>::new();
+            write!(name, "/fido/dat/filler{i}").unwrap();
+            let name = PathBuf::try_from(name.as_str()).unwrap();
+            fs.write(&name, &chunk[..block_size(fs).min(chunk.len())])
+                .unwrap();
+            i += 1;
+        }
+    }
+
+    fn run(f: impl FnOnce(&VirtStore, &dyn DynFilesystem, usize, usize)) {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.efs();
+            fs.create_dir_all(path!("/fido/dat")).unwrap();
+            f(&store, fs, block_size(fs), QUOTAS[0].max_blocks(fs));
+        })
+    }
+
+    #[test]
+    fn quota_exceeded() {
+        run(|store, fs, block_size, max_blocks| {
+            fs.write(path!("/fido/dat/file"), b"data").unwrap();
+            let used = client_blocks(fs, CLIENT).unwrap();
+            let free = (max_blocks - used) * block_size;
+
+            assert!(check(store, &mut QuotaContext::default(), write(free)));
+            assert!(!check(store, &mut QuotaContext::default(), write(free + 1)));
+            // keys need at least one block
+            fill(fs, max_blocks);
+            let key = Write::new(Location::External, None);
+            assert!(!check(store, &mut QuotaContext::default(), key));
+
+            // other clients and filesystems are not limited
+            let mut context = QuotaContext::default();
+            let other = QUOTAS.iter().copied().map(|quota| Quota {
+                location: Location::Internal,
+                ..quota
+            });
+            let other: Vec<_, 1> = other.collect();
+            assert!(super::check(&other, store, CLIENT, &mut context, write(1)).is_ok());
+            assert!(super::check(QUOTAS, store, path!("opcard"), &mut context, write(1)).is_ok());
+        });
+    }
+
+    #[test]
+    fn quota_replaced_file() {
+        run(|store, fs, block_size, max_blocks| {
+            let len = block_size.min(1024);
+            fs.write(path!("/fido/dat/file"), &[0xff; 1024][..len])
+                .unwrap();
+            fill(fs, max_blocks);
+
+            let rewrite = |path| Write {
+                location: Location::External,
+                len: Some(len),
+                replaces: Some(path),
+            };
+            assert!(check(
+                store,
+                &mut QuotaContext::default(),
+                rewrite(path!("file"))
+            ));
+            assert!(!check(
+                store,
+                &mut QuotaContext::default(),
+                rewrite(path!("missing"))
+            ));
+        });
+    }
+
+    #[test]
+    fn quota_cache() {
+        run(|store, fs, block_size, max_blocks| {
+            let mut context = QuotaContext::default();
+            assert!(check(store, &mut context, write(block_size)));
+            let cached = context.usage[location_index(Location::External)].unwrap();
+            assert_eq!(cached, client_blocks(fs, CLIENT).unwrap() + 1);
+
+            // The cached usage is used as long as it is below the quota, even if files are
+            // written without the quota backend
+            fill(fs, max_blocks);
+            assert!(check(store, &mut context, write(block_size)));
+            assert!(!check(
+                store,
+                &mut QuotaContext::default(),
+                write(block_size)
+            ));
+
+            // The usage is read again if the cached usage exceeds the quota
+            let mut context = QuotaContext::default();
+            context.usage[location_index(Location::External)] = Some(max_blocks);
+            fs.remove_dir_all(path!("/fido")).unwrap();
+            assert!(check(store, &mut context, write(block_size)));
+
+            // The size of keys is not known, so the cache is cleared
+            assert!(check(
+                store,
+                &mut context,
+                Write::new(Location::External, None)
+            ));
+            assert!(context.usage[location_index(Location::External)].is_none());
+        });
+    }
+
+    #[test]
+    fn quota_defaults() {
+        let quotas = Quota::defaults();
+        assert_eq!(quotas.len(), 10);
+        for location in LOCATIONS {
+            let total: usize = quotas
+                .iter()
+                .filter(|quota| quota.location == location)
+                .map(|quota| usize::from(quota.max_percent))
+                .sum();
+            assert!(total <= 100, "{location:?}: {total}");
+        }
+        assert!(quotas.contains(&Quota {
+            client: path!("fido"),
+            location: Location::Internal,
+            max_percent: 35,
+        }));
+    }
+
+    #[test]
+    fn quota_config() {
+        let mut config = QuotaConfig::default();
+        assert_eq!(config.quotas(), Quota::defaults());
+
+        config.opcard = 1;
+        config.webcrypt = 200;
+        let quotas = config.quotas();
+        for (quota, default) in quotas.iter().zip(Quota::defaults()) {
+            let max_percent = match quota.client.as_str() {
+                "opcard" => 10,
+                _ => default.max_percent,
+            };
+            assert_eq!(
+                *quota,
+                Quota {
+                    max_percent,
+                    ..default
+                }
+            );
+        }
+        assert!(config.field("admin").is_none());
+    }
+
+    #[test]
+    fn quota_config_serialization() {
+        let mut buffer = [0; 16];
+        let config = QuotaConfig::default();
+        assert_eq!(
+            cbor_smol::cbor_serialize(&config, &mut buffer).unwrap(),
+            [0]
+        );
+
+        let config = QuotaConfig {
+            fido: 10,
+            opcard: 1,
+            piv: 2,
+            secrets: 3,
+            webcrypt: 4,
+        };
+        let data = cbor_smol::cbor_serialize(&config, &mut buffer).unwrap();
+        // 0x4321a as u32
+        assert_eq!(data, [0x1a, 0x00, 0x04, 0x32, 0x1a]);
+        let deserialized: QuotaConfig = cbor_smol::cbor_deserialize(data).unwrap();
+        assert_eq!(deserialized, config);
+    }
+}
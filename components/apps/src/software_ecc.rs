@@ -0,0 +1,551 @@
+//! Software implementation of the elliptic curves that are otherwise only provided by the SE050.
+//!
+//! This backend is intended for runners without a secure element, in particular the usbip
+//! runner.  It is not hardened against side channels and should not be used on real devices.
+//!
+//! The NIST and secp256k1 curves use the RustCrypto implementations.  The brainpool curves are
+//! implemented in the [`brainpool`][] module.
+
+mod brainpool;
+
+use ecdsa::signature::hazmat::{PrehashSigner as _, PrehashVerifier as _};
+use elliptic_curve::{
+    ecdh,
+    rand_core::CryptoRngCore,
+    sec1::{EncodedPoint, ToEncodedPoint as _},
+    PublicKey, SecretKey,
+};
+use sha2::{Digest as _, Sha256, Sha384, Sha512};
+
+use brainpool::{BrainpoolP256R1, BrainpoolP384R1, BrainpoolP512R1};
+use trussed::{
+    api::{reply, Reply, Request},
+    backend::Backend,
+    key,
+    service::ServiceResources,
+    store::keystore::Keystore,
+    types::{
+        CoreContext, KeyId, KeySerialization, Mechanism, SerializedKey, Signature,
+        SignatureSerialization,
+    },
+    Error, Platform,
+};
+
+/// The mechanisms implemented by [`SoftwareEcc`][]
+pub const MECHANISMS: &[Mechanism] = &[
+    Mechanism::P384,
+    Mechanism::P384Prehashed,
+    Mechanism::P521,
+    Mechanism::P521Prehashed,
+    Mechanism::Secp256k1,
+    Mechanism::Secp256k1Prehashed,
+    Mechanism::BrainpoolP256R1,
+    Mechanism::BrainpoolP256R1Prehashed,
+    Mechanism::BrainpoolP384R1,
+    Mechanism::BrainpoolP384R1Prehashed,
+    Mechanism::BrainpoolP512R1,
+    Mechanism::BrainpoolP512R1Prehashed,
+];
+
+// Large enough for a secret scalar, a compressed public key and a shared secret on P-521
+type Material = heapless::Vec<u8, 72>;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoftwareEcc;
+
+impl Backend for SoftwareEcc {
+    type Context = ();
+
+    fn request<P: Platform>(
+        &mut self,
+        core_ctx: &mut CoreContext,
+        _backend_ctx: &mut Self::Context,
+        request: &Request,
+        resources: &mut ServiceResources<P>,
+    ) -> Result<Reply, Error> {
+        let Some(mechanism) = mechanism(request) else {
+            return Err(Error::RequestNotAvailable);
+        };
+        let mut keystore = resources.keystore(core_ctx.path.clone())?;
+        let keystore = &mut keystore;
+        match mechanism {
+            Mechanism::P384 => handle::<P384>(keystore, request, false),
+            Mechanism::P384Prehashed => handle::<P384>(keystore, request, true),
+            Mechanism::P521 => handle::<P521>(keystore, request, false),
+            Mechanism::P521Prehashed => handle::<P521>(keystore, request, true),
+            Mechanism::Secp256k1 => handle::<Secp256k1>(keystore, request, false),
+            Mechanism::Secp256k1Prehashed => handle::<Secp256k1>(keystore, request, true),
+            Mechanism::BrainpoolP256R1 => handle::<BrainpoolP256R1>(keystore, request, false),
+            Mechanism::BrainpoolP256R1Prehashed => {
+                handle::<BrainpoolP256R1>(keystore, request, true)
+            }
+            Mechanism::BrainpoolP384R1 => handle::<BrainpoolP384R1>(keystore, request, false),
+            Mechanism::BrainpoolP384R1Prehashed => {
+                handle::<BrainpoolP384R1>(keystore, request, true)
+            }
+            Mechanism::BrainpoolP512R1 => handle::<BrainpoolP512R1>(keystore, request, false),
+            Mechanism::BrainpoolP512R1Prehashed => {
+                handle::<BrainpoolP512R1>(keystore, request, true)
+            }
+            _ => Err(Error::RequestNotAvailable),
+        }
+    }
+}
+
+fn mechanism(request: &Request) -> Option<Mechanism> {
+    match request {
+        Request::Agree(request) => Some(request.mechanism),
+        Request::DeriveKey(request) => Some(request.mechanism),
+        Request::DeserializeKey(request) => Some(request.mechanism),
+        Request::Exists(request) => Some(request.mechanism),
+        Request::GenerateKey(request) => Some(request.mechanism),
+        Request::SerializeKey(request) => Some(request.mechanism),
+        Request::Sign(request) => Some(request.mechanism),
+        Request::UnsafeInjectKey(request) => Some(request.mechanism),
+        Request::Verify(request) => Some(request.mechanism),
+        _ => None,
+    }
+}
+
+fn handle<C: Curve>(
+    keystore: &mut impl Keystore,
+    request: &Request,
+    prehashed: bool,
+) -> Result<Reply, Error> {
+    match request {
+        Request::Agree(request) => {
+            let secret = load_key::<C>(keystore, key::Secrecy::Secret, &request.private_key)?;
+            let public = load_key::<C>(keystore, key::Secrecy::Public, &request.public_key)?;
+            let shared_secret = C::agree(&secret, &public)?;
+            let mut flags = key::Flags::SENSITIVE;
+            if request.attributes.serializable {
+                flags |= key::Flags::SERIALIZABLE;
+            }
+            let info = key::Info {
+                flags,
+                kind: key::Kind::Shared(shared_secret.len()),
+            };
+            let shared_secret = keystore.store_key(
+                request.attributes.persistence,
+                key::Secrecy::Secret,
+                info,
+                &shared_secret,
+            )?;
+            Ok(Reply::Agree(reply::Agree { shared_secret }))
+        }
+        Request::DeriveKey(request) => {
+            let secret = load_key::<C>(keystore, key::Secrecy::Secret, &request.base_key)?;
+            let public = C::public_key(&secret)?;
+            let key = keystore.store_key(
+                request.attributes.persistence,
+                key::Secrecy::Public,
+                C::KIND,
+                &public,
+            )?;
+            Ok(Reply::DeriveKey(reply::DeriveKey { key }))
+        }
+        Request::DeserializeKey(request) => {
+            let public = C::deserialize_public_key(&request.serialized_key, request.format)?;
+            let key = keystore.store_key(
+                request.attributes.persistence,
+                key::Secrecy::Public,
+                C::KIND,
+                &public,
+            )?;
+            Ok(Reply::DeserializeKey(reply::DeserializeKey { key }))
+        }
+        Request::Exists(request) => {
+            let exists = keystore.exists_key(key::Secrecy::Secret, Some(C::KIND), &request.key);
+            Ok(Reply::Exists(reply::Exists { exists }))
+        }
+        Request::GenerateKey(request) => {
+            let secret = C::generate(keystore.rng());
+            let key = keystore.store_key(
+                request.attributes.persistence,
+                key::Secrecy::Secret,
+                key::Info::from(C::KIND).with_local_flag(),
+                &secret,
+            )?;
+            Ok(Reply::GenerateKey(reply::GenerateKey { key }))
+        }
+        Request::SerializeKey(request) => {
+            let public = load_key::<C>(keystore, key::Secrecy::Public, &request.key)?;
+            let serialized_key = C::serialize_public_key(&public, request.format)?;
+            Ok(Reply::SerializeKey(reply::SerializeKey { serialized_key }))
+        }
+        Request::Sign(request) => {
+            let secret = load_key::<C>(keystore, key::Secrecy::Secret, &request.key)?;
+            let signature = C::sign(&secret, &request.message, prehashed, request.format)?;
+            Ok(Reply::Sign(reply::Sign { signature }))
+        }
+        Request::UnsafeInjectKey(request) => {
+            let KeySerialization::Raw = request.format else {
+                return Err(Error::InvalidSerializationFormat);
+            };
+            let secret = C::import_secret_key(&request.raw_key)?;
+            let key = keystore.store_key(
+                request.attributes.persistence,
+                key::Secrecy::Secret,
+                C::KIND,
+                &secret,
+            )?;
+            Ok(Reply::UnsafeInjectKey(reply::UnsafeInjectKey { key }))
+        }
+        Request::Verify(request) => {
+            let public = load_key::<C>(keystore, key::Secrecy::Public, &request.key)?;
+            let valid = C::verify(
+                &public,
+                &request.message,
+                &request.signature,
+                prehashed,
+                request.format,
+            )?;
+            Ok(Reply::Verify(reply::Verify { valid }))
+        }
+        _ => Err(Error::RequestNotAvailable),
+    }
+}
+
+fn load_key<C: Curve>(
+    keystore: &mut impl Keystore,
+    secrecy: key::Secrecy,
+    id: &KeyId,
+) -> Result<Material, Error> {
+    let key = keystore.load_key(secrecy, Some(C::KIND), id)?;
+    Material::from_slice(&key.material).map_err(|_| Error::InternalError)
+}
+
+/// Curve operations on serialized keys
+///
+/// Secret keys are stored as the big-endian scalar, public keys as compressed SEC1 points.
+trait Curve {
+    const KIND: key::Kind;
+
+    fn generate(rng: &mut impl CryptoRngCore) -> Material;
+    fn import_secret_key(data: &[u8]) -> Result<Material, Error>;
+    fn public_key(secret: &[u8]) -> Result<Material, Error>;
+    fn deserialize_public_key(data: &[u8], format: KeySerialization) -> Result<Material, Error>;
+    fn serialize_public_key(
+        public: &[u8],
+        format: KeySerialization,
+    ) -> Result<SerializedKey, Error>;
+    fn agree(secret: &[u8], public: &[u8]) -> Result<Material, Error>;
+    fn sign(
+        secret: &[u8],
+        message: &[u8],
+        prehashed: bool,
+        format: SignatureSerialization,
+    ) -> Result<Signature, Error>;
+    fn verify(
+        public: &[u8],
+        message: &[u8],
+        signature: &[u8],
+        prehashed: bool,
+        format: SignatureSerialization,
+    ) -> Result<bool, Error>;
+}
+
+macro_rules! impl_curve {
+    ($name:ident, $curve:ty, $digest:ty, $kind:expr, $signing_key:ty, $verifying_key:ty) => {
+        struct $name;
+
+        impl $name {
+            fn load_secret_key(secret: &[u8]) -> Result<SecretKey<$curve>, Error> {
+                SecretKey::from_slice(secret).map_err(|_| Error::InternalError)
+            }
+
+            fn load_public_key(public: &[u8]) -> Result<PublicKey<$curve>, Error> {
+                PublicKey::from_sec1_bytes(public).map_err(|_| Error::InternalError)
+            }
+
+            fn compressed(public: &PublicKey<$curve>) -> Result<Material, Error> {
+                Material::from_slice(public.to_encoded_point(true).as_bytes())
+                    .map_err(|_| Error::InternalError)
+            }
+
+            fn prehash<'a>(
+                message: &'a [u8],
+                prehashed: bool,
+                digest: &'a mut Material,
+            ) -> Result<&'a [u8], Error> {
+                if prehashed {
+                    Ok(message)
+                } else {
+                    *digest = Material::from_slice(&<$digest>::digest(message))
+                        .map_err(|_| Error::InternalError)?;
+                    Ok(digest)
+                }
+            }
+        }
+
+        impl Curve for $name {
+            const KIND: key::Kind = $kind;
+
+            fn generate(rng: &mut impl CryptoRngCore) -> Material {
+                let secret = SecretKey::<$curve>::random(rng);
+                // The material buffer is large enough for the scalars of all supported curves
+                Material::from_slice(&secret.to_bytes()).unwrap()
+            }
+
+            fn import_secret_key(data: &[u8]) -> Result<Material, Error> {
+                let secret = SecretKey::<$curve>::from_slice(data)
+                    .map_err(|_| Error::InvalidSerializedKey)?;
+                Material::from_slice(&secret.to_bytes()).map_err(|_| Error::InternalError)
+            }
+
+            fn public_key(secret: &[u8]) -> Result<Material, Error> {
+                Self::compressed(&Self::load_secret_key(secret)?.public_key())
+            }
+
+            fn deserialize_public_key(
+                data: &[u8],
+                format: KeySerialization,
+            ) -> Result<Material, Error> {
+                let public = match format {
+                    KeySerialization::Raw => {
+                        let mut point = heapless::Vec::<u8, 160>::new();
+                        point.push(0x04).ok();
+                        point
+                            .extend_from_slice(data)
+                            .map_err(|_| Error::InvalidSerializedKey)?;
+                        PublicKey::<$curve>::from_sec1_bytes(&point)
+                    }
+                    KeySerialization::Sec1 => PublicKey::<$curve>::from_sec1_bytes(data),
+                    _ => return Err(Error::InvalidSerializationFormat),
+                }
+                .map_err(|_| Error::InvalidSerializedKey)?;
+                Self::compressed(&public)
+            }
+
+            fn serialize_public_key(
+                public: &[u8],
+                format: KeySerialization,
+            ) -> Result<SerializedKey, Error> {
+                let public = Self::load_public_key(public)?;
+                let point: EncodedPoint<$curve> = match format {
+                    KeySerialization::Raw => public.to_encoded_point(false),
+                    KeySerialization::Sec1 => public.to_encoded_point(true),
+                    _ => return Err(Error::InvalidSerializationFormat),
+                };
+                let data = match format {
+                    // Raw keys are the concatenated coordinates without the SEC1 tag
+                    KeySerialization::Raw => &point.as_bytes()[1..],
+                    _ => point.as_bytes(),
+                };
+                SerializedKey::from_slice(data).map_err(|_| Error::InternalError)
+            }
+
+            fn agree(secret: &[u8], public: &[u8]) -> Result<Material, Error> {
+                let secret = Self::load_secret_key(secret)?;
+                let public = Self::load_public_key(public)?;
+                let shared_secret =
+                    ecdh::diffie_hellman(secret.to_nonzero_scalar(), public.as_affine());
+                Material::from_slice(shared_secret.raw_secret_bytes())
+                    .map_err(|_| Error::InternalError)
+            }
+
+            fn sign(
+                secret: &[u8],
+                message: &[u8],
+                prehashed: bool,
+                format: SignatureSerialization,
+            ) -> Result<Signature, Error> {
+                let signing_key =
+                    <$signing_key>::from_bytes(&Self::load_secret_key(secret)?.to_bytes())
+                        .map_err(|_| Error::InternalError)?;
+                let mut digest = Material::new();
+                let prehash = Self::prehash(message, prehashed, &mut digest)?;
+                let signature: ecdsa::Signature<$curve> = signing_key
+                    .sign_prehash(prehash)
+                    .map_err(|_| Error::InternalError)?;
+                match format {
+                    SignatureSerialization::Raw => Signature::from_slice(&signature.to_bytes()),
+                    SignatureSerialization::Asn1Der => {
+                        Signature::from_slice(signature.to_der().as_bytes())
+                    }
+                    _ => return Err(Error::InvalidSerializationFormat),
+                }
+                .map_err(|_| Error::InternalError)
+            }
+
+            fn verify(
+                public: &[u8],
+                message: &[u8],
+                signature: &[u8],
+                prehashed: bool,
+                format: SignatureSerialization,
+            ) -> Result<bool, Error> {
+                let verifying_key =
+                    <$verifying_key>::from_sec1_bytes(public).map_err(|_| Error::InternalError)?;
+                let signature = match format {
+                    SignatureSerialization::Raw => {
+                        ecdsa::Signature::<$curve>::from_slice(signature)
+                    }
+                    SignatureSerialization::Asn1Der => {
+                        ecdsa::Signature::<$curve>::from_der(signature)
+                    }
+                    _ => return Err(Error::InvalidSerializationFormat),
+                }
+                .map_err(|_| Error::WrongSignatureLength)?;
+                let mut digest = Material::new();
+                let prehash = Self::prehash(message, prehashed, &mut digest)?;
+                Ok(verifying_key.verify_prehash(prehash, &signature).is_ok())
+            }
+        }
+    };
+}
+
+impl_curve!(
+    P384,
+    p384::NistP384,
+    Sha384,
+    key::Kind::P384,
+    p384::ecdsa::SigningKey,
+    p384::ecdsa::VerifyingKey
+);
+// p521 provides its own key wrappers because the curve does not implement the generic digest
+// selection used by ecdsa
+impl_curve!(
+    P521,
+    p521::NistP521,
+    Sha512,
+    key::Kind::P521,
+    p521::ecdsa::SigningKey,
+    p521::ecdsa::VerifyingKey
+);
+impl_curve!(
+    Secp256k1,
+    k256::Secp256k1,
+    Sha256,
+    key::Kind::Secp256k1,
+    k256::ecdsa::SigningKey,
+    k256::ecdsa::VerifyingKey
+);
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest;
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"message";
+
+    fn secret_key<C: Curve>(byte: u8, len: usize) -> Material {
+        let scalar = [byte; 66];
+        C::import_secret_key(&scalar[..len]).unwrap()
+    }
+
+    fn test_curve<C: Curve>(scalar_len: usize, digest: &[u8]) {
+        let secret = secret_key::<C>(0x01, scalar_len);
+        let public = C::public_key(&secret).unwrap();
+        assert_eq!(
+            C::import_secret_key(&[0; 66][..scalar_len]),
+            Err(Error::InvalidSerializedKey)
+        );
+
+        for format in [SignatureSerialization::Raw, SignatureSerialization::Asn1Der] {
+            let signature = C::sign(&secret, MESSAGE, false, format).unwrap();
+            assert!(C::verify(&public, MESSAGE, &signature, false, format).unwrap());
+            assert!(!C::verify(&public, b"other", &signature, false, format).unwrap());
+            assert!(C::verify(&public, digest, &signature, true, format).unwrap());
+
+            // The signatures are deterministic, see RFC 6979
+            let prehashed = C::sign(&secret, digest, true, format).unwrap();
+            assert_eq!(prehashed, signature);
+        }
+        let signature = C::sign(&secret, MESSAGE, false, SignatureSerialization::Raw).unwrap();
+        assert_eq!(signature.len(), 2 * scalar_len);
+
+        for format in [KeySerialization::Raw, KeySerialization::Sec1] {
+            let serialized = C::serialize_public_key(&public, format).unwrap();
+            let deserialized = C::deserialize_public_key(&serialized, format).unwrap();
+            assert_eq!(deserialized, public);
+        }
+        let raw = C::serialize_public_key(&public, KeySerialization::Raw).unwrap();
+        assert_eq!(raw.len(), 2 * scalar_len);
+        assert_eq!(
+            C::serialize_public_key(&public, KeySerialization::Cose),
+            Err(Error::InvalidSerializationFormat)
+        );
+
+        let other_secret = secret_key::<C>(0x02, scalar_len);
+        let other_public = C::public_key(&other_secret).unwrap();
+        let shared_secret = C::agree(&secret, &other_public).unwrap();
+        assert_eq!(shared_secret.len(), scalar_len);
+        assert_eq!(C::agree(&other_secret, &public).unwrap(), shared_secret);
+        assert_ne!(C::agree(&secret, &public).unwrap(), shared_secret);
+    }
+
+    /// Compares the results for the secret keys 0x0101.. and 0x0202.. with an independent
+    /// implementation
+    fn test_vectors<C: Curve>(scalar_len: usize, public: &str, shared: &str, signature: &str) {
+        let secret = secret_key::<C>(0x01, scalar_len);
+        let public_key = C::public_key(&secret).unwrap();
+        assert_eq!(hex::encode(&public_key), public);
+        let other_public = C::public_key(&secret_key::<C>(0x02, scalar_len)).unwrap();
+        assert_eq!(
+            hex::encode(C::agree(&secret, &other_public).unwrap()),
+            shared
+        );
+        let raw = C::sign(&secret, MESSAGE, false, SignatureSerialization::Raw).unwrap();
+        assert_eq!(hex::encode(&raw), signature);
+    }
+
+    #[test]
+    fn p384() {
+        test_curve::<P384>(48, &Sha384::digest(MESSAGE));
+    }
+
+    #[test]
+    fn p521() {
+        test_curve::<P521>(66, &Sha512::digest(MESSAGE));
+    }
+
+    #[test]
+    fn secp256k1() {
+        test_curve::<Secp256k1>(32, &Sha256::digest(MESSAGE));
+    }
+
+    #[test]
+    fn brainpool_p256r1() {
+        test_curve::<BrainpoolP256R1>(32, &Sha256::digest(MESSAGE));
+        test_vectors::<BrainpoolP256R1>(
+            32,
+            "029c2673c6514a204b38fccc53e73cee10c844c2c2f4362c32db014e2d53f81184",
+            "8ec9d4af77b8efe52d4d0750225cc333213afa6ab9cb648b60093f84ac0cc23a",
+            "4f3800b1981a5dce6d7d4077a41af9cde89610a0a97bc1dd2cca4d4ad3e458c8\
+             6b2b8aa34079256513ef41d067ff300bd8ba2e40a6f8038b39e819f6dc99195a",
+        );
+    }
+
+    #[test]
+    fn brainpool_p384r1() {
+        test_curve::<BrainpoolP384R1>(48, &Sha384::digest(MESSAGE));
+        test_vectors::<BrainpoolP384R1>(
+            48,
+            "033b358f4d6cb36e8aa358b5d4a260fccbc385a3fb7a2aea32dd5fbe8b8834b3bf\
+             cd1003f8578f9d68939e218725376b3c",
+            "12d1b19c0a3bcce19bad8ac7d7669d197e18b190c470eb2883eeeb5ddfddb939\
+             62b39c515065fae883cd8e4815c23f7f",
+            "42240b9f5fa096f37f2149070dedd5f402c72d9d81c5584e1542e73d16e92cef\
+             754be7d43ad3c701ff95c1962a0a00fd4fde26ca62cbc9240f2dcbf89ca5c9a8\
+             371dfaa42d35d640c5bf655dfeaab3e83bd3589e88bbd720e8aec692a4ac2a9f",
+        );
+    }
+
+    #[test]
+    fn brainpool_p512r1() {
+        test_curve::<BrainpoolP512R1>(64, &Sha512::digest(MESSAGE));
+        test_vectors::<BrainpoolP512R1>(
+            64,
+            "026c549ffa7d4c0f9655b0e3a0fd68d0edaef401deeca2efdbad740deff51849\
+             4f590a8f6771c6e3495ce6ddad026936d7a6a84c943bd583159d60859f2cfa0161",
+            "84866624ab99c018674a5f4554e74c262922c5a885682716d0c0bf7440b697de\
+             61ecb0ac7258db6ef43a97d3d279cdae6f8b7c57a97962ff2c76082be78d9080",
+            "5d8efb76e2baff89f72abec7ca61b43008cfdaf4c1d0008cea713f2d6886bb57\
+             645513302e429e78731be2f8499a32dc470c2825988327195667636ef3001bc0\
+             7fae7919625aaff506daa3d9f6597dbd25e57c3e45614295ddf1eab600cf81bb\
+             b1d8f66c861a60ab8320919966f7f99cdf3cc373684e64a73e0b2717d7edc118",
+        );
+    }
+}
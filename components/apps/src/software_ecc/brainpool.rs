@@ -0,0 +1,595 @@
+//! Brainpool curves for the software ECC backend, see RFC 5639.
+//!
+//! There is no stable RustCrypto implementation of the brainpool curve arithmetic, so this module
+//! implements it with big integers and Jacobian coordinates.  It is neither constant-time nor
+//! optimized.  Signatures are deterministic as specified in RFC 6979.
+
+use alloc::vec::Vec;
+
+use elliptic_curve::rand_core::CryptoRngCore;
+use hmac::{Mac as _, SimpleHmac};
+use num_bigint_dig::BigUint;
+use sha2::{
+    digest::{core_api::BlockSizeUser, Digest, Output},
+    Sha256, Sha384, Sha512,
+};
+use trussed::{
+    key,
+    types::{KeySerialization, SerializedKey, Signature, SignatureSerialization},
+    Error,
+};
+
+use super::{Curve, Material};
+
+/// Domain parameters of a curve as hexadecimal strings
+struct Params {
+    p: &'static [u8],
+    a: &'static [u8],
+    b: &'static [u8],
+    x: &'static [u8],
+    y: &'static [u8],
+    n: &'static [u8],
+}
+
+const BRAINPOOL_P256R1: Params = Params {
+    p: b"A9FB57DBA1EEA9BC3E660A909D838D726E3BF623D52620282013481D1F6E5377",
+    a: b"7D5A0975FC2C3057EEF67530417AFFE7FB8055C126DC5C6CE94A4B44F330B5D9",
+    b: b"26DC5C6CE94A4B44F330B5D9BBD77CBF958416295CF7E1CE6BCCDC18FF8C07B6",
+    x: b"8BD2AEB9CB7E57CB2C4B482FFC81B7AFB9DE27E1E3BD23C23A4453BD9ACE3262",
+    y: b"547EF835C3DAC4FD97F8461A14611DC9C27745132DED8E545C1D54C72F046997",
+    n: b"A9FB57DBA1EEA9BC3E660A909D838D718C397AA3B561A6F7901E0E82974856A7",
+};
+
+const BRAINPOOL_P384R1: Params = Params {
+    p: b"8CB91E82A3386D280F5D6F7E50E641DF152F7109ED5456B412B1DA197FB71123ACD3A729901D1A71874700133107EC53",
+    a: b"7BC382C63D8C150C3C72080ACE05AFA0C2BEA28E4FB22787139165EFBA91F90F8AA5814A503AD4EB04A8C7DD22CE2826",
+    b: b"04A8C7DD22CE28268B39B55416F0447C2FB77DE107DCD2A62E880EA53EEB62D57CB4390295DBC9943AB78696FA504C11",
+    x: b"1D1C64F068CF45FFA2A63A81B7C13F6B8847A3E77EF14FE3DB7FCAFE0CBD10E8E826E03436D646AAEF87B2E247D4AF1E",
+    y: b"8ABE1D7520F9C2A45CB1EB8E95CFD55262B70B29FEEC5864E19C054FF99129280E4646217791811142820341263C5315",
+    n: b"8CB91E82A3386D280F5D6F7E50E641DF152F7109ED5456B31F166E6CAC0425A7CF3AB6AF6B7FC3103B883202E9046565",
+};
+
+const BRAINPOOL_P512R1: Params = Params {
+    p: b"AADD9DB8DBE9C48B3FD4E6AE33C9FC07CB308DB3B3C9D20ED6639CCA703308717D4D9B009BC66842AECDA12AE6A380E62881FF2F2D82C68528AA6056583A48F3",
+    a: b"7830A3318B603B89E2327145AC234CC594CBDD8D3DF91610A83441CAEA9863BC2DED5D5AA8253AA10A2EF1C98B9AC8B57F1117A72BF2C7B9E7C1AC4D77FC94CA",
+    b: b"3DF91610A83441CAEA9863BC2DED5D5AA8253AA10A2EF1C98B9AC8B57F1117A72BF2C7B9E7C1AC4D77FC94CADC083E67984050B75EBAE5DD2809BD638016F723",
+    x: b"81AEE4BDD82ED9645A21322E9C4C6A9385ED9F70B5D916C1B43B62EEF4D0098EFF3B1F78E2D0D48D50D1687B93B97D5F7C6D5047406A5E688B352209BCB9F822",
+    y: b"7DDE385D566332ECC0EABFA9CF7822FDF209F70024A57B1AA000C55B881F8111B2DCDE494A5F485E5BCA4BD88A2763AED1CA2B2FA8F0540678CD1E0F3AD80892",
+    n: b"AADD9DB8DBE9C48B3FD4E6AE33C9FC07CB308DB3B3C9D20ED6639CCA70330870553E5C414CA92619418661197FAC10471DB1D381085DDADDB58796829CA90069",
+};
+
+#[derive(Clone, Debug, PartialEq)]
+struct Affine {
+    x: BigUint,
+    y: BigUint,
+}
+
+/// A point in Jacobian coordinates, i. e. (x / z², y / z³) in affine coordinates
+#[derive(Clone, Debug)]
+struct Jacobian {
+    x: BigUint,
+    y: BigUint,
+    z: BigUint,
+}
+
+impl From<&Affine> for Jacobian {
+    fn from(point: &Affine) -> Self {
+        Self {
+            x: point.x.clone(),
+            y: point.y.clone(),
+            z: BigUint::from(1u32),
+        }
+    }
+}
+
+/// A brainpool curve with the parsed domain parameters
+///
+/// The prime p is congruent to 3 mod 4 and the cofactor is 1 for all brainpool curves, and the
+/// order n has exactly the bit length of the scalars.
+struct Domain {
+    p: BigUint,
+    a: BigUint,
+    b: BigUint,
+    g: Affine,
+    n: BigUint,
+    /// The length of the field elements and scalars in bytes
+    size: usize,
+}
+
+impl Domain {
+    fn new(params: &Params) -> Self {
+        let parse = |value: &[u8]| BigUint::parse_bytes(value, 16).unwrap();
+        Self {
+            p: parse(params.p),
+            a: parse(params.a),
+            b: parse(params.b),
+            g: Affine {
+                x: parse(params.x),
+                y: parse(params.y),
+            },
+            n: parse(params.n),
+            size: params.p.len() / 2,
+        }
+    }
+
+    fn add(&self, x: &BigUint, y: &BigUint) -> BigUint {
+        (x + y) % &self.p
+    }
+
+    fn sub(&self, x: &BigUint, y: &BigUint) -> BigUint {
+        (x + &self.p - y) % &self.p
+    }
+
+    fn mul(&self, x: &BigUint, y: &BigUint) -> BigUint {
+        (x * y) % &self.p
+    }
+
+    fn small_mul(&self, factor: u32, x: &BigUint) -> BigUint {
+        (x * BigUint::from(factor)) % &self.p
+    }
+
+    /// Computes the inverse modulo the prime `modulus` using Fermat's little theorem
+    fn invert(x: &BigUint, modulus: &BigUint) -> BigUint {
+        x.modpow(&(modulus - BigUint::from(2u32)), modulus)
+    }
+
+    fn is_on_curve(&self, point: &Affine) -> bool {
+        if point.x >= self.p || point.y >= self.p {
+            return false;
+        }
+        self.mul(&point.y, &point.y) == self.rhs(&point.x)
+    }
+
+    /// Returns x³ + ax + b
+    fn rhs(&self, x: &BigUint) -> BigUint {
+        let x3 = self.mul(&self.mul(x, x), x);
+        self.add(&self.add(&x3, &self.mul(&self.a, x)), &self.b)
+    }
+
+    fn double(&self, point: &Jacobian) -> Option<Jacobian> {
+        if point.y == BigUint::from(0u32) {
+            return None;
+        }
+        let yy = self.mul(&point.y, &point.y);
+        let zz = self.mul(&point.z, &point.z);
+        let s = self.small_mul(4, &self.mul(&point.x, &yy));
+        let m = self.add(
+            &self.small_mul(3, &self.mul(&point.x, &point.x)),
+            &self.mul(&self.a, &self.mul(&zz, &zz)),
+        );
+        let x = self.sub(&self.mul(&m, &m), &self.small_mul(2, &s));
+        let y = self.sub(
+            &self.mul(&m, &self.sub(&s, &x)),
+            &self.small_mul(8, &self.mul(&yy, &yy)),
+        );
+        let z = self.small_mul(2, &self.mul(&point.y, &point.z));
+        Some(Jacobian { x, y, z })
+    }
+
+    fn add_points(&self, p: Option<&Jacobian>, q: Option<&Jacobian>) -> Option<Jacobian> {
+        let (p, q) = match (p, q) {
+            (Some(p), Some(q)) => (p, q),
+            (p, q) => return p.or(q).cloned(),
+        };
+        let z1z1 = self.mul(&p.z, &p.z);
+        let z2z2 = self.mul(&q.z, &q.z);
+        let u1 = self.mul(&p.x, &z2z2);
+        let u2 = self.mul(&q.x, &z1z1);
+        let s1 = self.mul(&p.y, &self.mul(&q.z, &z2z2));
+        let s2 = self.mul(&q.y, &self.mul(&p.z, &z1z1));
+        if u1 == u2 {
+            return if s1 == s2 { self.double(p) } else { None };
+        }
+        let h = self.sub(&u2, &u1);
+        let r = self.sub(&s2, &s1);
+        let hh = self.mul(&h, &h);
+        let hhh = self.mul(&h, &hh);
+        let v = self.mul(&u1, &hh);
+        let x = self.sub(&self.sub(&self.mul(&r, &r), &hhh), &self.small_mul(2, &v));
+        let y = self.sub(&self.mul(&r, &self.sub(&v, &x)), &self.mul(&s1, &hhh));
+        let z = self.mul(&h, &self.mul(&p.z, &q.z));
+        Some(Jacobian { x, y, z })
+    }
+
+    fn mul_point(&self, scalar: &BigUint, point: &Affine) -> Option<Jacobian> {
+        let point = Jacobian::from(point);
+        let mut result = None;
+        for byte in scalar.to_bytes_be() {
+            for i in (0..8).rev() {
+                result = result.and_then(|result| self.double(&result));
+                if byte & (1 << i) != 0 {
+                    result = self.add_points(result.as_ref(), Some(&point));
+                }
+            }
+        }
+        result
+    }
+
+    fn to_affine(&self, point: &Jacobian) -> Affine {
+        let z_inv = Self::invert(&point.z, &self.p);
+        let z_inv2 = self.mul(&z_inv, &z_inv);
+        Affine {
+            x: self.mul(&point.x, &z_inv2),
+            y: self.mul(&point.y, &self.mul(&z_inv2, &z_inv)),
+        }
+    }
+
+    /// Encodes the value as a big-endian integer with the length of the field elements
+    fn to_bytes(&self, value: &BigUint) -> Vec<u8> {
+        let bytes = value.to_bytes_be();
+        let mut padded = Vec::with_capacity(self.size);
+        padded.resize(self.size.saturating_sub(bytes.len()), 0);
+        padded.extend_from_slice(&bytes);
+        padded
+    }
+
+    fn secret_scalar(&self, secret: &[u8]) -> Result<BigUint, Error> {
+        let scalar = BigUint::from_bytes_be(secret);
+        if secret.len() != self.size || scalar == BigUint::from(0u32) || scalar >= self.n {
+            return Err(Error::InvalidSerializedKey);
+        }
+        Ok(scalar)
+    }
+
+    fn public_point(&self, secret: &BigUint) -> Result<Affine, Error> {
+        let point = self
+            .mul_point(secret, &self.g)
+            .ok_or(Error::InternalError)?;
+        Ok(self.to_affine(&point))
+    }
+
+    fn compressed(&self, point: &Affine) -> Result<Material, Error> {
+        let tag = if point.y.to_bytes_be().last().copied().unwrap_or_default() & 1 == 0 {
+            0x02
+        } else {
+            0x03
+        };
+        let mut data = Material::new();
+        data.push(tag).map_err(|_| Error::InternalError)?;
+        data.extend_from_slice(&self.to_bytes(&point.x))
+            .map_err(|_| Error::InternalError)?;
+        Ok(data)
+    }
+
+    /// Decodes a compressed or uncompressed SEC1 point
+    fn decode_point(&self, data: &[u8]) -> Result<Affine, Error> {
+        let point = match data.split_first() {
+            Some((0x04, coordinates)) if coordinates.len() == 2 * self.size => {
+                let (x, y) = coordinates.split_at(self.size);
+                Affine {
+                    x: BigUint::from_bytes_be(x),
+                    y: BigUint::from_bytes_be(y),
+                }
+            }
+            Some((&tag, x)) if (tag == 0x02 || tag == 0x03) && x.len() == self.size => {
+                let x = BigUint::from_bytes_be(x);
+                if x >= self.p {
+                    return Err(Error::InvalidSerializedKey);
+                }
+                // p is congruent to 3 mod 4, so the square root is rhs^((p + 1) / 4)
+                let exponent = (&self.p + BigUint::from(1u32)) / BigUint::from(4u32);
+                let y = self.rhs(&x).modpow(&exponent, &self.p);
+                let odd = y.to_bytes_be().last().copied().unwrap_or_default() & 1 != 0;
+                let y = if odd == (tag == 0x03) {
+                    y
+                } else {
+                    self.sub(&BigUint::from(0u32), &y)
+                };
+                Affine { x, y }
+            }
+            _ => return Err(Error::InvalidSerializedKey),
+        };
+        if self.is_on_curve(&point) {
+            Ok(point)
+        } else {
+            Err(Error::InvalidSerializedKey)
+        }
+    }
+
+    /// Converts a hash to an integer, truncated to the bit length of n
+    fn hash_to_int(&self, hash: &[u8]) -> BigUint {
+        BigUint::from_bytes_be(&hash[..hash.len().min(self.size)])
+    }
+
+    /// Generates the nonce for a signature as specified in RFC 6979, section 3.2
+    fn nonce<D: Digest + BlockSizeUser>(&self, secret: &BigUint, hash: &[u8]) -> BigUint {
+        let hmac = |key: &[u8], data: &[&[u8]]| -> Output<D> {
+            let mut mac =
+                SimpleHmac::<D>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            for data in data {
+                mac.update(data);
+            }
+            mac.finalize().into_bytes()
+        };
+        let x = self.to_bytes(secret);
+        let h = self.to_bytes(&(self.hash_to_int(hash) % &self.n));
+        let mut v = Output::<D>::default();
+        v.fill(0x01);
+        let mut k = Output::<D>::default();
+        k = hmac(&k, &[&v, &[0x00], &x, &h]);
+        v = hmac(&k, &[&v]);
+        k = hmac(&k, &[&v, &[0x01], &x, &h]);
+        v = hmac(&k, &[&v]);
+        loop {
+            let mut t = Vec::with_capacity(self.size + v.len());
+            while t.len() < self.size {
+                v = hmac(&k, &[&v]);
+                t.extend_from_slice(&v);
+            }
+            let nonce = BigUint::from_bytes_be(&t[..self.size]);
+            if nonce != BigUint::from(0u32) && nonce < self.n {
+                return nonce;
+            }
+            k = hmac(&k, &[&v, &[0x00]]);
+            v = hmac(&k, &[&v]);
+        }
+    }
+
+    fn sign<D: Digest + BlockSizeUser>(
+        &self,
+        secret: &[u8],
+        message: &[u8],
+        prehashed: bool,
+        format: SignatureSerialization,
+    ) -> Result<Signature, Error> {
+        let secret = self
+            .secret_scalar(secret)
+            .map_err(|_| Error::InternalError)?;
+        let digest;
+        let hash = if prehashed {
+            message
+        } else {
+            digest = D::digest(message);
+            &digest
+        };
+        let z = self.hash_to_int(hash);
+        let k = self.nonce::<D>(&secret, hash);
+        let r = self.public_point(&k)?.x % &self.n;
+        let s = (Self::invert(&k, &self.n) * ((z + &r * &secret) % &self.n)) % &self.n;
+        // r or s is only zero with negligible probability
+        if r == BigUint::from(0u32) || s == BigUint::from(0u32) {
+            return Err(Error::InternalError);
+        }
+        let (r, s) = (self.to_bytes(&r), self.to_bytes(&s));
+        let signature = match format {
+            SignatureSerialization::Raw => [r, s].concat(),
+            SignatureSerialization::Asn1Der => der::encode_signature(&r, &s),
+            _ => return Err(Error::InvalidSerializationFormat),
+        };
+        Signature::from_slice(&signature).map_err(|_| Error::InternalError)
+    }
+
+    fn verify<D: Digest>(
+        &self,
+        public: &[u8],
+        message: &[u8],
+        signature: &[u8],
+        prehashed: bool,
+        format: SignatureSerialization,
+    ) -> Result<bool, Error> {
+        let public = self
+            .decode_point(public)
+            .map_err(|_| Error::InternalError)?;
+        let (r, s) = match format {
+            SignatureSerialization::Raw if signature.len() == 2 * self.size => {
+                signature.split_at(self.size)
+            }
+            SignatureSerialization::Raw => return Err(Error::WrongSignatureLength),
+            SignatureSerialization::Asn1Der => {
+                der::decode_signature(signature).ok_or(Error::WrongSignatureLength)?
+            }
+            _ => return Err(Error::InvalidSerializationFormat),
+        };
+        let (r, s) = (BigUint::from_bytes_be(r), BigUint::from_bytes_be(s));
+        let zero = BigUint::from(0u32);
+        if r == zero || s == zero || r >= self.n || s >= self.n {
+            return Ok(false);
+        }
+        let digest;
+        let hash = if prehashed {
+            message
+        } else {
+            digest = D::digest(message);
+            &digest
+        };
+        let w = Self::invert(&s, &self.n);
+        let u1 = (self.hash_to_int(hash) * &w) % &self.n;
+        let u2 = (&r * &w) % &self.n;
+        let point = self.add_points(
+            self.mul_point(&u1, &self.g).as_ref(),
+            self.mul_point(&u2, &public).as_ref(),
+        );
+        Ok(point.is_some_and(|point| self.to_affine(&point).x % &self.n == r))
+    }
+}
+
+/// Minimal DER encoding of ECDSA signatures, i. e. a sequence of the two integers r and s
+mod der {
+    use alloc::vec::Vec;
+
+    const INTEGER: u8 = 0x02;
+    const SEQUENCE: u8 = 0x30;
+
+    fn push_length(out: &mut Vec<u8>, len: usize) {
+        // The signatures of all supported curves are shorter than 256 bytes
+        if len >= 0x80 {
+            out.push(0x81);
+        }
+        out.push(len as u8);
+    }
+
+    fn push_integer(out: &mut Vec<u8>, value: &[u8]) {
+        let start = value
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(value.len().saturating_sub(1));
+        let value = &value[start..];
+        let padding = value.first().is_some_and(|byte| byte & 0x80 != 0);
+        out.push(INTEGER);
+        push_length(out, value.len() + usize::from(padding));
+        if padding {
+            out.push(0);
+        }
+        out.extend_from_slice(value);
+    }
+
+    pub fn encode_signature(r: &[u8], s: &[u8]) -> Vec<u8> {
+        let mut integers = Vec::new();
+        push_integer(&mut integers, r);
+        push_integer(&mut integers, s);
+        let mut out = Vec::with_capacity(integers.len() + 3);
+        out.push(SEQUENCE);
+        push_length(&mut out, integers.len());
+        out.extend_from_slice(&integers);
+        out
+    }
+
+    /// Reads a TLV with the given tag and returns the value and the remaining data
+    fn read(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+        let (&actual_tag, data) = data.split_first()?;
+        let (&len, data) = data.split_first()?;
+        let (len, data) = match len {
+            0x81 => {
+                let (&len, data) = data.split_first()?;
+                (usize::from(len), data)
+            }
+            len if len < 0x80 => (usize::from(len), data),
+            _ => return None,
+        };
+        if actual_tag != tag || data.len() < len {
+            return None;
+        }
+        Some(data.split_at(len))
+    }
+
+    pub fn decode_signature(data: &[u8]) -> Option<(&[u8], &[u8])> {
+        let (integers, rest) = read(data, SEQUENCE)?;
+        let (r, integers) = read(integers, INTEGER)?;
+        let (s, integers) = read(integers, INTEGER)?;
+        (rest.is_empty() && integers.is_empty()).then_some((r, s))
+    }
+}
+
+macro_rules! impl_brainpool {
+    ($name:ident, $params:expr, $digest:ty, $kind:expr) => {
+        pub struct $name;
+
+        impl Curve for $name {
+            const KIND: key::Kind = $kind;
+
+            fn generate(rng: &mut impl CryptoRngCore) -> Material {
+                let domain = Domain::new(&$params);
+                let mut secret = Material::new();
+                // The material buffer is large enough for the scalars of all supported curves
+                secret.resize_default(domain.size).unwrap();
+                loop {
+                    rng.fill_bytes(&mut secret);
+                    if domain.secret_scalar(&secret).is_ok() {
+                        return secret;
+                    }
+                }
+            }
+
+            fn import_secret_key(data: &[u8]) -> Result<Material, Error> {
+                Domain::new(&$params).secret_scalar(data)?;
+                Material::from_slice(data).map_err(|_| Error::InternalError)
+            }
+
+            fn public_key(secret: &[u8]) -> Result<Material, Error> {
+                let domain = Domain::new(&$params);
+                let secret = domain
+                    .secret_scalar(secret)
+                    .map_err(|_| Error::InternalError)?;
+                domain.compressed(&domain.public_point(&secret)?)
+            }
+
+            fn deserialize_public_key(
+                data: &[u8],
+                format: KeySerialization,
+            ) -> Result<Material, Error> {
+                let domain = Domain::new(&$params);
+                let public = match format {
+                    KeySerialization::Raw => domain.decode_point(&[&[0x04][..], data].concat()),
+                    KeySerialization::Sec1 => domain.decode_point(data),
+                    _ => return Err(Error::InvalidSerializationFormat),
+                }?;
+                domain.compressed(&public)
+            }
+
+            fn serialize_public_key(
+                public: &[u8],
+                format: KeySerialization,
+            ) -> Result<SerializedKey, Error> {
+                let domain = Domain::new(&$params);
+                let point = domain
+                    .decode_point(public)
+                    .map_err(|_| Error::InternalError)?;
+                match format {
+                    // Raw keys are the concatenated coordinates without the SEC1 tag
+                    KeySerialization::Raw => SerializedKey::from_slice(
+                        &[domain.to_bytes(&point.x), domain.to_bytes(&point.y)].concat(),
+                    ),
+                    KeySerialization::Sec1 => {
+                        SerializedKey::from_slice(&domain.compressed(&point)?)
+                    }
+                    _ => return Err(Error::InvalidSerializationFormat),
+                }
+                .map_err(|_| Error::InternalError)
+            }
+
+            fn agree(secret: &[u8], public: &[u8]) -> Result<Material, Error> {
+                let domain = Domain::new(&$params);
+                let secret = domain
+                    .secret_scalar(secret)
+                    .map_err(|_| Error::InternalError)?;
+                let public = domain
+                    .decode_point(public)
+                    .map_err(|_| Error::InternalError)?;
+                let shared = domain
+                    .mul_point(&secret, &public)
+                    .ok_or(Error::InternalError)?;
+                Material::from_slice(&domain.to_bytes(&domain.to_affine(&shared).x))
+                    .map_err(|_| Error::InternalError)
+            }
+
+            fn sign(
+                secret: &[u8],
+                message: &[u8],
+                prehashed: bool,
+                format: SignatureSerialization,
+            ) -> Result<Signature, Error> {
+                Domain::new(&$params).sign::<$digest>(secret, message, prehashed, format)
+            }
+
+            fn verify(
+                public: &[u8],
+                message: &[u8],
+                signature: &[u8],
+                prehashed: bool,
+                format: SignatureSerialization,
+            ) -> Result<bool, Error> {
+                Domain::new(&$params)
+                    .verify::<$digest>(public, message, signature, prehashed, format)
+            }
+        }
+    };
+}
+
+impl_brainpool!(
+    BrainpoolP256R1,
+    BRAINPOOL_P256R1,
+    Sha256,
+    key::Kind::BrainpoolP256R1
+);
+impl_brainpool!(
+    BrainpoolP384R1,
+    BRAINPOOL_P384R1,
+    Sha384,
+    key::Kind::BrainpoolP384R1
+);
+impl_brainpool!(
+    BrainpoolP512R1,
+    BRAINPOOL_P512R1,
+    Sha512,
+    key::Kind::BrainpoolP512R1
+);
@@ -0,0 +1,138 @@
+//! Extended device status.
+//!
+//! The legacy admin-app status is a fixed 5-byte array that cannot be extended without breaking
+//! existing hosts.  The extended status is a CBOR map with short keys and a format version, so
+//! new fields can be added while hosts skip the fields that they do not know.  Hosts that only
+//! request the legacy status still receive the 5-byte form.
+
+use serde::Serialize;
+use trussed::{store::Store, types::Location};
+
+use crate::usage::filesystem;
+
+/// The version of the extended status format
+///
+/// The version is only increased if the meaning of an existing field changes.  Adding fields
+/// does not require a new version.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The cause of the last reset, as reported by the SoC
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetReason {
+    #[default]
+    Unknown = 0,
+    /// Power-on reset, also reported for brown-outs on SoCs that cannot distinguish them
+    PowerOn = 1,
+    /// Reset pin
+    Pin = 2,
+    Watchdog = 3,
+    /// Reset requested by the firmware, e. g. after a reboot command
+    SoftReset = 4,
+    /// CPU lockup, e. g. after a fault in the fault handler
+    Lockup = 5,
+    BrownOut = 6,
+    /// Wakeup from a low-power mode
+    Wakeup = 7,
+}
+
+/// The state of the FIDO large blobs feature
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LargeBlobsStatus {
+    /// Disabled in the config, or the FIDO authenticator is not available
+    #[default]
+    Disabled = 0,
+    Enabled = 1,
+    /// Enabled in the config, but the device does not have an external flash
+    NoExternalFlash = 2,
+    /// Enabled in the config, but the external flash cannot be used because the device is
+    /// powered by NFC
+    NfcPowered = 3,
+}
+
+impl LargeBlobsStatus {
+    pub fn new(enabled: bool, has_external_flash: bool, nfc_powered: bool) -> Self {
+        if !enabled {
+            Self::Disabled
+        } else if !has_external_flash {
+            Self::NoExternalFlash
+        } else if nfc_powered {
+            Self::NfcPowered
+        } else {
+            Self::Enabled
+        }
+    }
+}
+
+/// The firmware version of the SE050
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Se050Version {
+    #[serde(rename = "j")]
+    pub major: u8,
+    #[serde(rename = "n")]
+    pub minor: u8,
+    #[serde(rename = "p")]
+    pub patch: u8,
+}
+
+/// The total and available blocks of a filesystem
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct FilesystemBlocks {
+    #[serde(rename = "t")]
+    pub total: usize,
+    #[serde(rename = "a")]
+    pub available: usize,
+}
+
+impl FilesystemBlocks {
+    pub fn of<S: Store>(store: &S, location: Location) -> Option<Self> {
+        let fs = filesystem(store, location);
+        let available = fs
+            .available_blocks()
+            .map_err(|_err| warn_now!("Failed to read available blocks on {location:?}: {_err:?}"))
+            .ok()?;
+        Some(Self {
+            total: fs.total_blocks(),
+            available,
+        })
+    }
+}
+
+/// The extended status, returned by the admin-app extended status command
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ExtendedStatus {
+    #[serde(rename = "v")]
+    pub format_version: u8,
+    /// The init status flags, see [`InitStatus`][crate::InitStatus]
+    #[serde(rename = "i")]
+    pub init_status: u8,
+    #[serde(rename = "d")]
+    pub variant: u8,
+    /// The filesystem version after the migrations
+    #[serde(rename = "m")]
+    pub migration_version: u32,
+    #[serde(rename = "s")]
+    pub se050_present: bool,
+    #[serde(rename = "sv", skip_serializing_if = "Option::is_none")]
+    pub se050_version: Option<Se050Version>,
+    /// The cause of the last reset, see [`ResetReason`][]
+    #[serde(rename = "r")]
+    pub reset_reason: u8,
+    /// Uptime in milliseconds
+    #[serde(rename = "u")]
+    pub uptime: u64,
+    #[serde(rename = "n")]
+    pub nfc_powered: bool,
+    #[serde(rename = "l")]
+    pub locked: bool,
+    /// The blocks of the internal filesystem, `None` if powered by NFC
+    #[serde(rename = "fi", skip_serializing_if = "Option::is_none")]
+    pub ifs_blocks: Option<FilesystemBlocks>,
+    /// The blocks of the external filesystem, `None` if powered by NFC
+    #[serde(rename = "fe", skip_serializing_if = "Option::is_none")]
+    pub efs_blocks: Option<FilesystemBlocks>,
+    /// Whether FIDO large blobs are available, see [`LargeBlobsStatus`][]
+    #[serde(rename = "b")]
+    pub large_blobs: u8,
+}
@@ -0,0 +1,194 @@
+//! Storage usage of the clients on the internal and external filesystem.
+//!
+//! The usage is an upper bound: every file is counted with at least one block and every
+//! directory with a metadata pair, even though littlefs inlines small files into the metadata.
+
+use heapless::Vec;
+use littlefs2_core::{path, DynFilesystem, Path};
+use serde::Serialize;
+use trussed::{store::Store, types::Location};
+
+/// Maximum directory depth that is walked to calculate the usage
+const MAX_DEPTH: usize = 8;
+/// Blocks used by the metadata pair of a directory
+const DIRECTORY_BLOCKS: usize = 2;
+
+/// The directories that are included in the usage report
+///
+/// The trussed-auth state of the apps is stored in their client directory.  The `backend-auth`
+/// directory only contains the global state of the auth backend.
+const REPORTED_DIRS: &[&Path] = &[
+    path!("admin"),
+    path!("attn"),
+    path!("backend-auth"),
+    path!("fido"),
+    path!("opcard"),
+    path!("piv"),
+    path!("secrets"),
+    path!("webcrypt"),
+];
+
+/// Storage used by a client on one filesystem
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Usage {
+    #[serde(rename = "f")]
+    pub files: usize,
+    #[serde(rename = "s")]
+    pub bytes: usize,
+    #[serde(rename = "b")]
+    pub blocks: usize,
+}
+
+impl Usage {
+    /// Walks the directory of the given client and sums up the size of all files
+    pub fn of_client(fs: &dyn DynFilesystem, client: &Path) -> littlefs2_core::Result<Self> {
+        let mut usage = Self::default();
+        if fs.exists(client) {
+            usage.walk(fs, client, block_size(fs), 0)?;
+        }
+        Ok(usage)
+    }
+
+    fn walk(
+        &mut self,
+        fs: &dyn DynFilesystem,
+        dir: &Path,
+        block_size: usize,
+        depth: usize,
+    ) -> littlefs2_core::Result<()> {
+        self.blocks += DIRECTORY_BLOCKS;
+        fs.read_dir_and_then(dir, |entries| {
+            // skip "." and ".."
+            for entry in entries.skip(2) {
+                let entry = entry?;
+                let metadata = entry.metadata();
+                if metadata.is_dir() {
+                    if depth < MAX_DEPTH {
+                        self.walk(fs, entry.path(), block_size, depth + 1)?;
+                    }
+                } else {
+                    self.files += 1;
+                    self.bytes += metadata.len();
+                    self.blocks += blocks(metadata.len(), block_size);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Storage used by a client on the internal and external filesystem
+///
+/// The usage is `None` if the directory could not be read or if the external filesystem was not
+/// traversed because the device is powered by NFC.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ClientUsage {
+    #[serde(rename = "c")]
+    pub client: &'static str,
+    #[serde(rename = "i")]
+    pub internal: Option<Usage>,
+    #[serde(rename = "e")]
+    pub external: Option<Usage>,
+}
+
+/// Storage usage report for all clients, returned by the admin-app storage usage command
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StorageUsage {
+    #[serde(rename = "c")]
+    pub clients: Vec<ClientUsage, { REPORTED_DIRS.len() }>,
+}
+
+impl StorageUsage {
+    /// Collects the usage of all reported clients
+    ///
+    /// Like during boot, the external filesystem is not traversed if powered by NFC.
+    pub fn collect<S: Store>(store: &S, nfc_powered: bool) -> Self {
+        let ifs = filesystem(store, Location::Internal);
+        let efs = (!nfc_powered).then(|| filesystem(store, Location::External));
+        let clients = REPORTED_DIRS
+            .iter()
+            .map(|&client| {
+                let internal = Usage::of_client(ifs, client)
+                    .map_err(|_err| {
+                        warn_now!("Failed to read usage of {client:?} on IFS: {_err:?}");
+                    })
+                    .ok();
+                let external = efs.and_then(|efs| {
+                    Usage::of_client(efs, client)
+                        .map_err(|_err| {
+                            warn_now!("Failed to read usage of {client:?} on EFS: {_err:?}");
+                        })
+                        .ok()
+                });
+                ClientUsage {
+                    client: client.as_str(),
+                    internal,
+                    external,
+                }
+            })
+            .collect();
+        Self { clients }
+    }
+}
+
+pub fn filesystem<S: Store>(store: &S, location: Location) -> &dyn DynFilesystem {
+    match location {
+        Location::Internal => store.ifs(),
+        Location::External => store.efs(),
+        Location::Volatile => store.vfs(),
+    }
+}
+
+/// The index of the location, in the order of the variants of [`Location`][]
+pub fn location_id(location: Location) -> u8 {
+    match location {
+        Location::Volatile => 0,
+        Location::Internal => 1,
+        Location::External => 2,
+    }
+}
+
+pub fn block_size(fs: &dyn DynFilesystem) -> usize {
+    fs.total_space() / fs.total_blocks().max(1)
+}
+
+pub fn blocks(len: usize, block_size: usize) -> usize {
+    len.div_ceil(block_size.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use trussed::{
+        virt::{self, Ram},
+        Platform as _,
+    };
+
+    use super::*;
+
+    #[test]
+    fn collect_nfc_powered() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            for location in [Location::Internal, Location::External] {
+                let fs = filesystem(&store, location);
+                fs.create_dir_all(path!("/fido/dat")).unwrap();
+                fs.write(path!("/fido/dat/file"), b"data").unwrap();
+            }
+            let fido = |usage: &StorageUsage| {
+                *usage
+                    .clients
+                    .iter()
+                    .find(|usage| usage.client == "fido")
+                    .unwrap()
+            };
+
+            let usage = fido(&StorageUsage::collect(&store, false));
+            assert_eq!(usage.internal.unwrap().files, 1);
+            assert_eq!(usage.external.unwrap().bytes, 4);
+
+            let usage = fido(&StorageUsage::collect(&store, true));
+            assert_eq!(usage.internal.unwrap().files, 1);
+            assert_eq!(usage.external, None);
+        });
+    }
+}
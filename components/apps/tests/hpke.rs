@@ -0,0 +1,73 @@
+#![cfg(all(feature = "backend-software-hpke", not(feature = "se050")))]
+
+use apps::{Backend, Dispatch};
+use trussed::{
+    backend::BackendId,
+    client::CryptoClient as _,
+    syscall, try_syscall,
+    types::{Bytes, Location},
+    virt::{self, Ram},
+};
+use trussed_hpke::HpkeClient as _;
+
+const BACKENDS_HPKE: &[BackendId<Backend>] =
+    &[BackendId::Custom(Backend::SoftwareHpke), BackendId::Core];
+const BACKENDS_CORE: &[BackendId<Backend>] = &[BackendId::Core];
+
+fn dispatch() -> Dispatch {
+    #[cfg(feature = "backend-auth")]
+    return Dispatch::new(apps::AUTH_LOCATION);
+    #[cfg(not(feature = "backend-auth"))]
+    Dispatch::new()
+}
+
+#[test]
+fn hpke_seal_open() {
+    virt::with_platform(Ram::default(), |platform| {
+        platform.run_client_with_backends("test", dispatch(), BACKENDS_HPKE, |mut client| {
+            let secret_key = syscall!(client.generate_x255_secret_key(Location::Volatile)).key;
+            let public_key =
+                syscall!(client.derive_x255_public_key(secret_key, Location::Volatile)).key;
+
+            let plaintext = Bytes::from_slice(b"secret message").unwrap();
+            let aad = Bytes::from_slice(b"additional data").unwrap();
+            let info = Bytes::from_slice(b"info").unwrap();
+
+            let sealed = syscall!(client.hpke_seal(
+                public_key,
+                plaintext.clone(),
+                aad.clone(),
+                info.clone()
+            ));
+            assert_ne!(sealed.ciphertext, plaintext);
+
+            let opened = syscall!(client.hpke_open(
+                secret_key,
+                sealed.enc,
+                sealed.ciphertext,
+                aad,
+                info,
+                sealed.tag
+            ));
+            assert_eq!(opened.plaintext, plaintext);
+        })
+    });
+}
+
+#[test]
+fn hpke_not_selected() {
+    virt::with_platform(Ram::default(), |platform| {
+        platform.run_client_with_backends("test", dispatch(), BACKENDS_CORE, |mut client| {
+            let secret_key = syscall!(client.generate_x255_secret_key(Location::Volatile)).key;
+            let public_key =
+                syscall!(client.derive_x255_public_key(secret_key, Location::Volatile)).key;
+            let result = try_syscall!(client.hpke_seal(
+                public_key,
+                Bytes::from_slice(b"secret message").unwrap(),
+                Bytes::new(),
+                Bytes::new()
+            ));
+            assert!(result.is_err());
+        })
+    });
+}
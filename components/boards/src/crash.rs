@@ -0,0 +1,160 @@
+//! Crash information that survives a reset.
+//!
+//! The panic and hard fault handlers write the crash to a `.uninit` RAM region that is not
+//! initialized by the runtime.  On the next boot, [`take`][] reads it back so that it can be
+//! stored in the crash log on the internal filesystem.
+//!
+//! The record is only removed by [`mark_boot_successful`][].  Until then, it also counts the
+//! crashes since the last successful boot so that the handlers can stop resetting the device if
+//! it crashes on every boot, see [`MAX_CRASH_RESETS`][].
+
+use core::{
+    fmt::{self, Write as _},
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr::{addr_of_mut, read_volatile, write_volatile},
+    time::Duration,
+};
+
+use apps::{Crash, CrashKind};
+use cortex_m_rt::ExceptionFrame;
+
+const MAX_MESSAGE_LEN: usize = Crash::MAX_MESSAGE_LEN;
+const REGISTER_COUNT: usize = Crash::REGISTER_COUNT;
+
+/// Marks a valid crash, RAM content after a power-on is random
+const MAGIC: u32 = 0x4352_4153;
+
+/// The number of crashes since the last successful boot after which the device is no longer
+/// reset, so that the watchdog starts the bootloader instead
+pub const MAX_CRASH_RESETS: u8 = 3;
+
+/// The uptime after which a boot is considered successful, see [`mark_boot_successful`][]
+pub const SUCCESSFUL_BOOT_UPTIME: Duration = Duration::from_secs(10);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawCrash {
+    magic: u32,
+    kind: u8,
+    message_len: u8,
+    /// Set by [`take`][] so that the crash is only stored once
+    taken: u8,
+    /// The number of crashes since the last successful boot, including this one
+    count: u8,
+    message: [u8; MAX_MESSAGE_LEN],
+    registers: [u32; REGISTER_COUNT],
+}
+
+#[link_section = ".uninit.CRASH"]
+static mut CRASH: MaybeUninit<RawCrash> = MaybeUninit::uninit();
+
+/// Truncates the formatted panic message to the size of the buffer
+struct MessageWriter {
+    message: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl fmt::Write for MessageWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(MAX_MESSAGE_LEN - self.len);
+        self.message[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+fn read() -> Option<RawCrash> {
+    // All fields are plain integers, so any RAM content is a valid value
+    let raw = unsafe { read_volatile(addr_of_mut!(CRASH).cast::<RawCrash>()) };
+    (raw.magic == MAGIC).then_some(raw)
+}
+
+/// Stores the crash and returns true if the device should be reset
+fn write(
+    kind: CrashKind,
+    message: [u8; MAX_MESSAGE_LEN],
+    message_len: u8,
+    registers: [u32; REGISTER_COUNT],
+) -> bool {
+    let count = read().map(|raw| raw.count.saturating_add(1)).unwrap_or(1);
+    let crash = RawCrash {
+        magic: MAGIC,
+        kind: kind as u8,
+        message_len,
+        taken: 0,
+        count,
+        message,
+        registers,
+    };
+    unsafe { write_volatile(addr_of_mut!(CRASH).cast::<RawCrash>(), crash) }
+    count <= MAX_CRASH_RESETS
+}
+
+/// Stores a panic and returns true if the device should be reset, see [`MAX_CRASH_RESETS`][]
+pub fn record_panic(info: &PanicInfo) -> bool {
+    let mut writer = MessageWriter {
+        message: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+    write!(writer, "{info}").ok();
+    write(
+        CrashKind::Panic,
+        writer.message,
+        writer.len as u8,
+        [0; REGISTER_COUNT],
+    )
+}
+
+/// Stores a hard fault and returns true if the device should be reset, see
+/// [`MAX_CRASH_RESETS`][]
+pub fn record_hard_fault(ef: &ExceptionFrame) -> bool {
+    write(
+        CrashKind::HardFault,
+        [0; MAX_MESSAGE_LEN],
+        0,
+        [
+            ef.r0(),
+            ef.r1(),
+            ef.r2(),
+            ef.r3(),
+            ef.r12(),
+            ef.lr(),
+            ef.pc(),
+            ef.xpsr(),
+        ],
+    )
+}
+
+/// Returns the crash recorded before the last reset if it has not been taken yet
+///
+/// The crash stays in RAM until [`mark_boot_successful`][] is called so that further crashes
+/// are counted.
+pub fn take() -> Option<Crash> {
+    let raw = read().filter(|raw| raw.taken == 0)?;
+    unsafe {
+        let ptr = addr_of_mut!(CRASH).cast::<RawCrash>();
+        write_volatile(addr_of_mut!((*ptr).taken), 1);
+    }
+    let kind = if raw.kind == CrashKind::Panic as u8 {
+        CrashKind::Panic
+    } else {
+        CrashKind::HardFault
+    };
+    let message_len = usize::from(raw.message_len).min(MAX_MESSAGE_LEN);
+    Some(Crash::new(kind, &raw.message[..message_len], raw.registers))
+}
+
+/// Removes the crash from RAM once it has been taken and resets the crash count
+///
+/// This is called periodically by the user interface once the uptime exceeds
+/// [`SUCCESSFUL_BOOT_UPTIME`][].  A crash that has not been taken yet, for example because the
+/// device is powered by NFC, is kept.
+pub fn mark_boot_successful() {
+    if read().is_some_and(|raw| raw.taken != 0) {
+        unsafe {
+            let ptr = addr_of_mut!(CRASH).cast::<RawCrash>();
+            write_volatile(addr_of_mut!((*ptr).magic), 0);
+        }
+    }
+}
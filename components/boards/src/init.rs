@@ -181,6 +181,15 @@ pub fn init_apps<B: Board>(
 ) -> (Apps<B>, Endpoints) {
     let mut admin = AdminData::new(*store, B::Soc::VARIANT, version, version_string);
     admin.init_status = init_status;
+    admin.nfc_powered = nfc_powered;
+    admin.reset_reason = soc.reset_reason();
+    // Writing to the filesystem is avoided if powered by NFC, so the crash is kept in RAM until
+    // the next boot with USB power
+    if !nfc_powered {
+        if let Some(crash) = crate::crash::take() {
+            apps::CrashLog::record(store, &crash);
+        }
+    }
     if !nfc_powered {
         if let Ok(ifs_blocks) = store.ifs().available_blocks() {
             if let Ok(ifs_blocks) = u8::try_from(ifs_blocks) {
@@ -211,7 +220,7 @@ pub fn init_apps<B: Board>(
 
     let runner = Runner {
         uuid: *soc.uuid(),
-        is_efs_available: !nfc_powered,
+        is_efs_available: B::HAS_EXTERNAL_FLASH && !nfc_powered,
         _marker: Default::default(),
     };
     let data = Data {
@@ -240,13 +249,23 @@ fn init_se050<
     delay: D,
     dev_rng: &mut R,
     init_status: &mut InitStatus,
-) -> (se05x::se05x::Se05X<I2C, D>, [u8; 32]) {
+) -> (
+    se05x::se05x::Se05X<I2C, D>,
+    [u8; 32],
+    Option<apps::Se050Version>,
+) {
     use se05x::se05x::commands::GetRandom;
 
     let seed: [u8; 32] = dev_rng.gen();
     let mut se050 = se05x::se05x::Se05X::new(i2c, 0x48, delay);
+    let mut version = None;
     let seed = (|| {
-        se050.enable()?;
+        let atr = se050.enable()?;
+        version = Some(apps::Se050Version {
+            major: atr.major,
+            minor: atr.minor,
+            patch: atr.patch,
+        });
         let buf = &mut [0; 100];
         let se050_rand = se050.run_command(&GetRandom { length: 32.into() }, buf)?;
         let mut s: [u8; 32] = se050_rand
@@ -263,7 +282,7 @@ fn init_se050<
         *init_status |= InitStatus::SE050_ERROR;
         seed
     });
-    (se050, seed)
+    (se050, seed, version)
 }
 
 pub fn init_trussed<B: Board, R: CryptoRng + RngCore>(
@@ -275,11 +294,11 @@ pub fn init_trussed<B: Board, R: CryptoRng + RngCore>(
     #[cfg(feature = "se050")] se050: Option<(B::Twi, B::Se050Timer)>,
 ) -> Trussed<B> {
     #[cfg(feature = "se050")]
-    let (se050, seed) = if let Some((twi, timer)) = se050 {
-        let (se050, seed) = init_se050(twi, timer, dev_rng, init_status);
-        (Some(se050), Some(seed))
+    let (se050, seed, se050_version) = if let Some((twi, timer)) = se050 {
+        let (se050, seed, version) = init_se050(twi, timer, dev_rng, init_status);
+        (Some(se050), Some(seed), version)
     } else {
-        (None, None)
+        (None, None, None)
     };
     #[cfg(not(feature = "se050"))]
     let seed = None;
@@ -296,7 +315,8 @@ pub fn init_trussed<B: Board, R: CryptoRng + RngCore>(
     };
 
     #[cfg(feature = "trussed-auth")]
-    let dispatch = if let Some(hw_key) = hw_key {
+    #[allow(unused_mut)]
+    let mut dispatch = if let Some(hw_key) = hw_key {
         Dispatch::with_hw_key(
             AUTH_LOCATION,
             trussed::types::Bytes::from_slice(hw_key).unwrap(),
@@ -311,12 +331,17 @@ pub fn init_trussed<B: Board, R: CryptoRng + RngCore>(
         )
     };
     #[cfg(not(feature = "trussed-auth"))]
-    let dispatch = Dispatch::new(
+    #[allow(unused_mut)]
+    let mut dispatch = Dispatch::new(
         #[cfg(feature = "se050")]
         AUTH_LOCATION,
         #[cfg(feature = "se050")]
         se050,
     );
+    #[cfg(feature = "se050")]
+    if let Some(version) = se050_version {
+        dispatch.set_se050_version(version);
+    }
 
     Trussed::with_dispatch(platform, dispatch)
 }
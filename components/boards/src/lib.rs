@@ -5,6 +5,7 @@ delog::generate_macros!();
 
 use cortex_m_rt::ExceptionFrame;
 
+pub mod crash;
 pub mod flash;
 pub mod init;
 pub mod runtime;
@@ -21,7 +22,7 @@ pub mod nkpk;
 
 use core::marker::PhantomData;
 
-use apps::Dispatch;
+use apps::{Dispatch, PreserveRule};
 #[cfg(feature = "se050")]
 use embedded_hal::blocking::delay::DelayUs;
 use littlefs2::{
@@ -62,6 +63,10 @@ pub trait Board: StoragePointers {
 
     const BOARD_NAME: &'static str;
     const HAS_NFC: bool;
+    /// Whether the board has a persistent external flash
+    const HAS_EXTERNAL_FLASH: bool = true;
+    /// The files that are preserved by a factory reset
+    const PRESERVE_POLICY: &'static [PreserveRule] = apps::DEFAULT_PRESERVE_POLICY;
 
     fn prepare_ifs(ifs: &mut Self::InternalStorage) {
         let _ = ifs;
@@ -92,13 +97,24 @@ impl<B: Board> apps::Runner for Runner<B> {
     type Twi = B::Twi;
     type Se050Timer = B::Se050Timer;
 
+    const PRESERVE_POLICY: &'static [PreserveRule] = B::PRESERVE_POLICY;
+
     fn uuid(&self) -> [u8; 16] {
         self.uuid
     }
 
+    fn has_external_flash(&self) -> bool {
+        B::HAS_EXTERNAL_FLASH
+    }
+
     fn is_efs_available(&self) -> bool {
         self.is_efs_available
     }
+
+    #[cfg(feature = "se050")]
+    fn is_se050_backend_available(&self) -> bool {
+        true
+    }
 }
 
 pub struct RunnerPlatform<B: Board> {
@@ -150,20 +166,32 @@ impl<S: Soc> Syscall for RunnerSyscall<S> {
     }
 }
 
-pub fn handle_panic<B: Board>(_info: &core::panic::PanicInfo) -> ! {
-    error_now!("{}", _info);
+pub fn handle_panic<B: Board>(info: &core::panic::PanicInfo) -> ! {
+    error_now!("{}", info);
     #[cfg(feature = "rtt-target")]
-    rtt_target::rprint!("{}", _info);
-    B::Led::set_panic_led();
-    loop {
-        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-    }
+    rtt_target::rprint!("{}", info);
+    let reset = crash::record_panic(info);
+    halt_after_crash::<B>(reset)
 }
 
-pub fn handle_hard_fault<B: Board>(_ef: &ExceptionFrame) -> ! {
+pub fn handle_hard_fault<B: Board>(ef: &ExceptionFrame) -> ! {
     #[cfg(feature = "rtt-target")]
-    rtt_target::rprint!("HardFault: {:?}", _ef);
+    rtt_target::rprint!("HardFault: {:?}", ef);
+    let reset = crash::record_hard_fault(ef);
+    halt_after_crash::<B>(reset)
+}
+
+/// Resets the device so that the crash is moved to the crash log during the next boot
+///
+/// A watchdog reset would start the bootloader on most runners, so the crash would not be stored
+/// until the bootloader is left.  If the device crashed too often since the last successful boot,
+/// it waits for the watchdog instead of resetting in a loop so that it can be recovered with the
+/// bootloader, see [`crash::MAX_CRASH_RESETS`][].
+fn halt_after_crash<B: Board>(reset: bool) -> ! {
     B::Led::set_panic_led();
+    if reset {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
     loop {
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
     }
@@ -33,6 +33,7 @@ impl Board for NKPK {
 
     const BOARD_NAME: &'static str = "NKPK";
     const HAS_NFC: bool = false;
+    const HAS_EXTERNAL_FLASH: bool = false;
 
     fn prepare_ifs(ifs: &mut Self::InternalStorage) {
         ifs.format_journal_blocks();
@@ -2,7 +2,7 @@ use cortex_m::interrupt::InterruptNumber;
 use embedded_time::duration::Milliseconds;
 use usb_device::bus::UsbBus;
 
-use apps::{Reboot, Variant};
+use apps::{Reboot, ResetReason, Variant};
 
 use crate::ui::Clock;
 
@@ -26,4 +26,7 @@ pub trait Soc: Reboot + 'static {
     const VARIANT: Variant;
 
     fn uuid(&self) -> &Uuid;
+
+    /// Returns the cause of the last reset
+    fn reset_reason(&self) -> ResetReason;
 }
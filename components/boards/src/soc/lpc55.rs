@@ -7,7 +7,7 @@ use embedded_time::duration::Milliseconds;
 use lpc55_hal::{
     drivers::timer::Timer,
     peripherals::{ctimer, flash, rtc::Rtc, syscon},
-    raw::{Interrupt, SCB},
+    raw::{Interrupt, PMC, SCB},
     traits::flash::WriteErase,
     typestates::init_state::Enabled,
 };
@@ -19,22 +19,18 @@ type UsbPeripheral = lpc55_hal::peripherals::usbhs::EnabledUsbhsDevice;
 
 pub struct Lpc55 {
     uuid: Uuid,
+    reset_reason: apps::ResetReason,
 }
 
 impl Lpc55 {
-    pub fn new() -> Self {
+    pub fn new(reset_reason: apps::ResetReason) -> Self {
         Self {
             uuid: lpc55_hal::uuid(),
+            reset_reason,
         }
     }
 }
 
-impl Default for Lpc55 {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl Soc for Lpc55 {
     type UsbBus = lpc55_hal::drivers::UsbBus<UsbPeripheral>;
     type Clock = RtcClock;
@@ -50,6 +46,86 @@ impl Soc for Lpc55 {
     fn uuid(&self) -> &Uuid {
         &self.uuid
     }
+
+    fn reset_reason(&self) -> apps::ResetReason {
+        self.reset_reason
+    }
+}
+
+/// The reset cause bits of the always-on register of the PMC
+#[derive(Debug)]
+pub struct ResetReason {
+    pub por: bool,
+    pub padreset: bool,
+    pub borreset: bool,
+    pub systemreset: bool,
+    /// Reset from watchdog
+    pub wdtreset: bool,
+    pub swrreset: bool,
+    pub dpdreset: bool,
+    pub cdogreset: bool,
+}
+
+impl From<&ResetReason> for apps::ResetReason {
+    fn from(reason: &ResetReason) -> Self {
+        // The watchdog bit is still set if the bootloader reset the device after a watchdog
+        // reset, so the watchdog reset is reported
+        if reason.wdtreset || reason.cdogreset {
+            Self::Watchdog
+        } else if reason.borreset {
+            Self::BrownOut
+        } else if reason.systemreset || reason.swrreset {
+            Self::SoftReset
+        } else if reason.padreset {
+            Self::Pin
+        } else if reason.dpdreset {
+            Self::Wakeup
+        } else if reason.por {
+            Self::PowerOn
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// Reads the cause of the last reset
+///
+/// The bits are kept across resets until they are cleared with [`clear_reset_reason`][].
+pub fn reset_reason(pmc: &PMC) -> ResetReason {
+    let read = pmc.aoreg1.read();
+    debug_now!("Reset Reason: {:b}", read.bits());
+    ResetReason {
+        por: read.por().bit(),
+        padreset: read.padreset().bit(),
+        borreset: read.borreset().bit(),
+        systemreset: read.systemreset().bit(),
+        wdtreset: read.wdtreset().bit(),
+        swrreset: read.swrreset().bit(),
+        dpdreset: read.dpdreset().bit(),
+        cdogreset: read.cdogreset().bit(),
+    }
+}
+
+/// Clears the reset cause bits so that the next boot only reports the cause of the next reset
+pub fn clear_reset_reason(pmc: &PMC) {
+    pmc.aoreg1.modify(|_, w| {
+        w.por()
+            .clear_bit()
+            .padreset()
+            .clear_bit()
+            .borreset()
+            .clear_bit()
+            .systemreset()
+            .clear_bit()
+            .wdtreset()
+            .clear_bit()
+            .swrreset()
+            .clear_bit()
+            .dpdreset()
+            .clear_bit()
+            .cdogreset()
+            .clear_bit()
+    });
 }
 
 impl apps::Reboot for Lpc55 {
@@ -15,6 +15,7 @@ pub mod rtic_monotonic;
 
 pub struct Nrf52 {
     uuid: Uuid,
+    reset_reason: apps::ResetReason,
 }
 
 impl Soc for Nrf52 {
@@ -32,6 +33,10 @@ impl Soc for Nrf52 {
     fn uuid(&self) -> &Uuid {
         &self.uuid
     }
+
+    fn reset_reason(&self) -> apps::ResetReason {
+        self.reset_reason
+    }
 }
 
 impl apps::Reboot for Nrf52 {
@@ -55,7 +60,11 @@ impl apps::Reboot for Nrf52 {
     }
 }
 
-pub fn init_bootup(ficr: &nrf52840_pac::FICR, uicr: &nrf52840_pac::UICR) -> Nrf52 {
+pub fn init_bootup(
+    ficr: &nrf52840_pac::FICR,
+    uicr: &nrf52840_pac::UICR,
+    reset_reason: &ResetReason,
+) -> Nrf52 {
     let deviceid0 = ficr.deviceid[0].read().bits();
     let deviceid1 = ficr.deviceid[1].read().bits();
 
@@ -99,7 +108,10 @@ pub fn init_bootup(ficr: &nrf52840_pac::FICR, uicr: &nrf52840_pac::UICR) -> Nrf5
         info!("UICR APPROTECT is DISABLED!");
     };
 
-    Nrf52 { uuid }
+    Nrf52 {
+        uuid,
+        reset_reason: reset_reason.into(),
+    }
 }
 
 pub type UsbClockType = Clocks<
@@ -147,10 +159,34 @@ pub struct ResetReason {
     pub vbus: bool,
 }
 
+impl From<&ResetReason> for apps::ResetReason {
+    fn from(reason: &ResetReason) -> Self {
+        // Both flags are set if a watchdog reset was followed by a soft reset before the flags
+        // were cleared, so the watchdog reset is reported
+        if reason.dog {
+            Self::Watchdog
+        } else if reason.sreq {
+            Self::SoftReset
+        } else if reason.lockup {
+            Self::Lockup
+        } else if reason.resetpin {
+            Self::Pin
+        } else if reason.off || reason.lpcomp || reason.dif || reason.nfc || reason.vbus {
+            Self::Wakeup
+        } else {
+            // No flag is set after a power-on or brown-out reset
+            Self::PowerOn
+        }
+    }
+}
+
+/// Reads the cause of the last reset
+///
+/// The flags are kept across resets until they are cleared with [`clear_reset_reason`][].
 pub fn reset_reason(reset_reason: &RESETREAS) -> ResetReason {
     debug_now!("Reset Reason: {:b}", reset_reason.read().bits());
     let read = reset_reason.read();
-    let res = ResetReason {
+    ResetReason {
         resetpin: read.resetpin().bits(),
         dog: read.dog().bits(),
         sreq: read.sreq().bits(),
@@ -160,9 +196,14 @@ pub fn reset_reason(reset_reason: &RESETREAS) -> ResetReason {
         dif: read.dif().bits(),
         nfc: read.nfc().bits(),
         vbus: read.vbus().bits(),
-    };
-    reset_reason.write(|w| w);
-    res
+    }
+}
+
+/// Clears the reset flags so that the next boot only reports the cause of the next reset
+pub fn clear_reset_reason(reset_reason: &RESETREAS) {
+    // The flags are cleared by writing 1 to them
+    let bits = reset_reason.read().bits();
+    reset_reason.write(|w| unsafe { w.bits(bits) });
 }
 
 pub fn init_watchdog(wdt: WDT) -> Result<wdt::Parts<(WatchdogHandle<Hdl0>,)>, WDT> {
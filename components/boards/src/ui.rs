@@ -106,6 +106,9 @@ impl<C: Clock, P: UserPresence, L: RgbLed> platform::UserInterface for UserInter
 
     fn refresh(&mut self) {
         let uptime = self.uptime();
+        if uptime >= crate::crash::SUCCESSFUL_BOOT_UPTIME {
+            crate::crash::mark_boot_successful();
+        }
         self.refresh_ui(uptime);
     }
 
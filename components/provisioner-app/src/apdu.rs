@@ -26,6 +26,7 @@ impl From<Error> for Status {
             Error::IncorrectDataParameter => Status::IncorrectDataParameter,
             Error::NotEnoughMemory => Status::NotEnoughMemory,
             Error::NotFound => Status::NotFound,
+            Error::SecurityStatusNotSatisfied => Status::SecurityStatusNotSatisfied,
         }
     }
 }
@@ -6,6 +6,8 @@
 //! attestation keys.
 //! It allows generating Trussed device attestation keys and obtaining their public keys,
 //! to then generate and inject attn certs from a given root or intermedidate CA.
+//! Certificates on the internal filesystem can be read back and deleted, and directories can
+//! be listed, to verify the provisioning.
 //!
 //! See `solo2-cli` for usage.
 #![no_std]
@@ -17,9 +19,11 @@ mod ctaphid;
 extern crate delog;
 generate_macros!();
 
-use core::convert::TryFrom;
+use core::convert::{TryFrom, TryInto};
 use heapless::Vec;
 use littlefs2::{
+    io::OpenSeekFrom,
+    object_safe::DynFilesystem,
     path,
     path::{Path, PathBuf},
 };
@@ -40,6 +44,9 @@ pub enum Instruction {
     WriteBinary,
 
     WriteFile,
+    ReadFile,
+    ListDirectory,
+    DeleteFile,
 
     BootToBootrom,
     ReformatFilesystem,
@@ -65,6 +72,9 @@ impl TryFrom<u8> for Instruction {
             0xd0 => Self::WriteBinary,
 
             0xbf => Self::WriteFile,
+            0xb8 => Self::ReadFile,
+            0xbe => Self::ListDirectory,
+            0xb4 => Self::DeleteFile,
 
             0x51 => Self::BootToBootrom,
             0xbd => Self::ReformatFilesystem,
@@ -90,6 +100,7 @@ pub enum Error {
     IncorrectDataParameter,
     NotEnoughMemory,
     NotFound,
+    SecurityStatusNotSatisfied,
 }
 
 type Uuid = [u8; 16];
@@ -104,6 +115,12 @@ const FILENAME_P256_CERT: &Path = path!("/attn/x5c/01");
 const FILENAME_ED255_CERT: &Path = path!("/attn/x5c/02");
 const FILENAME_X255_CERT: &Path = path!("/attn/x5c/03");
 
+/// Maximum number of bytes read from a file per ReadFile request
+const READ_CHUNK_LEN: usize = 1024;
+
+/// Directories with the files that can be read and deleted
+const CERTIFICATE_DIRS: &[&str] = &["/attn/x5c/", "/fido/x5c/"];
+
 enum SelectedBuffer {
     Filename,
     File,
@@ -212,6 +229,9 @@ where
                     Err(Error::IncorrectDataParameter)
                 }
             }
+            Instruction::ReadFile => read_file(self.store.ifs(), data, reply),
+            Instruction::ListDirectory => list_directory(self.store.ifs(), data, reply),
+            Instruction::DeleteFile => delete_file(self.store.ifs(), data),
             Instruction::GenerateP256Key => {
                 use p256_cortex_m4::{Keypair, SecretKey};
                 info!("GenerateP256Key");
@@ -411,3 +431,301 @@ where
         }
     }
 }
+
+/// Reads a chunk of a file
+///
+/// The request contains the offset as a big-endian u32 followed by the path.  The reply
+/// contains the file length as a big-endian u32 followed by the data starting at the offset.
+/// The chunk is limited by the size of the reply buffer, so the file must be read with
+/// increasing offsets until the file length is reached.  Only certificates can be read, see
+/// [`is_certificate`][].
+fn read_file<const N: usize>(
+    fs: &dyn DynFilesystem,
+    data: &[u8],
+    reply: &mut Vec<u8, N>,
+) -> Result<(), Error> {
+    if data.len() < 4 {
+        return Err(Error::IncorrectDataParameter);
+    }
+    let (offset, path) = data.split_at(4);
+    let offset = u32::from_be_bytes(offset.try_into().unwrap());
+    let path = PathBuf::try_from(path).map_err(|_| Error::IncorrectDataParameter)?;
+    info!("reading file {} at offset {}", path.as_str(), offset);
+
+    if !is_certificate(&path) {
+        return Err(Error::SecurityStatusNotSatisfied);
+    }
+    if !fs.exists(&path) {
+        return Err(Error::NotFound);
+    }
+    let len = fs.metadata(&path).map_err(|_| Error::NotFound)?.len();
+    let available = N.saturating_sub(reply.len() + 4);
+    reply
+        .extend_from_slice(&(len as u32).to_be_bytes())
+        .map_err(|_| Error::NotEnoughMemory)?;
+    if (offset as usize) < len {
+        let (chunk, _) = fs
+            .read_chunk::<READ_CHUNK_LEN>(&path, OpenSeekFrom::Start(offset))
+            .map_err(|_| Error::NotFound)?;
+        reply
+            .extend_from_slice(&chunk[..chunk.len().min(available)])
+            .map_err(|_| Error::NotEnoughMemory)?;
+    }
+    Ok(())
+}
+
+/// Lists a directory
+///
+/// The request contains the index of the first entry as a big-endian u16 followed by the path
+/// of the directory.  The reply contains as many entries as fit into the reply buffer.  Each
+/// entry consists of the type (0 for files, 1 for directories), the file size as a
+/// big-endian u32, the length of the name and the name.  If the reply is empty, all entries
+/// have been listed.  If the first entry does not fit into the reply buffer, an error is
+/// returned.
+fn list_directory<const N: usize>(
+    fs: &dyn DynFilesystem,
+    data: &[u8],
+    reply: &mut Vec<u8, N>,
+) -> Result<(), Error> {
+    if data.len() < 2 {
+        return Err(Error::IncorrectDataParameter);
+    }
+    let (start, path) = data.split_at(2);
+    let start = u16::from_be_bytes(start.try_into().unwrap());
+    let path = PathBuf::try_from(path).map_err(|_| Error::IncorrectDataParameter)?;
+    info!("listing directory {} from entry {}", path.as_str(), start);
+
+    if !fs.exists(&path) {
+        return Err(Error::NotFound);
+    }
+    let mut truncated = false;
+    fs.read_dir_and_then(&path, |entries| {
+        // skip "." and ".."
+        for entry in entries.skip(2 + usize::from(start)) {
+            let entry = entry?;
+            let name = entry.file_name().as_str().as_bytes();
+            let metadata = entry.metadata();
+            if reply.len() + 6 + name.len() > N {
+                truncated = reply.is_empty();
+                break;
+            }
+            reply.push(u8::from(metadata.is_dir())).ok();
+            reply
+                .extend_from_slice(&(metadata.len() as u32).to_be_bytes())
+                .ok();
+            reply.push(name.len() as u8).ok();
+            reply.extend_from_slice(name).ok();
+        }
+        Ok(())
+    })
+    .map_err(|_| Error::IncorrectDataParameter)?;
+    if truncated {
+        // an empty reply would signal that all entries have been listed
+        return Err(Error::NotEnoughMemory);
+    }
+    Ok(())
+}
+
+/// Deletes a file
+///
+/// The request contains the path of the file.  Only certificates can be deleted, see
+/// [`is_certificate`][].
+fn delete_file(fs: &dyn DynFilesystem, data: &[u8]) -> Result<(), Error> {
+    let path = PathBuf::try_from(data).map_err(|_| Error::IncorrectDataParameter)?;
+    info!("deleting file {}", path.as_str());
+    if !is_certificate(&path) {
+        return Err(Error::SecurityStatusNotSatisfied);
+    }
+    if !fs.exists(&path) {
+        return Err(Error::NotFound);
+    }
+    fs.remove(&path).map_err(|_| Error::IncorrectDataParameter)
+}
+
+/// Returns true if the path is a file in one of the [`CERTIFICATE_DIRS`][]
+///
+/// Other files, e. g. keys, salts and the RNG state, must not be read or deleted.
+fn is_certificate(path: &Path) -> bool {
+    CERTIFICATE_DIRS.iter().any(|dir| {
+        path.as_str()
+            .strip_prefix(dir)
+            .map(|name| !name.is_empty() && !name.contains('/') && name != "." && name != "..")
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use trussed::{
+        virt::{self, Ram},
+        Platform as _,
+    };
+
+    use super::*;
+
+    fn request(prefix: &[u8], path: &Path) -> Vec<u8, 256> {
+        let mut data = Vec::new();
+        data.extend_from_slice(prefix).unwrap();
+        data.extend_from_slice(path.as_str().as_bytes()).unwrap();
+        data
+    }
+
+    #[test]
+    fn read_file_chunks() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            fs.create_dir_all(path!("/fido/x5c")).unwrap();
+            fs.write(path!("/fido/x5c/00"), b"certificate").unwrap();
+
+            let mut reply = Vec::<u8, 10>::new();
+            let data = request(&0u32.to_be_bytes(), path!("/fido/x5c/00"));
+            read_file(fs, &data, &mut reply).unwrap();
+            assert_eq!(&reply[..4], &11u32.to_be_bytes());
+            assert_eq!(&reply[4..], b"certif");
+
+            let mut reply = Vec::<u8, 10>::new();
+            let data = request(&6u32.to_be_bytes(), path!("/fido/x5c/00"));
+            read_file(fs, &data, &mut reply).unwrap();
+            assert_eq!(&reply[..4], &11u32.to_be_bytes());
+            assert_eq!(&reply[4..], b"icate");
+
+            let mut reply = Vec::<u8, 10>::new();
+            let data = request(&11u32.to_be_bytes(), path!("/fido/x5c/00"));
+            read_file(fs, &data, &mut reply).unwrap();
+            assert_eq!(&reply[..], &11u32.to_be_bytes());
+        });
+    }
+
+    #[test]
+    fn read_file_errors() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            let mut reply = Vec::<u8, 64>::new();
+
+            let data = request(&0u32.to_be_bytes(), path!("/fido/x5c/00"));
+            assert!(matches!(
+                read_file(fs, &data, &mut reply),
+                Err(Error::NotFound)
+            ));
+            assert!(matches!(
+                read_file(fs, &[0, 0], &mut reply),
+                Err(Error::IncorrectDataParameter)
+            ));
+            assert!(reply.is_empty());
+        });
+    }
+
+    #[test]
+    fn read_delete_restricted() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            for file in [
+                path!("/attn/sec/01"),
+                path!("/fido/sec/00"),
+                path!("/backend-auth/salt"),
+                path!("/fido/backend-auth/pin.00"),
+                path!("/trussed/dat/rng-state.bin"),
+            ] {
+                fs.create_dir_all(&file.parent().unwrap()).unwrap();
+                fs.write(file, b"secret").unwrap();
+
+                let mut reply = Vec::<u8, 64>::new();
+                let data = request(&0u32.to_be_bytes(), file);
+                assert!(matches!(
+                    read_file(fs, &data, &mut reply),
+                    Err(Error::SecurityStatusNotSatisfied)
+                ));
+                assert!(reply.is_empty());
+                assert!(matches!(
+                    delete_file(fs, file.as_str().as_bytes()),
+                    Err(Error::SecurityStatusNotSatisfied)
+                ));
+                assert!(fs.exists(file));
+            }
+
+            assert!(is_certificate(path!("/attn/x5c/01")));
+            assert!(is_certificate(path!("/fido/x5c/00")));
+            assert!(!is_certificate(path!("/fido/x5c")));
+            assert!(!is_certificate(path!("/fido/x5c/")));
+            assert!(!is_certificate(path!("/fido/x5c/..")));
+            assert!(!is_certificate(path!("/fido/x5c/../sec/00")));
+            assert!(!is_certificate(path!("/piv/x5c/00")));
+            assert!(!is_certificate(path!("fido/x5c/00")));
+        });
+    }
+
+    #[test]
+    fn list_directory_entries() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            fs.create_dir_all(path!("/fido/dat")).unwrap();
+            fs.write(path!("/fido/x5c"), b"cert").unwrap();
+
+            let mut reply = Vec::<u8, 64>::new();
+            let data = request(&0u16.to_be_bytes(), path!("/fido"));
+            list_directory(fs, &data, &mut reply).unwrap();
+            assert_eq!(
+                &reply[..],
+                &[1, 0, 0, 0, 0, 3, b'd', b'a', b't', 0, 0, 0, 0, 4, 3, b'x', b'5', b'c'],
+            );
+
+            let mut reply = Vec::<u8, 64>::new();
+            let data = request(&1u16.to_be_bytes(), path!("/fido"));
+            list_directory(fs, &data, &mut reply).unwrap();
+            assert_eq!(&reply[..], &[0, 0, 0, 0, 4, 3, b'x', b'5', b'c']);
+
+            let mut reply = Vec::<u8, 64>::new();
+            let data = request(&2u16.to_be_bytes(), path!("/fido"));
+            list_directory(fs, &data, &mut reply).unwrap();
+            assert!(reply.is_empty());
+
+            let mut reply = Vec::<u8, 12>::new();
+            let data = request(&0u16.to_be_bytes(), path!("/fido"));
+            list_directory(fs, &data, &mut reply).unwrap();
+            assert_eq!(&reply[..], &[1, 0, 0, 0, 0, 3, b'd', b'a', b't']);
+
+            let mut reply = Vec::<u8, 8>::new();
+            let data = request(&0u16.to_be_bytes(), path!("/fido"));
+            assert!(matches!(
+                list_directory(fs, &data, &mut reply),
+                Err(Error::NotEnoughMemory)
+            ));
+            let data = request(&2u16.to_be_bytes(), path!("/fido"));
+            list_directory(fs, &data, &mut reply).unwrap();
+            assert!(reply.is_empty());
+
+            let mut reply = Vec::<u8, 64>::new();
+            let data = request(&0u16.to_be_bytes(), path!("/piv"));
+            assert!(matches!(
+                list_directory(fs, &data, &mut reply),
+                Err(Error::NotFound)
+            ));
+            assert!(matches!(
+                list_directory(fs, &[0], &mut reply),
+                Err(Error::IncorrectDataParameter)
+            ));
+        });
+    }
+
+    #[test]
+    fn delete_file_removes() {
+        virt::with_platform(Ram::default(), |platform| {
+            let store = platform.store();
+            let fs = store.ifs();
+            fs.create_dir_all(path!("/fido/x5c")).unwrap();
+            fs.write(path!("/fido/x5c/00"), b"certificate").unwrap();
+
+            delete_file(fs, b"/fido/x5c/00").unwrap();
+            assert!(!fs.exists(path!("/fido/x5c/00")));
+            assert!(fs.exists(path!("/fido/x5c")));
+            assert!(matches!(
+                delete_file(fs, b"/fido/x5c/00"),
+                Err(Error::NotFound)
+            ));
+        });
+    }
+}
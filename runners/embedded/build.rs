@@ -40,6 +40,20 @@ fn check_build_triplet() -> SocType {
     }
 }
 
+/// The crash record of `boards::crash` is stored in `.uninit` and must survive a reset, so the
+/// section must not be loaded or zeroed by the runtime.  The reset handler of cortex-m-rt only
+/// initializes `.data` and zeroes `.bss`.
+fn check_uninit_section(link_x: &Path) {
+    println!("cargo:rerun-if-changed={}", link_x.display());
+    let script = std::fs::read_to_string(link_x).expect("cannot read cortex-m-rt linker script");
+    if !script.contains(".uninit (NOLOAD)") || !script.contains("*(.uninit .uninit.*);") {
+        panic!(
+            "{} does not define a NOLOAD .uninit section",
+            link_x.display()
+        );
+    }
+}
+
 fn generate_memory_x(outpath: &Path, template: &str, regions: &MemoryRegions) {
     let buildrs_caveat = r#"/* DO NOT EDIT THIS FILE */
 /* This file was generated by build.rs */
@@ -107,7 +121,13 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         .find(|p| p.name.as_str() == "cortex-m-rt");
 
     if let Some(p) = pkg_cortex_m_rt {
-        println!("cargo:rustc-link-arg=-Tcortex-m-rt_{}_link.x", p.version);
+        let link_x = format!("cortex-m-rt_{}_link.x", p.version);
+        check_uninit_section(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../ld")
+                .join(&link_x),
+        );
+        println!("cargo:rustc-link-arg=-T{}", link_x);
     }
 
     Ok(())
@@ -106,7 +106,19 @@ mod app {
 
     #[init(local = [resources: Resources<NK3xN> = Resources::new()])]
     fn init(c: init::Context) -> (SharedResources, LocalResources, init::Monotonics) {
-        let was_reset_from_wwdt = c.device.PMC.aoreg1.read().wdtreset().bit();
+        let reset_reason = lpc55::reset_reason(&c.device.PMC);
+        debug_now!("Reset Reason: {reset_reason:?}");
+
+        // Go to the bootrom after a watchdog failure.  The bits are only
+        // cleared on a normal boot, so the `wdtreset` bit is still set when
+        // the device is reset after leaving the bootrom.  In that case,
+        // another reset bit is set too, and we go back to normal operation
+        // and report the watchdog reset.
+        let boot_to_bootrom = reset_reason.wdtreset
+            && !(reset_reason.systemreset || reset_reason.swrreset || reset_reason.padreset);
+        if !boot_to_bootrom {
+            lpc55::clear_reset_reason(&c.device.PMC);
+        }
 
         #[cfg(feature = "alloc")]
         embedded_runner_lib::init_alloc();
@@ -119,7 +131,7 @@ mod app {
             endpoints,
             clock_controller,
             wwdt,
-        } = nk3xn::init(c.device, c.core, c.local.resources);
+        } = nk3xn::init(c.device, c.core, c.local.resources, (&reset_reason).into());
         let perf_timer = basic.perf_timer;
         let wait_extender = basic.delay_timer;
 
@@ -132,8 +144,7 @@ mod app {
         let systick = unsafe { lpc55_hal::raw::CorePeripherals::steal() }.SYST;
         let systick = Systick::new(systick, 96_000_000); // TODO: read out sysclk
 
-        debug_now!("Reset from watchdog: {}", was_reset_from_wwdt);
-        if was_reset_from_wwdt {
+        if boot_to_bootrom {
             lpc55_hal::boot_to_bootrom();
         }
 
@@ -67,13 +67,15 @@ mod app {
 
         // Go to bootloader after watchdog failure
         // After a soft reset, go back to normal operation
-        // This is required because for some reason the `RESETREAS` register
-        // is not cleared until a full poweroff
+        // The flags are only cleared after this check, so the `dog` flag is
+        // still set when the bootloader resets the device and the watchdog
+        // reset is reported after the bootloader round trip
         if reset_reason.dog && !reset_reason.sreq {
             Nrf52::reboot_to_firmware_update();
         }
+        nrf52::clear_reset_reason(&ctx.device.POWER.resetreas);
 
-        let soc = nrf52::init_bootup(&ctx.device.FICR, &ctx.device.UICR);
+        let soc = nrf52::init_bootup(&ctx.device.FICR, &ctx.device.UICR, &reset_reason);
 
         let wdt_parts = nrf52::init_watchdog(ctx.device.WDT);
 
@@ -8,6 +8,7 @@ pub fn init(
     device_peripherals: lpc55_hal::raw::Peripherals,
     core_peripherals: rtic::export::Peripherals,
     resources: &'static mut Resources<NK3xN>,
+    reset_reason: apps::ResetReason,
 ) -> init::All {
     const SECURE_FIRMWARE_VERSION: u32 = VERSION.encode();
 
@@ -20,7 +21,7 @@ pub fn init(
     let nfc_enabled = true;
     let boot_to_bootrom = true;
 
-    init::start(hal.syscon, hal.pmc, hal.anactrl)
+    init::start(hal.syscon, hal.pmc, hal.anactrl, reset_reason)
         .next(hal.iocon, hal.gpio, hal.wwdt)
         .next(
             hal.adc,
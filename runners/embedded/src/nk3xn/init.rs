@@ -1,7 +1,7 @@
 use apdu_dispatch::interchanges::{
     Channel as CcidChannel, Requester as CcidRequester, Responder as CcidResponder,
 };
-use apps::{Endpoints, InitStatus};
+use apps::{Endpoints, InitStatus, ResetReason};
 #[cfg(feature = "se050")]
 use boards::nk3xn::TimerDelay;
 use boards::{
@@ -108,6 +108,7 @@ struct Flash {
 
 pub struct Stage0 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
 }
 
@@ -180,6 +181,7 @@ impl Stage0 {
         debug_now!("Wwdt tv again: {:?}", wwdt.timer());
         Stage1 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks,
             wwdt,
@@ -189,6 +191,7 @@ impl Stage0 {
 
 pub struct Stage1 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     wwdt: EnabledWwdt,
@@ -353,6 +356,7 @@ impl Stage1 {
         };
         Stage2 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks: self.clocks,
             se050_timer,
@@ -364,6 +368,7 @@ impl Stage1 {
 
 pub struct Stage2 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     basic: Basic,
@@ -487,6 +492,7 @@ impl Stage2 {
 
         Stage3 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks: self.clocks,
             basic: self.basic,
@@ -502,6 +508,7 @@ impl Stage2 {
 
 pub struct Stage3 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     basic: Basic,
@@ -539,6 +546,7 @@ impl Stage3 {
         };
         Stage4 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks: self.clocks,
             basic: self.basic,
@@ -555,6 +563,7 @@ impl Stage3 {
 
 pub struct Stage4 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     basic: Basic,
@@ -660,6 +669,7 @@ impl Stage4 {
 
         Stage5 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks: self.clocks,
             basic: self.basic,
@@ -710,6 +720,7 @@ fn initialize_fs_flash(flash_gordon: &mut FlashGordon, prince: &mut Prince<Enabl
 
 pub struct Stage5 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     basic: Basic,
@@ -761,6 +772,7 @@ impl Stage5 {
 
         Stage6 {
             status: self.status,
+            reset_reason: self.reset_reason,
             peripherals: self.peripherals,
             clocks: self.clocks,
             basic: self.basic,
@@ -775,6 +787,7 @@ impl Stage5 {
 
 pub struct Stage6 {
     status: InitStatus,
+    reset_reason: ResetReason,
     peripherals: Peripherals,
     clocks: Clocks,
     basic: Basic,
@@ -829,7 +842,7 @@ impl Stage6 {
     ) -> All {
         self.perform_data_migrations();
         let (apps, endpoints) = init::init_apps(
-            &Lpc55::new(),
+            &Lpc55::new(self.reset_reason),
             &mut self.trussed,
             self.status,
             &self.store,
@@ -894,7 +907,12 @@ pub struct All {
 }
 
 #[inline(never)]
-pub fn start(syscon: hal::Syscon, pmc: hal::Pmc, anactrl: hal::Anactrl) -> Stage0 {
+pub fn start(
+    syscon: hal::Syscon,
+    pmc: hal::Pmc,
+    anactrl: hal::Anactrl,
+    reset_reason: ResetReason,
+) -> Stage0 {
     let status = Default::default();
     let peripherals = Peripherals {
         syscon,
@@ -903,6 +921,7 @@ pub fn start(syscon: hal::Syscon, pmc: hal::Pmc, anactrl: hal::Anactrl) -> Stage
     };
     Stage0 {
         status,
+        reset_reason,
         peripherals,
     }
 }
@@ -1,9 +1,11 @@
+#[cfg(feature = "se050")]
+mod se050;
 mod store;
 mod ui;
 
 use std::{path::PathBuf, sync::Arc, thread};
 
-use apps::{AdminData, Apps, Dispatch, FidoData, Variant};
+use apps::{AdminData, Apps, Dispatch, FidoData, PreserveRule, Variant};
 use clap::{ArgAction, Parser, ValueEnum};
 use clap_num::maybe_hex;
 use rand_core::{OsRng, RngCore};
@@ -117,16 +119,35 @@ impl apps::Runner for Runner {
     #[cfg(feature = "provisioner")]
     type Filesystem = <store::Store as trussed::store::Store>::I;
 
+    #[cfg(feature = "se050")]
+    type Twi = se050::Se050;
+    #[cfg(feature = "se050")]
+    type Se050Timer = se050::Delay;
+    #[cfg(not(feature = "se050"))]
     type Twi = ();
+    #[cfg(not(feature = "se050"))]
     type Se050Timer = ();
 
+    const PRESERVE_POLICY: &'static [PreserveRule] = apps::DEFAULT_PRESERVE_POLICY;
+
     fn uuid(&self) -> [u8; 16] {
         self.serial
     }
 
+    fn has_external_flash(&self) -> bool {
+        true
+    }
+
     fn is_efs_available(&self) -> bool {
         true
     }
+
+    /// The emulated SE050 only implements secure objects and sessions, not the cryptographic
+    /// operations, so the apps always use the software backends.
+    #[cfg(feature = "se050")]
+    fn is_se050_backend_available(&self) -> bool {
+        false
+    }
 }
 
 fn main() {
@@ -159,6 +180,8 @@ fn print_version() {
         "test",
         #[cfg(feature = "provisioner")]
         "provisioner",
+        #[cfg(feature = "se050")]
+        "se050",
     ];
 
     print!("{} {}", crate_name, crate_version);
@@ -181,11 +204,25 @@ fn exec(
         });
     }
 
+    #[cfg(feature = "se050")]
+    let se050 = {
+        log::info!("Initializing emulated SE050");
+        let mut driver =
+            se05x::se05x::Se05X::new(se050::Se050::new(), se050::ADDRESS, se050::Delay);
+        driver
+            .enable()
+            .map_err(|err| log::error!("Failed to enable emulated SE050: {err:?}"))
+            .ok()
+            .map(|_| driver)
+    };
+
     log::info!("Initializing Trussed");
     trussed_usbip::Builder::new(store, options)
         .dispatch(Dispatch::with_hw_key(
             Location::Internal,
             Bytes::from_slice(b"Unique hw key").unwrap(),
+            #[cfg(feature = "se050")]
+            se050,
         ))
         .init_platform(move |platform| {
             let ui: Box<dyn trussed::platform::UserInterface + Send + Sync> =
@@ -0,0 +1,391 @@
+//! In-process emulation of the SE050 secure element.
+//!
+//! The emulator implements the T=1 over I2C protocol used by the se05x driver and a subset of the
+//! SE050 applet, see the `applet` module.  All secure objects are kept in memory, so they are
+//! lost when the runner exits.
+
+mod applet;
+
+use std::{thread, time::Duration};
+
+use embedded_hal::blocking::{
+    delay::DelayUs,
+    i2c::{Read, Write, WriteRead},
+};
+use log::{debug, warn};
+use se05x::t1::I2CErrorNack;
+
+use applet::Applet;
+
+/// The I2C address used by the se05x driver
+pub const ADDRESS: u8 = 0x48;
+
+const NAD_HOST_TO_SE: u8 = 0x5A;
+const NAD_SE_TO_HOST: u8 = 0xA5;
+
+const HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 2;
+const DEFAULT_IFS: usize = 0xFE;
+
+/// Answer to reset of a SE050 with JCOP 4
+const ATR: &[u8] = &[
+    0x00, 0xA0, 0x00, 0x00, 0x03, 0x96, 0x04, 0x03, 0xE8, 0x00, 0xFE, 0x02, 0x0B, 0x03, 0xE8, 0x08,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x0A, 0x4A, 0x43, 0x4F, 0x50, 0x34, 0x20, 0x41,
+    0x54, 0x50, 0x4F,
+];
+
+const PCB_S_BLOCK: u8 = 0b1100_0000;
+const PCB_R_BLOCK: u8 = 0b1000_0000;
+const PCB_S_RESPONSE: u8 = 0b0010_0000;
+const PCB_I_MORE: u8 = 0b0010_0000;
+
+const S_RESYNC: u8 = 0b0000_0000;
+const S_IFS: u8 = 0b0000_0001;
+const S_ABORT: u8 = 0b0000_0010;
+const S_END_APDU_SESSION: u8 = 0b0000_0101;
+const S_CHIP_RESET: u8 = 0b0000_0110;
+const S_GET_ATR: u8 = 0b0000_0111;
+const S_INTERFACE_SOFT_RESET: u8 = 0b0000_1111;
+
+/// The I2C error returned by the emulator
+///
+/// The SE050 does not acknowledge its address if it has no data to send, so the driver treats an
+/// address NACK as a request to retry.
+#[derive(Debug)]
+pub struct Nack;
+
+impl I2CErrorNack for Nack {
+    fn is_address_nack(&self) -> bool {
+        true
+    }
+
+    fn is_data_nack(&self) -> bool {
+        false
+    }
+}
+
+/// Delay implementation for the se05x driver
+#[derive(Debug, Default)]
+pub struct Delay;
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        thread::sleep(Duration::from_micros(us.into()));
+    }
+}
+
+/// Emulated SE050 connected to the I2C bus
+#[derive(Default)]
+pub struct Se050 {
+    applet: Applet,
+    /// Bytes written by the host that do not form a complete frame yet
+    received: Vec<u8>,
+    /// Frame waiting to be read by the host
+    response: Vec<u8>,
+    response_offset: usize,
+    /// The last frame sent to the host, used for retransmissions
+    last_frame: Vec<u8>,
+    /// Command APDU received with chained I-blocks
+    command: Vec<u8>,
+    /// Remaining part of the response APDU that is sent with chained I-blocks
+    pending: Vec<u8>,
+    send_sequence: bool,
+    ifsd: usize,
+}
+
+impl Se050 {
+    pub fn new() -> Self {
+        Self {
+            ifsd: DEFAULT_IFS,
+            ..Default::default()
+        }
+    }
+
+    fn reset_transport(&mut self) {
+        self.command.clear();
+        self.pending.clear();
+        self.send_sequence = false;
+        self.ifsd = DEFAULT_IFS;
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        self.received.extend_from_slice(data);
+        let Some(&len) = self.received.get(HEADER_LEN - 1) else {
+            return;
+        };
+        let frame_len = HEADER_LEN + usize::from(len) + CRC_LEN;
+        if self.received.len() < frame_len {
+            return;
+        }
+        let frame: Vec<u8> = self.received.drain(..frame_len).collect();
+        let (data, crc) = frame.split_at(frame_len - CRC_LEN);
+        if data[0] != NAD_HOST_TO_SE {
+            warn!("SE050: ignoring frame with NAD {:02x}", data[0]);
+        } else if crc != checksum(data) {
+            warn!("SE050: ignoring frame with invalid CRC");
+            self.send_r_block(true);
+        } else {
+            self.handle_frame(data[1], &data[HEADER_LEN..]);
+        }
+    }
+
+    fn handle_frame(&mut self, pcb: u8, inf: &[u8]) {
+        if pcb & PCB_S_BLOCK == PCB_S_BLOCK {
+            self.handle_s_block(pcb & !PCB_S_BLOCK, inf);
+        } else if pcb & PCB_R_BLOCK == PCB_R_BLOCK {
+            self.handle_r_block(pcb);
+        } else {
+            self.handle_i_block(pcb, inf);
+        }
+    }
+
+    fn handle_s_block(&mut self, request: u8, inf: &[u8]) {
+        if request & PCB_S_RESPONSE != 0 {
+            // The emulator never sends requests, e. g. for a waiting time extension
+            warn!("SE050: unexpected S-block response {request:02x}");
+            return;
+        }
+        debug!("SE050: S-block request {request:02x}");
+        let response = match request {
+            S_RESYNC | S_END_APDU_SESSION => {
+                self.reset_transport();
+                Vec::new()
+            }
+            S_IFS => {
+                if let Some(&ifsd) = inf.last() {
+                    self.ifsd = usize::from(ifsd).max(1);
+                }
+                inf.to_vec()
+            }
+            S_ABORT => {
+                self.command.clear();
+                self.pending.clear();
+                Vec::new()
+            }
+            S_CHIP_RESET => {
+                self.reset_transport();
+                self.applet.reset();
+                Vec::new()
+            }
+            S_GET_ATR => ATR.to_vec(),
+            S_INTERFACE_SOFT_RESET => {
+                self.reset_transport();
+                self.applet.reset();
+                ATR.to_vec()
+            }
+            _ => {
+                warn!("SE050: unsupported S-block request {request:02x}");
+                return;
+            }
+        };
+        self.send_frame(PCB_S_BLOCK | PCB_S_RESPONSE | request, &response);
+    }
+
+    fn handle_r_block(&mut self, pcb: u8) {
+        let error = pcb & 0b11 != 0;
+        if error || self.pending.is_empty() {
+            // Retransmit the last frame
+            self.response = self.last_frame.clone();
+            self.response_offset = 0;
+        } else {
+            self.send_pending();
+        }
+    }
+
+    fn handle_i_block(&mut self, pcb: u8, inf: &[u8]) {
+        let sequence = pcb & 0b0100_0000 != 0;
+        self.command.extend_from_slice(inf);
+        if pcb & PCB_I_MORE != 0 {
+            let next = u8::from(!sequence) << 4;
+            self.send_frame(PCB_R_BLOCK | next, &[]);
+            return;
+        }
+        let command = std::mem::take(&mut self.command);
+        self.pending = self.applet.process(&command);
+        self.send_pending();
+    }
+
+    fn send_pending(&mut self) {
+        let len = self.pending.len().min(self.ifsd);
+        let chunk: Vec<u8> = self.pending.drain(..len).collect();
+        let mut pcb = u8::from(self.send_sequence) << 6;
+        if !self.pending.is_empty() {
+            pcb |= PCB_I_MORE;
+        }
+        self.send_sequence = !self.send_sequence;
+        self.send_frame(pcb, &chunk);
+    }
+
+    fn send_r_block(&mut self, error: bool) {
+        self.send_frame(PCB_R_BLOCK | u8::from(error), &[]);
+    }
+
+    fn send_frame(&mut self, pcb: u8, inf: &[u8]) {
+        // INF is at most IFSD bytes long, which fits into the length byte
+        let len = u8::try_from(inf.len()).unwrap();
+        let mut frame = vec![NAD_SE_TO_HOST, pcb, len];
+        frame.extend_from_slice(inf);
+        frame.extend_from_slice(&checksum(&frame));
+        self.last_frame = frame.clone();
+        self.response = frame;
+        self.response_offset = 0;
+    }
+}
+
+impl Write<u8> for Se050 {
+    type Error = Nack;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        if address != ADDRESS {
+            return Err(Nack);
+        }
+        self.receive(bytes);
+        Ok(())
+    }
+}
+
+impl Read<u8> for Se050 {
+    type Error = Nack;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let available = &self.response[self.response_offset..];
+        if address != ADDRESS || available.is_empty() {
+            return Err(Nack);
+        }
+        let len = buffer.len().min(available.len());
+        buffer[..len].copy_from_slice(&available[..len]);
+        buffer[len..].fill(0);
+        self.response_offset += len;
+        if self.response_offset == self.response.len() {
+            self.response.clear();
+            self.response_offset = 0;
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead<u8> for Se050 {
+    type Error = Nack;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write(address, bytes)?;
+        self.read(address, buffer)
+    }
+}
+
+/// CRC-16/X-25 as used by the T=1 over I2C protocol, transmitted LSB first
+fn checksum(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    (!crc).to_le_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SELECT command for the SE050 applet
+    const SELECT: &[u8] = &[
+        0x00, 0xA4, 0x04, 0x00, 0x10, 0xA0, 0x00, 0x00, 0x03, 0x96, 0x54, 0x53, 0x00, 0x00, 0x00,
+        0x01, 0x03, 0x00, 0x00, 0x00, 0x00,
+    ];
+    const SELECT_RESPONSE: &[u8] = &[0x03, 0x01, 0x01, 0x6F, 0xFF, 0x01, 0x0B, 0x90, 0x00];
+
+    fn send(se050: &mut Se050, pcb: u8, inf: &[u8]) {
+        let mut frame = vec![NAD_HOST_TO_SE, pcb, u8::try_from(inf.len()).unwrap()];
+        frame.extend_from_slice(inf);
+        frame.extend_from_slice(&checksum(&frame));
+        se050.write(ADDRESS, &frame).unwrap();
+    }
+
+    fn receive(se050: &mut Se050) -> (u8, Vec<u8>) {
+        let mut header = [0; HEADER_LEN];
+        se050.read(ADDRESS, &mut header).unwrap();
+        assert_eq!(header[0], NAD_SE_TO_HOST);
+        let mut rest = vec![0; usize::from(header[2]) + CRC_LEN];
+        se050.read(ADDRESS, &mut rest).unwrap();
+        let (inf, crc) = rest.split_at(rest.len() - CRC_LEN);
+        let mut frame = header.to_vec();
+        frame.extend_from_slice(inf);
+        assert_eq!(crc, checksum(&frame));
+        assert!(se050.read(ADDRESS, &mut [0]).is_err());
+        (header[1], inf.to_vec())
+    }
+
+    #[test]
+    fn get_atr() {
+        let mut se050 = Se050::new();
+        assert!(se050.read(ADDRESS, &mut [0]).is_err());
+        send(&mut se050, PCB_S_BLOCK | S_GET_ATR, &[]);
+        assert_eq!(
+            receive(&mut se050),
+            (PCB_S_BLOCK | PCB_S_RESPONSE | S_GET_ATR, ATR.to_vec())
+        );
+    }
+
+    #[test]
+    fn select() {
+        let mut se050 = Se050::new();
+        send(&mut se050, 0, SELECT);
+        assert_eq!(receive(&mut se050), (0, SELECT_RESPONSE.to_vec()));
+    }
+
+    #[test]
+    fn chained_command() {
+        let mut se050 = Se050::new();
+        let (first, second) = SELECT.split_at(8);
+        send(&mut se050, PCB_I_MORE, first);
+        assert_eq!(receive(&mut se050), (PCB_R_BLOCK | 0b0001_0000, Vec::new()));
+        send(&mut se050, 0b0100_0000, second);
+        assert_eq!(receive(&mut se050), (0, SELECT_RESPONSE.to_vec()));
+    }
+
+    #[test]
+    fn chained_response() {
+        let mut se050 = Se050::new();
+        send(&mut se050, PCB_S_BLOCK | S_IFS, &[4]);
+        assert_eq!(
+            receive(&mut se050),
+            (PCB_S_BLOCK | PCB_S_RESPONSE | S_IFS, vec![4])
+        );
+
+        send(&mut se050, 0, SELECT);
+        let mut response = Vec::new();
+        loop {
+            let (pcb, inf) = receive(&mut se050);
+            response.extend(inf);
+            if pcb & PCB_I_MORE == 0 {
+                break;
+            }
+            send(&mut se050, PCB_R_BLOCK, &[]);
+        }
+        assert_eq!(response, SELECT_RESPONSE);
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        let mut se050 = Se050::new();
+        let mut frame = vec![NAD_HOST_TO_SE, 0, 1, 0x00];
+        frame.extend_from_slice(&[0, 0]);
+        se050.write(ADDRESS, &frame).unwrap();
+        assert_eq!(receive(&mut se050), (PCB_R_BLOCK | 1, Vec::new()));
+
+        // An R-block with an error requests the retransmission of the last frame
+        send(&mut se050, PCB_R_BLOCK | 1, &[]);
+        assert_eq!(receive(&mut se050), (PCB_R_BLOCK | 1, Vec::new()));
+    }
+}
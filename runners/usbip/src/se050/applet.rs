@@ -0,0 +1,776 @@
+//! Emulation of the SE050 IoT applet
+//!
+//! Supported are the commands needed to manage secure objects and sessions: object creation,
+//! reading, listing and deletion, EC curve configuration, user ID sessions and random number
+//! generation.  Cryptographic operations with keys stored on the SE050 and key generation are not
+//! supported and fail with the status word `6A81` (function not supported).  Therefore the apps
+//! always use the software backends in the usbip runner, independent of their
+//! `use_se050_backend` config option.
+
+use std::collections::BTreeMap;
+
+use log::debug;
+use rand_core::{OsRng, RngCore as _};
+
+const AID: &[u8] = &[
+    0xA0, 0x00, 0x00, 0x03, 0x96, 0x54, 0x53, 0x00, 0x00, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00,
+];
+/// Applet version 3.1.1 with all features enabled and secure box version 1.11
+const VERSION: &[u8] = &[0x03, 0x01, 0x01, 0x6F, 0xFF, 0x01, 0x0B];
+const FREE_MEMORY: u16 = 0x7FFF;
+
+const CLA_ISO: u8 = 0x00;
+const CLA_SE050: u8 = 0x80;
+const INS_SELECT: u8 = 0xA4;
+
+const INS_MASK: u8 = 0x1F;
+const INS_WRITE: u8 = 0x01;
+const INS_READ: u8 = 0x02;
+const INS_MGMT: u8 = 0x04;
+const INS_PROCESS: u8 = 0x05;
+
+const P1_DEFAULT: u8 = 0x00;
+const P1_EC: u8 = 0x01;
+const P1_RSA: u8 = 0x02;
+const P1_AES: u8 = 0x03;
+const P1_DES: u8 = 0x04;
+const P1_HMAC: u8 = 0x05;
+const P1_BINARY: u8 = 0x06;
+const P1_USERID: u8 = 0x07;
+const P1_COUNTER: u8 = 0x08;
+const P1_CURVE: u8 = 0x0B;
+const P1_KEY_TYPE_MASK: u8 = 0x60;
+const P1_KEY_PAIR: u8 = 0x60;
+const P1_PRIVATE: u8 = 0x40;
+const P1_PUBLIC: u8 = 0x20;
+
+const P2_DEFAULT: u8 = 0x00;
+const P2_GENERATE: u8 = 0x03;
+const P2_CREATE: u8 = 0x04;
+const P2_SIZE: u8 = 0x07;
+const P2_SESSION_CREATE: u8 = 0x1B;
+const P2_SESSION_CLOSE: u8 = 0x1C;
+const P2_VERSION: u8 = 0x20;
+const P2_MEMORY: u8 = 0x22;
+const P2_LIST: u8 = 0x25;
+const P2_TYPE: u8 = 0x26;
+const P2_EXIST: u8 = 0x27;
+const P2_DELETE_OBJECT: u8 = 0x28;
+const P2_DELETE_ALL: u8 = 0x2A;
+const P2_SESSION_USERID: u8 = 0x2C;
+const P2_PARAM: u8 = 0x40;
+const P2_RANDOM: u8 = 0x49;
+
+const TAG_SESSION_ID: u8 = 0x10;
+const TAG_1: u8 = 0x41;
+const TAG_2: u8 = 0x42;
+const TAG_3: u8 = 0x43;
+const TAG_4: u8 = 0x44;
+
+const RESULT_SUCCESS: u8 = 0x01;
+const RESULT_FAILURE: u8 = 0x02;
+const MORE_INDICATOR_NO_MORE: u8 = 0x02;
+const PERSISTENT: u8 = 0x01;
+const TRANSIENT: u8 = 0x02;
+const CURVE_SET: u8 = 0x01;
+const CURVE_NOT_SET: u8 = 0x02;
+const MAX_CURVE_ID: u8 = 0x11;
+
+const SW_SUCCESS: u16 = 0x9000;
+const SW_WRONG_LENGTH: u16 = 0x6700;
+const SW_SECURITY_STATUS_NOT_SATISFIED: u16 = 0x6982;
+const SW_CONDITIONS_NOT_SATISFIED: u16 = 0x6985;
+const SW_WRONG_DATA: u16 = 0x6A80;
+const SW_FUNCTION_NOT_SUPPORTED: u16 = 0x6A81;
+const SW_FILE_NOT_FOUND: u16 = 0x6A82;
+const SW_INCORRECT_P1P2: u16 = 0x6A86;
+const SW_INS_NOT_SUPPORTED: u16 = 0x6D00;
+const SW_CLA_NOT_SUPPORTED: u16 = 0x6E00;
+
+type Result<T = Vec<u8>> = std::result::Result<T, u16>;
+
+/// Secure object types as returned by ReadType
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+enum ObjectType {
+    EcKeyPair = 0x01,
+    EcPrivateKey = 0x02,
+    EcPublicKey = 0x03,
+    RsaKeyPair = 0x04,
+    RsaPrivateKey = 0x06,
+    RsaPublicKey = 0x08,
+    AesKey = 0x09,
+    DesKey = 0x0A,
+    BinaryFile = 0x0B,
+    UserId = 0x0C,
+    Counter = 0x0D,
+    HmacKey = 0x11,
+}
+
+impl ObjectType {
+    fn key(p1: u8) -> Result<Self> {
+        let ty = match (p1 & !P1_KEY_TYPE_MASK, p1 & P1_KEY_TYPE_MASK) {
+            (P1_EC, P1_KEY_PAIR) => Self::EcKeyPair,
+            (P1_EC, P1_PRIVATE) => Self::EcPrivateKey,
+            (P1_EC, P1_PUBLIC) => Self::EcPublicKey,
+            (P1_RSA, P1_KEY_PAIR) => Self::RsaKeyPair,
+            (P1_RSA, P1_PRIVATE) => Self::RsaPrivateKey,
+            (P1_RSA, P1_PUBLIC) => Self::RsaPublicKey,
+            (P1_AES, 0) => Self::AesKey,
+            (P1_DES, 0) => Self::DesKey,
+            (P1_HMAC, 0) => Self::HmacKey,
+            _ => return Err(SW_INCORRECT_P1P2),
+        };
+        Ok(ty)
+    }
+}
+
+struct Object {
+    ty: ObjectType,
+    transient: bool,
+    /// The readable content: the file content, the counter value or the public key
+    public: Vec<u8>,
+    /// Secret key material and user IDs that can never be read
+    secret: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct Applet {
+    selected: bool,
+    objects: BTreeMap<u32, Object>,
+    curves: BTreeMap<u8, BTreeMap<u8, Vec<u8>>>,
+    sessions: BTreeMap<[u8; 8], u32>,
+}
+
+impl Applet {
+    /// Resets the volatile state like the selection, the sessions and transient objects
+    pub fn reset(&mut self) {
+        self.selected = false;
+        self.sessions.clear();
+        self.objects.retain(|_, object| !object.transient);
+    }
+
+    /// Processes a command APDU and returns the response APDU including the status word
+    pub fn process(&mut self, apdu: &[u8]) -> Vec<u8> {
+        let result = self.process_apdu(apdu, None);
+        response(apdu, result)
+    }
+
+    fn process_apdu(&mut self, apdu: &[u8], session: Option<[u8; 8]>) -> Result {
+        let command = Command::parse(apdu)?;
+        match command.cla {
+            CLA_ISO if command.ins == INS_SELECT => self.select(&command),
+            CLA_ISO => Err(SW_INS_NOT_SUPPORTED),
+            CLA_SE050 if !self.selected => Err(SW_CONDITIONS_NOT_SATISFIED),
+            CLA_SE050 => {
+                let tlvs = Tlvs::parse(command.data)?;
+                match command.ins & INS_MASK {
+                    INS_WRITE => self.write(&command, &tlvs),
+                    INS_READ => self.read(&command, &tlvs),
+                    INS_MGMT => self.mgmt(&command, &tlvs, session),
+                    INS_PROCESS if session.is_none() => self.process_session(&command, &tlvs),
+                    _ => Err(SW_FUNCTION_NOT_SUPPORTED),
+                }
+            }
+            _ => Err(SW_CLA_NOT_SUPPORTED),
+        }
+    }
+
+    fn select(&mut self, command: &Command<'_>) -> Result {
+        if command.p1 != 0x04 || command.data != AID {
+            return Err(SW_FILE_NOT_FOUND);
+        }
+        self.selected = true;
+        Ok(VERSION.to_vec())
+    }
+
+    fn write(&mut self, command: &Command<'_>, tlvs: &Tlvs<'_>) -> Result {
+        let transient = command.ins & 0x80 != 0;
+        match (command.p1, command.p2) {
+            (P1_CURVE, P2_CREATE) => {
+                let curve = tlvs.u8(TAG_1)?;
+                if self.curves.contains_key(&curve) {
+                    return Err(SW_CONDITIONS_NOT_SATISFIED);
+                }
+                self.curves.insert(curve, BTreeMap::new());
+                Ok(Vec::new())
+            }
+            (P1_CURVE, P2_PARAM) => {
+                let curve = tlvs.u8(TAG_1)?;
+                let param = tlvs.u8(TAG_2)?;
+                let value = tlvs.get(TAG_3)?;
+                self.curves
+                    .get_mut(&curve)
+                    .ok_or(SW_CONDITIONS_NOT_SATISFIED)?
+                    .insert(param, value.to_vec());
+                Ok(Vec::new())
+            }
+            (P1_BINARY, P2_DEFAULT) => {
+                let id = tlvs.u32(TAG_1)?;
+                let offset = tlvs.optional_u16(TAG_2)?.unwrap_or_default();
+                let data = tlvs.optional(TAG_4).unwrap_or_default();
+                let object = match tlvs.optional_u16(TAG_3)? {
+                    Some(len) => self.create(id, ObjectType::BinaryFile, transient, |object| {
+                        object.public = vec![0; len.into()];
+                    })?,
+                    None => self.existing(id, ObjectType::BinaryFile)?,
+                };
+                let end = usize::from(offset) + data.len();
+                let file = object
+                    .public
+                    .get_mut(offset.into()..end)
+                    .ok_or(SW_WRONG_LENGTH)?;
+                file.copy_from_slice(data);
+                Ok(Vec::new())
+            }
+            (P1_USERID, P2_DEFAULT) => {
+                let id = tlvs.u32(TAG_1)?;
+                let value = tlvs.get(TAG_2)?;
+                self.create(id, ObjectType::UserId, transient, |object| {
+                    object.secret = value.to_vec();
+                })?;
+                Ok(Vec::new())
+            }
+            (P1_COUNTER, P2_DEFAULT) => {
+                let id = tlvs.u32(TAG_1)?;
+                let value = match tlvs.optional(TAG_3) {
+                    Some(value) => value.to_vec(),
+                    None => vec![0; tlvs.optional_u16(TAG_2)?.unwrap_or(8).into()],
+                };
+                self.create(id, ObjectType::Counter, transient, |object| {
+                    object.public = value;
+                })?;
+                Ok(Vec::new())
+            }
+            (_, P2_GENERATE) => Err(SW_FUNCTION_NOT_SUPPORTED),
+            (p1, P2_DEFAULT) => {
+                let ty = ObjectType::key(p1)?;
+                let id = tlvs.u32(TAG_1)?;
+                let (secret, public) = match ty {
+                    ObjectType::EcKeyPair | ObjectType::EcPrivateKey | ObjectType::EcPublicKey => {
+                        (tlvs.optional(TAG_3), tlvs.optional(TAG_4))
+                    }
+                    // Symmetric keys are passed in TAG_3, TAG_2 is the optional wrapping key
+                    _ => (tlvs.optional(TAG_3), None),
+                };
+                let object = if self.objects.contains_key(&id) {
+                    self.existing(id, ty)?
+                } else {
+                    self.create(id, ty, transient, |_| {})?
+                };
+                if let Some(secret) = secret {
+                    object.secret = secret.to_vec();
+                }
+                if let Some(public) = public {
+                    object.public = public.to_vec();
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(SW_INCORRECT_P1P2),
+        }
+    }
+
+    fn read(&mut self, command: &Command<'_>, tlvs: &Tlvs<'_>) -> Result {
+        match (command.p1, command.p2) {
+            (P1_DEFAULT, P2_DEFAULT) => {
+                let id = tlvs.u32(TAG_1)?;
+                let object = self.objects.get(&id).ok_or(SW_FILE_NOT_FOUND)?;
+                if object.public.is_empty() {
+                    return Err(SW_CONDITIONS_NOT_SATISFIED);
+                }
+                let offset = usize::from(tlvs.optional_u16(TAG_2)?.unwrap_or_default());
+                let len = match tlvs.optional_u16(TAG_3)? {
+                    Some(len) => usize::from(len),
+                    None => object.public.len().saturating_sub(offset),
+                };
+                let data = object
+                    .public
+                    .get(offset..offset + len)
+                    .ok_or(SW_WRONG_LENGTH)?;
+                Ok(tlv(TAG_1, data))
+            }
+            (P1_DEFAULT, P2_SIZE) => {
+                let id = tlvs.u32(TAG_1)?;
+                let object = self.objects.get(&id).ok_or(SW_FILE_NOT_FOUND)?;
+                let size = object.public.len().max(object.secret.len());
+                let size = u16::try_from(size).map_err(|_| SW_WRONG_LENGTH)?;
+                Ok(tlv(TAG_1, &size.to_be_bytes()))
+            }
+            (P1_DEFAULT, P2_TYPE) => {
+                let id = tlvs.u32(TAG_1)?;
+                let object = self.objects.get(&id).ok_or(SW_FILE_NOT_FOUND)?;
+                let persistence = if object.transient {
+                    TRANSIENT
+                } else {
+                    PERSISTENT
+                };
+                let mut response = tlv(TAG_1, &[object.ty as u8]);
+                response.extend(tlv(TAG_2, &[persistence]));
+                Ok(response)
+            }
+            (P1_DEFAULT, P2_LIST) => {
+                let offset = usize::from(tlvs.optional_u16(TAG_1)?.unwrap_or_default());
+                let filter = tlvs.optional(TAG_2).and_then(|f| f.first().copied());
+                let ids: Vec<u8> = self
+                    .objects
+                    .iter()
+                    .filter(|(_, object)| {
+                        matches!(filter, None | Some(0xFF)) || filter == Some(object.ty as u8)
+                    })
+                    .skip(offset)
+                    .flat_map(|(id, _)| id.to_be_bytes())
+                    .collect();
+                let mut response = tlv(TAG_1, &[MORE_INDICATOR_NO_MORE]);
+                response.extend(tlv(TAG_2, &ids));
+                Ok(response)
+            }
+            (P1_CURVE, P2_LIST) => {
+                let curves: Vec<u8> = (1..=MAX_CURVE_ID)
+                    .map(|id| {
+                        if self.curves.contains_key(&id) {
+                            CURVE_SET
+                        } else {
+                            CURVE_NOT_SET
+                        }
+                    })
+                    .collect();
+                Ok(tlv(TAG_1, &curves))
+            }
+            _ => Err(SW_INCORRECT_P1P2),
+        }
+    }
+
+    fn mgmt(&mut self, command: &Command<'_>, tlvs: &Tlvs<'_>, session: Option<[u8; 8]>) -> Result {
+        match (command.p1, command.p2) {
+            (P1_DEFAULT, P2_VERSION) => Ok(tlv(TAG_1, VERSION)),
+            (P1_DEFAULT, P2_MEMORY) => Ok(tlv(TAG_1, &FREE_MEMORY.to_be_bytes())),
+            (P1_DEFAULT, P2_RANDOM) => {
+                let len = tlvs.u16(TAG_1)?;
+                let mut random = vec![0; len.into()];
+                OsRng.fill_bytes(&mut random);
+                Ok(tlv(TAG_1, &random))
+            }
+            (P1_DEFAULT, P2_EXIST) => {
+                let id = tlvs.u32(TAG_1)?;
+                let result = if self.objects.contains_key(&id) {
+                    RESULT_SUCCESS
+                } else {
+                    RESULT_FAILURE
+                };
+                Ok(tlv(TAG_1, &[result]))
+            }
+            (P1_DEFAULT, P2_DELETE_OBJECT) => {
+                let id = tlvs.u32(TAG_1)?;
+                self.objects.remove(&id).ok_or(SW_FILE_NOT_FOUND)?;
+                Ok(Vec::new())
+            }
+            (P1_DEFAULT, P2_DELETE_ALL) => {
+                self.objects.clear();
+                self.curves.clear();
+                Ok(Vec::new())
+            }
+            (P1_CURVE, P2_DELETE_OBJECT) => {
+                let curve = tlvs.u8(TAG_1)?;
+                self.curves.remove(&curve).ok_or(SW_FILE_NOT_FOUND)?;
+                Ok(Vec::new())
+            }
+            (P1_DEFAULT, P2_SESSION_CREATE) => {
+                let user = tlvs.u32(TAG_1)?;
+                if !self.objects.contains_key(&user) {
+                    return Err(SW_FILE_NOT_FOUND);
+                }
+                let mut id = [0; 8];
+                OsRng.fill_bytes(&mut id);
+                self.sessions.insert(id, user);
+                Ok(tlv(TAG_1, &id))
+            }
+            (P1_DEFAULT, P2_SESSION_CLOSE) => {
+                let session = session.ok_or(SW_CONDITIONS_NOT_SATISFIED)?;
+                self.sessions.remove(&session);
+                Ok(Vec::new())
+            }
+            (P1_DEFAULT, P2_SESSION_USERID) => {
+                let session = session.ok_or(SW_CONDITIONS_NOT_SATISFIED)?;
+                let value = tlvs.get(TAG_1)?;
+                let user = self
+                    .sessions
+                    .get(&session)
+                    .copied()
+                    .ok_or(SW_CONDITIONS_NOT_SATISFIED)?;
+                let object = self.objects.get(&user).ok_or(SW_FILE_NOT_FOUND)?;
+                if object.ty != ObjectType::UserId || object.secret != value {
+                    return Err(SW_SECURITY_STATUS_NOT_SATISFIED);
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(SW_FUNCTION_NOT_SUPPORTED),
+        }
+    }
+
+    fn process_session(&mut self, command: &Command<'_>, tlvs: &Tlvs<'_>) -> Result {
+        if (command.p1, command.p2) != (P1_DEFAULT, P2_DEFAULT) {
+            return Err(SW_INCORRECT_P1P2);
+        }
+        let session: [u8; 8] = tlvs
+            .get(TAG_SESSION_ID)?
+            .try_into()
+            .map_err(|_| SW_WRONG_DATA)?;
+        if !self.sessions.contains_key(&session) {
+            return Err(SW_CONDITIONS_NOT_SATISFIED);
+        }
+        // The response contains the response APDU of the wrapped command including its status
+        let apdu = tlvs.get(TAG_1)?;
+        let result = self.process_apdu(apdu, Some(session));
+        Ok(response(apdu, result))
+    }
+
+    fn create(
+        &mut self,
+        id: u32,
+        ty: ObjectType,
+        transient: bool,
+        f: impl FnOnce(&mut Object),
+    ) -> Result<&mut Object> {
+        if self.objects.contains_key(&id) {
+            return Err(SW_CONDITIONS_NOT_SATISFIED);
+        }
+        let mut object = Object {
+            ty,
+            transient,
+            public: Vec::new(),
+            secret: Vec::new(),
+        };
+        f(&mut object);
+        Ok(self.objects.entry(id).or_insert(object))
+    }
+
+    fn existing(&mut self, id: u32, ty: ObjectType) -> Result<&mut Object> {
+        let object = self.objects.get_mut(&id).ok_or(SW_FILE_NOT_FOUND)?;
+        if object.ty != ty {
+            return Err(SW_CONDITIONS_NOT_SATISFIED);
+        }
+        Ok(object)
+    }
+}
+
+struct Command<'a> {
+    cla: u8,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Command<'a> {
+    fn parse(apdu: &'a [u8]) -> Result<Self> {
+        let [cla, ins, p1, p2, body @ ..] = apdu else {
+            return Err(SW_WRONG_LENGTH);
+        };
+        let data = match body {
+            // Case 1 and case 2 (short and extended)
+            [] | [_] | [0, _, _] => &[],
+            // Case 3 and 4 extended
+            [0, lc1, lc2, rest @ ..] => {
+                let lc = usize::from(u16::from_be_bytes([*lc1, *lc2]));
+                rest.get(..lc).ok_or(SW_WRONG_LENGTH)?
+            }
+            // Case 3 and 4 short
+            [lc, rest @ ..] => rest.get(..usize::from(*lc)).ok_or(SW_WRONG_LENGTH)?,
+        };
+        Ok(Self {
+            cla: *cla,
+            ins: *ins,
+            p1: *p1,
+            p2: *p2,
+            data,
+        })
+    }
+}
+
+struct Tlvs<'a>(Vec<(u8, &'a [u8])>);
+
+impl<'a> Tlvs<'a> {
+    fn parse(mut data: &'a [u8]) -> Result<Self> {
+        let mut tlvs = Vec::new();
+        while let [tag, rest @ ..] = data {
+            let (len, rest) = match rest {
+                [0x81, len, rest @ ..] => (usize::from(*len), rest),
+                [0x82, len1, len2, rest @ ..] => {
+                    (usize::from(u16::from_be_bytes([*len1, *len2])), rest)
+                }
+                [len, rest @ ..] if *len < 0x80 => (usize::from(*len), rest),
+                _ => return Err(SW_WRONG_DATA),
+            };
+            if rest.len() < len {
+                return Err(SW_WRONG_DATA);
+            }
+            let (value, rest) = rest.split_at(len);
+            tlvs.push((*tag, value));
+            data = rest;
+        }
+        Ok(Self(tlvs))
+    }
+
+    fn optional(&self, tag: u8) -> Option<&'a [u8]> {
+        self.0.iter().find(|(t, _)| *t == tag).map(|(_, v)| *v)
+    }
+
+    fn get(&self, tag: u8) -> Result<&'a [u8]> {
+        self.optional(tag).ok_or(SW_WRONG_DATA)
+    }
+
+    fn u8(&self, tag: u8) -> Result<u8> {
+        let [value] = self.get(tag)? else {
+            return Err(SW_WRONG_DATA);
+        };
+        Ok(*value)
+    }
+
+    fn optional_u16(&self, tag: u8) -> Result<Option<u16>> {
+        self.optional(tag)
+            .map(|value| {
+                value
+                    .try_into()
+                    .map(u16::from_be_bytes)
+                    .map_err(|_| SW_WRONG_DATA)
+            })
+            .transpose()
+    }
+
+    fn u16(&self, tag: u8) -> Result<u16> {
+        self.optional_u16(tag)?.ok_or(SW_WRONG_DATA)
+    }
+
+    fn u32(&self, tag: u8) -> Result<u32> {
+        self.get(tag)?
+            .try_into()
+            .map(u32::from_be_bytes)
+            .map_err(|_| SW_WRONG_DATA)
+    }
+}
+
+fn response(apdu: &[u8], result: Result) -> Vec<u8> {
+    let (mut response, status) = match result {
+        Ok(response) => (response, SW_SUCCESS),
+        Err(status) => (Vec::new(), status),
+    };
+    debug!("SE050: {:02x?} -> {status:04x}", apdu.get(..4));
+    response.extend_from_slice(&status.to_be_bytes());
+    response
+}
+
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tag];
+    match u8::try_from(value.len()) {
+        Ok(len) if len < 0x80 => tlv.push(len),
+        Ok(len) => tlv.extend([0x81, len]),
+        // The length of a response APDU always fits into two bytes
+        Err(_) => {
+            tlv.push(0x82);
+            tlv.extend(u16::try_from(value.len()).unwrap().to_be_bytes());
+        }
+    }
+    tlv.extend_from_slice(value);
+    tlv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INS_CRYPTO: u8 = 0x03;
+    const P2_VERIFY: u8 = P2_SESSION_USERID;
+
+    fn apdu(ins: u8, p1: u8, p2: u8, tlvs: &[(u8, &[u8])]) -> Vec<u8> {
+        let data: Vec<u8> = tlvs
+            .iter()
+            .flat_map(|(tag, value)| tlv(*tag, value))
+            .collect();
+        let mut apdu = vec![CLA_SE050, ins, p1, p2, u8::try_from(data.len()).unwrap()];
+        apdu.extend(data);
+        apdu
+    }
+
+    fn status(applet: &mut Applet, apdu: &[u8]) -> (Vec<u8>, u16) {
+        let mut response = applet.process(apdu);
+        let status = response.split_off(response.len() - 2);
+        (response, u16::from_be_bytes(status.try_into().unwrap()))
+    }
+
+    fn ok(applet: &mut Applet, apdu: &[u8]) -> Vec<u8> {
+        let (response, status) = status(applet, apdu);
+        assert_eq!(status, SW_SUCCESS, "{apdu:02x?}");
+        response
+    }
+
+    fn select(applet: &mut Applet) {
+        let mut apdu = vec![
+            CLA_ISO,
+            INS_SELECT,
+            0x04,
+            0x00,
+            u8::try_from(AID.len()).unwrap(),
+        ];
+        apdu.extend_from_slice(AID);
+        assert_eq!(ok(applet, &apdu), VERSION);
+    }
+
+    fn selected() -> Applet {
+        let mut applet = Applet::default();
+        select(&mut applet);
+        applet
+    }
+
+    fn write_file(ins: u8, id: u32, data: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(data.len()).unwrap().to_be_bytes();
+        apdu(
+            ins,
+            P1_BINARY,
+            P2_DEFAULT,
+            &[(TAG_1, &id.to_be_bytes()), (TAG_3, &len), (TAG_4, data)],
+        )
+    }
+
+    fn read(id: u32) -> Vec<u8> {
+        apdu(
+            INS_READ,
+            P1_DEFAULT,
+            P2_DEFAULT,
+            &[(TAG_1, &id.to_be_bytes())],
+        )
+    }
+
+    #[test]
+    fn not_selected() {
+        let mut applet = Applet::default();
+        let (_, status) = status(&mut applet, &read(1));
+        assert_eq!(status, SW_CONDITIONS_NOT_SATISFIED);
+    }
+
+    #[test]
+    fn binary_file() {
+        let mut applet = selected();
+        ok(&mut applet, &write_file(INS_WRITE, 0x1000, b"test"));
+        assert_eq!(ok(&mut applet, &read(0x1000)), tlv(TAG_1, b"test"));
+
+        let (_, status) = status(&mut applet, &write_file(INS_WRITE, 0x1000, b"test"));
+        assert_eq!(status, SW_CONDITIONS_NOT_SATISFIED);
+
+        let exists = apdu(INS_MGMT, P1_DEFAULT, P2_EXIST, &[(TAG_1, &[0, 0, 0x10, 0])]);
+        assert_eq!(ok(&mut applet, &exists), tlv(TAG_1, &[RESULT_SUCCESS]));
+        let delete = apdu(
+            INS_MGMT,
+            P1_DEFAULT,
+            P2_DELETE_OBJECT,
+            &[(TAG_1, &[0, 0, 0x10, 0])],
+        );
+        ok(&mut applet, &delete);
+        assert_eq!(ok(&mut applet, &exists), tlv(TAG_1, &[RESULT_FAILURE]));
+        let (_, status) = status(&mut applet, &read(0x1000));
+        assert_eq!(status, SW_FILE_NOT_FOUND);
+    }
+
+    #[test]
+    fn list() {
+        let mut applet = selected();
+        ok(&mut applet, &write_file(INS_WRITE, 1, b"a"));
+        let user_id = apdu(
+            INS_WRITE,
+            P1_USERID,
+            P2_DEFAULT,
+            &[(TAG_1, &2u32.to_be_bytes()), (TAG_2, b"user")],
+        );
+        ok(&mut applet, &user_id);
+
+        let list = |filter: u8| {
+            apdu(
+                INS_READ,
+                P1_DEFAULT,
+                P2_LIST,
+                &[(TAG_1, &[0, 0]), (TAG_2, &[filter])],
+            )
+        };
+        let ids = |ids: &[u8]| {
+            let mut response = tlv(TAG_1, &[MORE_INDICATOR_NO_MORE]);
+            response.extend(tlv(TAG_2, ids));
+            response
+        };
+        assert_eq!(ok(&mut applet, &list(0xFF)), ids(&[0, 0, 0, 1, 0, 0, 0, 2]));
+        assert_eq!(
+            ok(&mut applet, &list(ObjectType::BinaryFile as u8)),
+            ids(&[0, 0, 0, 1])
+        );
+        assert_eq!(
+            ok(&mut applet, &list(ObjectType::UserId as u8)),
+            ids(&[0, 0, 0, 2])
+        );
+        assert_eq!(ok(&mut applet, &list(ObjectType::AesKey as u8)), ids(&[]));
+    }
+
+    #[test]
+    fn transient_objects() {
+        let mut applet = selected();
+        ok(&mut applet, &write_file(INS_WRITE, 1, b"persistent"));
+        ok(&mut applet, &write_file(INS_WRITE | 0x80, 2, b"transient"));
+
+        applet.reset();
+        let (_, status) = status(&mut applet, &read(1));
+        assert_eq!(status, SW_CONDITIONS_NOT_SATISFIED);
+
+        select(&mut applet);
+        assert_eq!(ok(&mut applet, &read(1)), tlv(TAG_1, b"persistent"));
+        let (_, status) = status(&mut applet, &read(2));
+        assert_eq!(status, SW_FILE_NOT_FOUND);
+    }
+
+    #[test]
+    fn user_id_session() {
+        let mut applet = selected();
+        let id = 0x7FFF_0201u32.to_be_bytes();
+        let user_id = apdu(
+            INS_WRITE,
+            P1_USERID,
+            P2_DEFAULT,
+            &[(TAG_1, &id), (TAG_2, b"secret")],
+        );
+        ok(&mut applet, &user_id);
+
+        let create = apdu(INS_MGMT, P1_DEFAULT, P2_SESSION_CREATE, &[(TAG_1, &id)]);
+        let response = ok(&mut applet, &create);
+        let session = &response[2..];
+        assert_eq!(response[..2], [TAG_1, 8]);
+
+        let verify = |value: &[u8]| {
+            let inner = apdu(INS_MGMT, P1_DEFAULT, P2_VERIFY, &[(TAG_1, value)]);
+            apdu(
+                INS_PROCESS,
+                P1_DEFAULT,
+                P2_DEFAULT,
+                &[(TAG_SESSION_ID, session), (TAG_1, &inner)],
+            )
+        };
+        let sw = |status: u16| status.to_be_bytes().to_vec();
+        assert_eq!(ok(&mut applet, &verify(b"secret")), sw(SW_SUCCESS));
+        assert_eq!(
+            ok(&mut applet, &verify(b"wrong")),
+            sw(SW_SECURITY_STATUS_NOT_SATISFIED)
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut applet = selected();
+        let generate = apdu(
+            INS_WRITE,
+            P1_EC | P1_KEY_PAIR,
+            P2_GENERATE,
+            &[(TAG_1, &1u32.to_be_bytes()), (TAG_2, &[0x03])],
+        );
+        let (_, status) = status(&mut applet, &generate);
+        assert_eq!(status, SW_FUNCTION_NOT_SUPPORTED);
+
+        let sign = apdu(INS_CRYPTO, 0x0C, 0x09, &[(TAG_1, &1u32.to_be_bytes())]);
+        let (_, status) = status(&mut applet, &sign);
+        assert_eq!(status, SW_FUNCTION_NOT_SUPPORTED);
+    }
+}